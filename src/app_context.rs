@@ -0,0 +1,131 @@
+use crate::bind_groups::UserBindGroups;
+use crate::camera::Camera;
+use crate::debug_draw::DebugDraw;
+use crate::instance::{Instance, InstanceState};
+use crate::mesh::Mesh;
+
+// Called with the seconds since the last frame, see `App::on_update`
+pub type UpdateFn = Box<dyn FnMut(&mut AppContext, f32)>;
+
+// What an `App::on_update` callback gets to change: the active camera, the
+// meshes' instances, the shader's user bind groups and the debug shapes drawn
+// on top. The camera uniform and the instance buffers with their staging belts
+// stay out of reach. They're rebuilt from the camera and the instances when the
+// frame is drawn right after the callback.
+pub struct AppContext<'a> {
+    camera: &'a mut Camera,
+    meshes: &'a mut [Mesh],
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    user_bind_groups: &'a mut UserBindGroups,
+    debug_draw: &'a mut DebugDraw,
+}
+
+impl<'a> AppContext<'a> {
+    pub fn new(
+        camera: &'a mut Camera,
+        meshes: &'a mut [Mesh],
+        device: &'a wgpu::Device,
+        queue: &'a wgpu::Queue,
+        user_bind_groups: &'a mut UserBindGroups,
+        debug_draw: &'a mut DebugDraw,
+    ) -> Self {
+        Self {
+            camera,
+            meshes,
+            device,
+            queue,
+            user_bind_groups,
+            debug_draw,
+        }
+    }
+
+    // For creating the buffers and bind groups to add to `user_bind_groups`
+    pub fn device(&self) -> &wgpu::Device {
+        self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        self.queue
+    }
+
+    // Empty again whenever the device was recreated, see `UserBindGroups`
+    pub fn user_bind_groups(&mut self) -> &mut UserBindGroups {
+        self.user_bind_groups
+    }
+
+    // Lines and points drawn with this frame only
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        self.debug_draw
+    }
+
+    pub fn camera(&self) -> &Camera {
+        self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        self.camera
+    }
+
+    // Meshes are drawn in order, the cube first, then the `GLTF_MODEL` if there is one
+    pub fn num_meshes(&self) -> usize {
+        self.meshes.len()
+    }
+
+    // None when there's no mesh `index`
+    pub fn instances(&mut self, index: usize) -> Option<MeshInstances<'_>> {
+        self.meshes.get_mut(index).map(|mesh| MeshInstances {
+            state: &mut mesh.instances,
+        })
+    }
+}
+
+// One mesh's instances, limited to the parts of `InstanceState` that keep its
+// upload tracking intact
+pub struct MeshInstances<'a> {
+    state: &'a mut InstanceState,
+}
+
+impl MeshInstances<'_> {
+    pub fn instances(&self) -> &[Instance] {
+        self.state.instances()
+    }
+
+    // Edited instances are uploaded again before the frame is drawn
+    pub fn instances_mut(&mut self) -> &mut [Instance] {
+        self.state.instances_mut()
+    }
+
+    // See `InstanceState::push_instance`
+    pub fn push_instance(
+        &mut self,
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+    ) -> usize {
+        self.state.push_instance(position, rotation, scale)
+    }
+
+    pub fn remove_instance(&mut self, index: usize) -> anyhow::Result<Instance> {
+        self.state.remove_instance(index)
+    }
+
+    // Only the first `active_count` instances are drawn, hiding the rest keeps
+    // them around to show again later
+    pub fn active_count(&self) -> usize {
+        self.state.active_count()
+    }
+
+    pub fn set_active_count(&mut self, count: usize) {
+        self.state.set_active_count(count);
+    }
+
+    pub fn num_instances(&self) -> u32 {
+        self.state.num_instances()
+    }
+
+    // Multiplies every instance's rotation speed, 0 stops the animation
+    pub fn set_rotation_speed_scale(&mut self, scale: f32) {
+        self.state.rotation_speed_scale = scale;
+    }
+}
@@ -0,0 +1,245 @@
+use std::borrow::Cow;
+
+use wgpu::util::DeviceExt;
+
+// How far each axis line reaches from the origin, out past the cubes around it
+// in the default grid
+const AXIS_LENGTH: f32 = 2.0;
+
+// Matches `VertexInput` in axis.wgsl, for the gizmo and the debug shapes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl LineVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+// X, Y and Z from the origin, in red, green and blue
+const VERTICES: [LineVertex; 6] = [
+    LineVertex {
+        position: [0.0, 0.0, 0.0],
+        color: [1.0, 0.0, 0.0],
+    },
+    LineVertex {
+        position: [AXIS_LENGTH, 0.0, 0.0],
+        color: [1.0, 0.0, 0.0],
+    },
+    LineVertex {
+        position: [0.0, 0.0, 0.0],
+        color: [0.0, 1.0, 0.0],
+    },
+    LineVertex {
+        position: [0.0, AXIS_LENGTH, 0.0],
+        color: [0.0, 1.0, 0.0],
+    },
+    LineVertex {
+        position: [0.0, 0.0, 0.0],
+        color: [0.0, 0.0, 1.0],
+    },
+    LineVertex {
+        position: [0.0, 0.0, AXIS_LENGTH],
+        color: [0.0, 0.0, 1.0],
+    },
+];
+
+// World space axis lines at the origin, drawn in the scene pass with the
+// camera's bind group so the meshes hide them where they're in front
+pub struct AxisGizmo {
+    pub enabled: bool,
+    vertex_buffer: wgpu::Buffer,
+    pipelines: LinePipelines,
+}
+
+impl AxisGizmo {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        target_format: wgpu::TextureFormat,
+        sample_count: u32,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("axis vertex buffer"),
+            contents: bytemuck::cast_slice(&VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let pipelines = LinePipelines::new(
+            device,
+            "axis",
+            camera_bind_group_layout,
+            target_format,
+            sample_count,
+            scene_depth_stencil,
+            &[wgpu::PrimitiveTopology::LineList],
+        );
+
+        Self {
+            enabled: false,
+            vertex_buffer,
+            pipelines,
+        }
+    }
+
+    pub fn set_depth_stencil(
+        &mut self,
+        device: &wgpu::Device,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+    ) {
+        self.pipelines
+            .set_depth_stencil(device, scene_depth_stencil);
+    }
+
+    pub fn set_target_format(
+        &mut self,
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+    ) {
+        self.pipelines
+            .set_target_format(device, target_format, scene_depth_stencil);
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        rpass.set_pipeline(self.pipelines.get(wgpu::PrimitiveTopology::LineList));
+        rpass.set_bind_group(0, camera_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.draw(0..VERTICES.len() as u32, 0..1);
+    }
+}
+
+// axis.wgsl's pipelines for `LineVertex` lines or points, one per topology,
+// drawn in the scene pass with the camera's bind group
+pub struct LinePipelines {
+    label: &'static str,
+    topologies: Vec<wgpu::PrimitiveTopology>,
+    // Kept to rebuild the pipelines when the scene's depth test changes
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    target_format: wgpu::TextureFormat,
+    sample_count: u32,
+    pipelines: Vec<wgpu::RenderPipeline>,
+}
+
+impl LinePipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        target_format: wgpu::TextureFormat,
+        sample_count: u32,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+        topologies: &[wgpu::PrimitiveTopology],
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&format!("{label} shader")),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("axis.wgsl"))),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{label} pipeline layout")),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut pipelines = Self {
+            label,
+            topologies: topologies.to_vec(),
+            shader,
+            pipeline_layout,
+            target_format,
+            sample_count,
+            pipelines: Vec::new(),
+        };
+        pipelines.set_depth_stencil(device, scene_depth_stencil);
+        pipelines
+    }
+
+    // Panics for a topology the pipelines weren't created with
+    pub fn get(&self, topology: wgpu::PrimitiveTopology) -> &wgpu::RenderPipeline {
+        let index = self
+            .topologies
+            .iter()
+            .position(|&created| created == topology)
+            .expect("no pipeline for the topology");
+        &self.pipelines[index]
+    }
+
+    // Has to follow the scene pipelines' depth and stencil state, None without depth
+    pub fn set_depth_stencil(
+        &mut self,
+        device: &wgpu::Device,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+    ) {
+        self.pipelines = self
+            .topologies
+            .iter()
+            .map(|&topology| self.create_pipeline(device, scene_depth_stencil.clone(), topology))
+            .collect();
+    }
+
+    // Has to follow the frame's color format, with the scene pipelines' depth state
+    pub fn set_target_format(
+        &mut self,
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+    ) {
+        self.target_format = target_format;
+        self.set_depth_stencil(device, scene_depth_stencil);
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+        topology: wgpu::PrimitiveTopology,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{} pipeline", self.label)),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[Some(self.target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology,
+                ..Default::default()
+            },
+            // Tested like the meshes, but leaving the depth buffer alone so
+            // nothing drawn after them gets hidden
+            depth_stencil: scene_depth_stencil.map(|scene_depth_stencil| wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                ..scene_depth_stencil
+            }),
+            multisample: wgpu::MultisampleState {
+                count: self.sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+}
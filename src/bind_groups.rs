@@ -0,0 +1,89 @@
+use anyhow::{ensure, Result};
+
+// Group index of the first bind group added with `UserBindGroups::add`. The
+// scene shader's groups are
+//   0  the cube textures and their sampler
+//   1  the camera, and the time, frame count and resolution at binding 1
+//   2  the lights, and the directional light's shadow map from binding 1
+//   3  the wireframe overlay's color
+//   4+ user bind groups, in the order they were added
+// and every scene pipeline is built with all of them, so any entry point can
+// use any group.
+pub const FIRST_USER_GROUP: u32 = 4;
+
+// Bind groups for the scene shader on top of the renderer's own, e.g. a uniform
+// with an effect's parameters. They belong to the device, so they're gone with
+// the render state after a suspend or device loss and have to be added again.
+pub struct UserBindGroups {
+    layouts: Vec<wgpu::BindGroupLayout>,
+    bind_groups: Vec<wgpu::BindGroup>,
+    max_bind_groups: u32,
+    // Set when a layout is added, the pipelines are rebuilt before the next frame
+    layouts_changed: bool,
+}
+
+impl UserBindGroups {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            layouts: Vec::new(),
+            bind_groups: Vec::new(),
+            max_bind_groups: device.limits().max_bind_groups,
+            layouts_changed: false,
+        }
+    }
+
+    // Returns the group index `bind_group` is set at. Fails once the device's
+    // `max_bind_groups` is reached.
+    pub fn add(
+        &mut self,
+        layout: wgpu::BindGroupLayout,
+        bind_group: wgpu::BindGroup,
+    ) -> Result<u32> {
+        let group = FIRST_USER_GROUP + self.layouts.len() as u32;
+        ensure!(
+            group < self.max_bind_groups,
+            "the device only supports {} bind groups",
+            self.max_bind_groups
+        );
+        self.layouts.push(layout);
+        self.bind_groups.push(bind_group);
+        self.layouts_changed = true;
+        Ok(group)
+    }
+
+    // Swaps the bind group at `group` for one created with the same layout, e.g.
+    // after recreating a buffer it points at
+    pub fn set(&mut self, group: u32, bind_group: wgpu::BindGroup) -> Result<()> {
+        let slot = group
+            .checked_sub(FIRST_USER_GROUP)
+            .and_then(|index| self.bind_groups.get_mut(index as usize));
+        let Some(slot) = slot else {
+            anyhow::bail!("there's no user bind group {group}");
+        };
+        *slot = bind_group;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.layouts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layouts.is_empty()
+    }
+
+    pub fn layouts(&self) -> impl Iterator<Item = &wgpu::BindGroupLayout> {
+        self.layouts.iter()
+    }
+
+    // True once after layouts were added
+    pub fn take_layouts_changed(&mut self) -> bool {
+        std::mem::take(&mut self.layouts_changed)
+    }
+
+    pub fn bind<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        for (group, bind_group) in (FIRST_USER_GROUP..).zip(&self.bind_groups) {
+            rpass.set_bind_group(group, bind_group, &[]);
+        }
+    }
+}
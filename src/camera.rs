@@ -1,16 +1,62 @@
-use cgmath::SquareMatrix;
+use std::path::Path;
+
+use anyhow::{ensure, Result};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Rotation, Rotation3, SquareMatrix};
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
+
+use crate::globals::GlobalsState;
 
 pub struct Camera {
     eye: cgmath::Point3<f32>,
     target: cgmath::Point3<f32>,
     up: cgmath::Vector3<f32>,
     fov: f32,
+    fov_axis: FovAxis,
     aspect: f32,
     znear: f32,
     zfar: f32,
 }
 
+// Which way across the view `Camera::fov` is measured. With a horizontal fov
+// the vertical one follows the aspect ratio, so a wider window shows less
+// above and below instead of more to the sides.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FovAxis {
+    #[default]
+    Vertical,
+    Horizontal,
+}
+
+// Everything needed to restore a camera, aspect ratio is left out since
+// that comes from the window
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct CameraConfig {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    pub fov: f32,
+    // Missing from configs saved before it existed
+    #[serde(default)]
+    pub fov_axis: FovAxis,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl CameraConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -19,99 +65,868 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.0, 1.0,
 );
 
+// Flips clip space depth so the near plane lands on 1 and the far plane on 0
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Camera {
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.view_projection(self.aspect)
+    }
+
+    // With some other aspect ratio than the window's, e.g. a viewport's
+    fn view_projection(&self, aspect: f32) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fov), self.aspect, self.znear, self.zfar);
+        let proj = cgmath::perspective(self.vertical_fov_at(aspect), aspect, self.znear, self.zfar);
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
 
+    // Inverse of the view projection with the camera's translation removed. It maps
+    // clip space positions straight to world space directions, as a skybox needs.
+    pub fn build_inverse_sky_matrix(&self) -> cgmath::Matrix4<f32> {
+        let mut view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+        view.w = cgmath::Vector4::unit_w();
+        let proj = cgmath::perspective(self.vertical_fov(), self.aspect, self.znear, self.zfar);
+        (OPENGL_TO_WGPU_MATRIX * proj * view)
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity)
+    }
+
     pub fn new() -> Self {
+        Self::looking_at(
+            cgmath::Point3::new(0.0, 8.0, 15.0),
+            cgmath::Point3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    pub fn looking_at(eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) -> Self {
         Self {
-            eye: cgmath::Point3::new(0.0, 8.0, 15.0),
-            target: cgmath::Point3::new(0.0, 0.0, 0.0),
+            eye,
+            target,
             up: cgmath::Vector3::unit_y(),
             fov: 45.0,
+            fov_axis: FovAxis::Vertical,
             aspect: 1.0,
             znear: 0.1,
             zfar: 100.0,
         }
     }
 
+    pub fn to_config(&self) -> CameraConfig {
+        CameraConfig {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: self.up.into(),
+            fov: self.fov,
+            fov_axis: self.fov_axis,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+
+    pub fn from_config(config: CameraConfig) -> Result<Self> {
+        let mut camera = Self {
+            eye: config.eye.into(),
+            target: config.target.into(),
+            fov_axis: config.fov_axis,
+            ..Self::new()
+        };
+        camera.set_up(config.up.into())?;
+        camera.set_fov(config.fov)?;
+        camera.set_clip_planes(config.znear, config.zfar)?;
+        Ok(camera)
+    }
+
     pub fn update_aspect_ratio(&mut self, aspect: f32) {
         self.aspect = aspect;
     }
+
+    // The world's up direction, unit Y by default. `orbit` yaws around it.
+    pub fn set_up(&mut self, up: cgmath::Vector3<f32>) -> Result<()> {
+        self.up = self.checked_up(up)?;
+        Ok(())
+    }
+
+    // `up` normalized. A zero-length up vector, or one along the view direction,
+    // leaves look_at_rh without a right vector and it produces NaNs.
+    fn checked_up(&self, up: cgmath::Vector3<f32>) -> Result<cgmath::Vector3<f32>> {
+        ensure!(
+            up.magnitude2() > f32::EPSILON && up.magnitude2().is_finite(),
+            "invalid up vector {up:?}"
+        );
+        let up = up.normalize();
+        let forward = (self.target - self.eye).normalize();
+        ensure!(
+            up.cross(forward).magnitude2() > f32::EPSILON,
+            "up vector {up:?} is parallel to the view direction {forward:?}"
+        );
+        Ok(up)
+    }
+
+    // Field of view in degrees, along `fov_axis`
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
+
+    pub fn fov_axis(&self) -> FovAxis {
+        self.fov_axis
+    }
+
+    // `fov` keeps its value, so switching axes changes the framing
+    pub fn set_fov_axis(&mut self, fov_axis: FovAxis) {
+        self.fov_axis = fov_axis;
+    }
+
+    // What `cgmath::perspective` takes, worked out from the current aspect ratio
+    // when the fov is horizontal
+    fn vertical_fov(&self) -> cgmath::Rad<f32> {
+        self.vertical_fov_at(self.aspect)
+    }
+
+    fn vertical_fov_at(&self, aspect: f32) -> cgmath::Rad<f32> {
+        let fov = cgmath::Rad::from(cgmath::Deg(self.fov));
+        match self.fov_axis {
+            FovAxis::Vertical => fov,
+            FovAxis::Horizontal => cgmath::Rad(2.0 * ((fov.0 / 2.0).tan() / aspect).atan()),
+        }
+    }
+
+    pub fn set_fov(&mut self, fov: f32) -> Result<()> {
+        ensure!(
+            fov.is_finite() && 0.0 < fov && fov < 180.0,
+            "invalid field of view {fov}, it needs to be between 0 and 180 degrees"
+        );
+        self.fov = fov;
+        Ok(())
+    }
+
+    pub fn clip_planes(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
+    // The depth buffer is Depth32Float compared with Less and cleared to 1.0, so any
+    // valid pair works. Precision is spread by the far / near ratio though, keep
+    // near as large as the scene allows.
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) -> Result<()> {
+        ensure!(
+            near.is_finite() && far.is_finite() && 0.0 < near && near < far,
+            "invalid clip planes near = {near}, far = {far}, they need 0 < near < far"
+        );
+        self.znear = near;
+        self.zfar = far;
+        Ok(())
+    }
+
+    // Right, up and backwards unit vectors of the view
+    fn basis(&self) -> [cgmath::Vector3<f32>; 3] {
+        let back = (self.eye - self.target).normalize();
+        let right = self.up.cross(back).normalize();
+        [right, back.cross(right), back]
+    }
+
+    // Moves the eye around the target, yawing about the up vector and pitching
+    // towards it, both in radians. Pitch stops just short of the poles.
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        let offset = cgmath::Quaternion::from_axis_angle(self.up, cgmath::Rad(yaw))
+            .rotate_vector(self.eye - self.target);
+
+        let axis = offset.cross(self.up);
+        let offset = if axis.magnitude2() > f32::EPSILON {
+            let polar = self.up.angle(offset).0;
+            let pitch = pitch.clamp(
+                polar - (std::f32::consts::PI - POLE_MARGIN),
+                polar - POLE_MARGIN,
+            );
+            cgmath::Quaternion::from_axis_angle(axis.normalize(), cgmath::Rad(pitch))
+                .rotate_vector(offset)
+        } else {
+            offset
+        };
+
+        self.eye = self.target + offset;
+    }
+
+    // Shifts the eye and target together along the view's right and up vectors
+    pub fn pan(&mut self, right: f32, up: f32) {
+        let [right_axis, up_axis, _] = self.basis();
+        let offset = right_axis * right + up_axis * up;
+        self.eye += offset;
+        self.target += offset;
+    }
+
+    // Scales the eye's distance to the target, a factor below 1 moves closer
+    pub fn zoom(&mut self, factor: f32) {
+        let offset = self.eye - self.target;
+        let distance = (offset.magnitude() * factor).max(MIN_ZOOM_DISTANCE);
+        self.eye = self.target + offset.normalize_to(distance);
+    }
+
+    pub fn distance_to_target(&self) -> f32 {
+        (self.eye - self.target).magnitude()
+    }
+
+    // Keeps the view direction but moves the camera so the whole box is in view.
+    // The far plane is pushed out if the box would otherwise be clipped.
+    pub fn frame_bounds(&mut self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) {
+        let center = min.midpoint(max);
+        let radius = (max - min).magnitude() / 2.0;
+
+        // The box's bounding sphere has to fit the narrower of the two fovs
+        let half_fov_y = self.vertical_fov().0 / 2.0;
+        let half_fov_x = (half_fov_y.tan() * self.aspect).atan();
+        let half_fov = half_fov_y.min(half_fov_x);
+        let distance = (radius / half_fov.sin()).max(MIN_ZOOM_DISTANCE);
+
+        let [_, _, back] = self.basis();
+        self.target = center;
+        self.eye = center + back * distance;
+        self.zfar = self.zfar.max(distance + radius);
+    }
+
+    // Rotates the eye and up vector around the target
+    pub fn rotate_about_target(&mut self, rotation: cgmath::Quaternion<f32>) {
+        self.eye = self.target + rotation.rotate_vector(self.eye - self.target);
+        self.up = rotation.rotate_vector(self.up).normalize();
+    }
+}
+
+// How close to straight up or down, in radians, orbiting can get
+const POLE_MARGIN: f32 = 0.01;
+// Zooming stops here, going through the target would flip the view
+const MIN_ZOOM_DISTANCE: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControllerMode {
+    // Yaw around the up vector and pitch, stopping short of the poles
+    Orbit,
+    // Drags a virtual sphere around the target, free of gimbal lock
+    Arcball,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DragAction {
+    Rotate,
+    Pan,
+    Zoom,
+}
+
+// Turns mouse drags into camera movement
+pub struct CameraController {
+    pub mode: ControllerMode,
+    // Radians the orbit turns per pixel dragged
+    pub orbit_sensitivity: f32,
+    // Fraction of the distance to the target moved per pixel, so panning covers
+    // the same part of the view at any zoom
+    pub pan_sensitivity: f32,
+    // Zoom factor exponent per pixel dragged
+    pub zoom_sensitivity: f32,
+    // Radians the orbit turns per unit of raw mouse motion, which is in the
+    // mouse's own counts rather than pixels
+    pub look_sensitivity: f32,
+    drag: Option<DragAction>,
+    last_cursor: Option<(f32, f32)>,
+}
+
+impl CameraController {
+    pub fn new() -> Self {
+        Self {
+            mode: ControllerMode::Orbit,
+            orbit_sensitivity: 0.005,
+            pan_sensitivity: 0.0015,
+            zoom_sensitivity: 0.005,
+            look_sensitivity: 0.003,
+            drag: None,
+            last_cursor: None,
+        }
+    }
+
+    // A newly pressed button takes over the drag, releasing it only ends the
+    // drag if it's still the one in control
+    pub fn set_drag(&mut self, action: DragAction, pressed: bool) {
+        if pressed {
+            self.drag = Some(action);
+        } else if self.drag == Some(action) {
+            self.drag = None;
+        }
+    }
+
+    // Call for every cursor move, even when not dragging, so the next drag
+    // starts from where the cursor actually is
+    pub fn cursor_moved(
+        &mut self,
+        camera: &mut Camera,
+        cursor: (f32, f32),
+        size: PhysicalSize<u32>,
+    ) {
+        let Some(last) = self.last_cursor.replace(cursor) else {
+            return;
+        };
+        let dx = cursor.0 - last.0;
+        let dy = cursor.1 - last.1;
+
+        match (self.drag, self.mode) {
+            (None, _) => {}
+            (Some(DragAction::Pan), _) => {
+                // Drag the scene along with the cursor
+                let scale = self.pan_sensitivity * camera.distance_to_target();
+                camera.pan(-dx * scale, dy * scale);
+            }
+            (Some(DragAction::Zoom), _) => {
+                camera.zoom((dy * self.zoom_sensitivity).exp());
+            }
+            (Some(DragAction::Rotate), ControllerMode::Orbit) => {
+                camera.orbit(-dx * self.orbit_sensitivity, dy * self.orbit_sensitivity);
+            }
+            (Some(DragAction::Rotate), ControllerMode::Arcball) => {
+                let [right, up, back] = camera.basis();
+                let to_world =
+                    |point: cgmath::Vector3<f32>| right * point.x + up * point.y + back * point.z;
+                let from = to_world(arcball_point(last, size));
+                let to = to_world(arcball_point(cursor, size));
+                // The drag turns the scene with the cursor, so the camera turns the other way
+                let rotation = cgmath::Quaternion::from_arc(from, to, None);
+                camera.rotate_about_target(rotation.conjugate());
+            }
+        }
+    }
+
+    // Mouse look from `DeviceEvent::MouseMotion`, the mouse's raw movement. The
+    // cursor is locked in place while looking, so it has no position to diff
+    // and `cursor_moved` would stop at the window's edge anyway.
+    pub fn look(&self, camera: &mut Camera, delta: (f32, f32)) {
+        camera.orbit(
+            -delta.0 * self.look_sensitivity,
+            delta.1 * self.look_sensitivity,
+        );
+    }
+}
+
+// Projects a cursor position onto a unit sphere filling the window, points
+// outside of it land on the sphere's silhouette
+fn arcball_point(cursor: (f32, f32), size: PhysicalSize<u32>) -> cgmath::Vector3<f32> {
+    let x = 2.0 * cursor.0 / size.width.max(1) as f32 - 1.0;
+    let y = 1.0 - 2.0 * cursor.1 / size.height.max(1) as f32;
+    let length2 = x * x + y * y;
+    if length2 <= 1.0 {
+        cgmath::Vector3::new(x, y, (1.0 - length2).sqrt())
+    } else {
+        cgmath::Vector3::new(x, y, 0.0).normalize()
+    }
+}
+
+// The six clip planes of a view projection, normals pointing inwards
+pub struct Frustum {
+    planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction. The matrix includes OPENGL_TO_WGPU_MATRIX,
+    // so clip space depth is 0 <= z <= w and the near plane is just the z row.
+    pub fn from_view_projection(view_proj: &cgmath::Matrix4<f32>) -> Self {
+        let (x, y, z, w) = (
+            view_proj.row(0),
+            view_proj.row(1),
+            view_proj.row(2),
+            view_proj.row(3),
+        );
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            // Normalize so distances come out in world units
+            plane / plane.truncate().magnitude()
+        });
+        Self { planes }
+    }
+
+    // False only when the sphere is entirely outside one of the planes
+    pub fn intersects_sphere(&self, center: cgmath::Vector3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
+    // Fragments with a lower texture alpha are discarded, 0 keeps them all
+    alpha_cutoff: f32,
+    // Multiplies the shaded color, a brightness knob that works without HDR
+    exposure: f32,
+    // 1 when the surface is composited with premultiplied alpha, the unblended
+    // pipelines then multiply their color by it
+    premultiply_alpha: u32,
+    // Uniform buffers are laid out in 16 byte chunks
+    _padding: f32,
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            alpha_cutoff: 0.0,
+            exposure: 1.0,
+            premultiply_alpha: 0,
+            _padding: 0.0,
+        }
+    }
+
+    // For drawing the scene from somewhere other than a camera, e.g. the shadow
+    // casting light, with nothing discarded
+    pub fn from_view_proj(view_proj: cgmath::Matrix4<f32>) -> Self {
+        Self {
+            view_proj: view_proj.into(),
+            ..Self::new()
         }
     }
 
-    fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+    fn update_view_proj(&mut self, view_proj: cgmath::Matrix4<f32>, reverse_z: bool) {
+        self.view_proj = if reverse_z {
+            REVERSE_Z_MATRIX * view_proj
+        } else {
+            view_proj
+        }
+        .into();
     }
 }
 
+// A part of the window drawn from one of the cameras. The rectangle is in
+// fractions of the window's size from its top left corner, so it follows resizes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub camera: usize,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn full(camera: usize) -> Self {
+        Self {
+            camera,
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+
+    // Equally wide columns over the whole height, one per camera, left to right
+    pub fn side_by_side(cameras: &[usize]) -> Vec<Self> {
+        let width = 1.0 / cameras.len() as f32;
+        cameras
+            .iter()
+            .enumerate()
+            .map(|(column, &camera)| Self {
+                x: column as f32 * width,
+                width,
+                ..Self::full(camera)
+            })
+            .collect()
+    }
+
+    // x, y, width and height in physical pixels. Every edge is rounded the same
+    // way, so neighbouring viewports meet without a gap or an overlap.
+    pub fn rect(&self, size: PhysicalSize<u32>) -> (u32, u32, u32, u32) {
+        let edge = |fraction: f32, length: u32| {
+            (fraction * length as f32).round().clamp(0.0, length as f32) as u32
+        };
+        let left = edge(self.x, size.width);
+        let top = edge(self.y, size.height);
+        let right = edge(self.x + self.width, size.width);
+        let bottom = edge(self.y + self.height, size.height);
+        (left, top, right - left, bottom - top)
+    }
+
+    // The cameras are kept at the window's aspect ratio
+    fn aspect(&self, camera: &Camera) -> f32 {
+        camera.aspect * self.width / self.height
+    }
+
+    fn contains(&self, point: (f32, f32), size: PhysicalSize<u32>) -> bool {
+        let (x, y, width, height) = self.rect(size);
+        let (x, y) = (x as f32, y as f32);
+        (x..x + width as f32).contains(&point.0) && (y..y + height as f32).contains(&point.1)
+    }
+
+    fn check(&self) -> Result<()> {
+        // A little slack for fractions like thirds that don't add up exactly
+        let inside = |start: f32, length: f32| {
+            0.0 <= start && 0.0 < length && start + length <= 1.0 + f32::EPSILON
+        };
+        ensure!(
+            inside(self.x, self.width) && inside(self.y, self.height),
+            "viewport {self:?} doesn't fit in the window"
+        );
+        Ok(())
+    }
+}
+
+// A viewport's own copy of the camera uniform, so each can be drawn with a
+// different camera in the same pass
+struct ViewportCamera {
+    viewport: Viewport,
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
 pub struct CameraState {
-    pub camera: Camera,
+    // Each camera keeps its own view, switching only changes which one is uploaded
+    cameras: Vec<Camera>,
+    active: usize,
+    // Split screen when not empty, otherwise the active camera fills the window
+    // and is drawn with `bind_group`
+    viewports: Vec<ViewportCamera>,
+    // Only the uploaded matrix is reversed, everything computed on the CPU
+    // (culling, picking) keeps the standard 0 near, 1 far depth
+    pub reverse_z: bool,
+    // Uploaded along with the matrix, see `CameraUniform::alpha_cutoff`
+    pub alpha_cutoff: f32,
+    // Uploaded along with the matrix, see `CameraUniform::exposure`
+    pub exposure: f32,
+    // Uploaded along with the matrix, see `CameraUniform::premultiply_alpha`
+    pub premultiply_alpha: bool,
     pub uniform: CameraUniform,
     pub buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
+    // Shared by every camera's bind group, at binding 1
+    pub globals: GlobalsState,
 }
 
 impl CameraState {
     pub fn new(device: &wgpu::Device) -> Self {
         let uniform = CameraUniform::new();
-
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&[uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let globals = GlobalsState::new(device);
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    // The fragment stage reads the alpha cutoff
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                GlobalsState::layout_entry(1),
+            ],
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
-        });
+        let buffer = create_uniform_buffer(device, uniform);
+        let bind_group = create_bind_group(device, &bind_group_layout, &buffer, &globals.buffer);
 
         Self {
-            camera: Camera::new(),
+            cameras: vec![
+                Camera::new(),
+                // Looking down on the grid from above
+                Camera::looking_at(
+                    cgmath::Point3::new(0.0, 25.0, 1.0),
+                    cgmath::Point3::new(0.0, 0.0, 0.0),
+                ),
+                // Level with the grid, from the side
+                Camera::looking_at(
+                    cgmath::Point3::new(20.0, 1.0, 0.0),
+                    cgmath::Point3::new(0.0, 0.0, 0.0),
+                ),
+            ],
+            active: 0,
+            viewports: Vec::new(),
+            reverse_z: false,
+            alpha_cutoff: 0.0,
+            exposure: 1.0,
+            premultiply_alpha: false,
             uniform,
             buffer,
             bind_group,
             bind_group_layout,
+            globals,
+        }
+    }
+
+    // A camera group for drawing with the matrix in `buffer`, which holds a
+    // `CameraUniform`, e.g. to draw the scene from somewhere else
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        create_bind_group(
+            device,
+            &self.bind_group_layout,
+            buffer,
+            &self.globals.buffer,
+        )
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.cameras[self.active]
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.cameras[self.active]
+    }
+
+    // Makes the next camera active, wrapping around, and returns its index
+    pub fn next_camera(&mut self) -> usize {
+        self.active = (self.active + 1) % self.cameras.len();
+        self.active
+    }
+
+    pub fn set_active(&mut self, index: usize) -> Result<()> {
+        let count = self.cameras.len();
+        ensure!(
+            index < count,
+            "camera index {index} out of range, there are {count} cameras"
+        );
+        self.active = index;
+        Ok(())
+    }
+
+    // Applied to every camera, or none of them when it's invalid for one
+    pub fn set_up(&mut self, up: cgmath::Vector3<f32>) -> Result<()> {
+        for camera in &self.cameras {
+            camera.checked_up(up)?;
+        }
+        for camera in &mut self.cameras {
+            camera.set_up(up)?;
+        }
+        Ok(())
+    }
+
+    // Splits the window between cameras, e.g. `Viewport::side_by_side(&[0, 1])`
+    // to compare two of them. An empty list goes back to the active camera
+    // filling the window. Nothing changes when a viewport doesn't fit in the
+    // window or names a camera that doesn't exist.
+    pub fn set_viewports(&mut self, device: &wgpu::Device, viewports: &[Viewport]) -> Result<()> {
+        let count = self.cameras.len();
+        for viewport in viewports {
+            ensure!(
+                viewport.camera < count,
+                "viewport camera {} out of range, there are {count} cameras",
+                viewport.camera
+            );
+            viewport.check()?;
+        }
+        self.viewports = viewports
+            .iter()
+            .map(|&viewport| {
+                let uniform = CameraUniform::new();
+                let buffer = create_uniform_buffer(device, uniform);
+                let bind_group = self.create_bind_group(device, &buffer);
+                ViewportCamera {
+                    viewport,
+                    uniform,
+                    buffer,
+                    bind_group,
+                }
+            })
+            .collect();
+        Ok(())
+    }
+
+    pub fn is_split_screen(&self) -> bool {
+        !self.viewports.is_empty()
+    }
+
+    // Where to draw the scene and the camera bind group to draw it with, the
+    // whole window and the active camera unless the screen is split
+    pub fn viewports(&self) -> impl Iterator<Item = (Viewport, &wgpu::BindGroup)> {
+        let full = (Viewport::full(self.active), &self.bind_group);
+        let split = self
+            .viewports
+            .iter()
+            .map(|view| (view.viewport, &view.bind_group));
+        std::iter::once(full)
+            .filter(|_| self.viewports.is_empty())
+            .chain(split)
+    }
+
+    // Applied to every camera, so a newly activated one already has the right aspect
+    pub fn update_aspect_ratio(&mut self, aspect: f32) {
+        for camera in &mut self.cameras {
+            camera.update_aspect_ratio(aspect);
         }
     }
 
     pub fn update(&mut self) {
-        self.uniform.update_view_proj(&self.camera);
+        self.uniform.update_view_proj(
+            self.cameras[self.active].build_view_projection_matrix(),
+            self.reverse_z,
+        );
+        self.uniform.alpha_cutoff = self.alpha_cutoff;
+        self.uniform.exposure = self.exposure;
+        self.uniform.premultiply_alpha = self.premultiply_alpha.into();
+        for view in &mut self.viewports {
+            let camera = &self.cameras[view.viewport.camera];
+            let aspect = view.viewport.aspect(camera);
+            view.uniform
+                .update_view_proj(camera.view_projection(aspect), self.reverse_z);
+            view.uniform.alpha_cutoff = self.alpha_cutoff;
+            view.uniform.exposure = self.exposure;
+            view.uniform.premultiply_alpha = self.premultiply_alpha.into();
+        }
+    }
+
+    // After `update`, once per frame
+    pub fn write_buffers(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+        for view in &self.viewports {
+            queue.write_buffer(&view.buffer, 0, bytemuck::cast_slice(&[view.uniform]));
+        }
+    }
+
+    // The viewport under a cursor in physical pixels. The last one drawn is on
+    // top where they overlap, and the active camera's full window view is
+    // used in gaps between them.
+    fn viewport_at(&self, cursor: (f32, f32), size: PhysicalSize<u32>) -> Viewport {
+        self.viewports
+            .iter()
+            .rev()
+            .map(|view| view.viewport)
+            .find(|viewport| viewport.contains(cursor, size))
+            .unwrap_or_else(|| Viewport::full(self.active))
+    }
+
+    // Returns a world space ray (origin on the near plane, normalized direction)
+    // through the given cursor position in physical pixels, from the camera of
+    // the viewport under it
+    pub fn screen_ray(
+        &self,
+        mouse: (f32, f32),
+        size: PhysicalSize<u32>,
+    ) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        let viewport = self.viewport_at(mouse, size);
+        let (left, top, width, height) = viewport.rect(size);
+        let x = 2.0 * (mouse.0 - left as f32) / width.max(1) as f32 - 1.0;
+        // Window y grows downwards, NDC y grows upwards
+        let y = 1.0 - 2.0 * (mouse.1 - top as f32) / height.max(1) as f32;
+
+        // The view projection includes OPENGL_TO_WGPU_MATRIX, so the clip space
+        // depth runs from 0 at the near plane to 1 at the far plane, not GL's -1 to 1
+        let camera = &self.cameras[viewport.camera];
+        let inverse = camera
+            .view_projection(viewport.aspect(camera))
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        let unproject =
+            |z: f32| cgmath::Point3::from_homogeneous(inverse * cgmath::Vector4::new(x, y, z, 1.0));
+
+        let near = unproject(0.0);
+        let far = unproject(1.0);
+        (near, (far - near).normalize())
+    }
+}
+
+fn create_uniform_buffer(device: &wgpu::Device, uniform: CameraUniform) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: None,
+        contents: bytemuck::cast_slice(&[uniform]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    globals_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: globals_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_clip_planes_changes_the_projection() {
+        let mut camera = Camera::new();
+        let before = camera.build_view_projection_matrix();
+
+        camera.set_clip_planes(1.0, 500.0).unwrap();
+
+        assert_eq!(camera.clip_planes(), (1.0, 500.0));
+        assert_ne!(camera.build_view_projection_matrix(), before);
+    }
+
+    #[test]
+    fn set_clip_planes_rejects_invalid_planes() {
+        let mut camera = Camera::new();
+        assert!(camera.set_clip_planes(0.0, 100.0).is_err());
+        assert!(camera.set_clip_planes(-1.0, 100.0).is_err());
+        assert!(camera.set_clip_planes(10.0, 10.0).is_err());
+        assert!(camera.set_clip_planes(10.0, 1.0).is_err());
+        assert!(camera.set_clip_planes(0.1, f32::INFINITY).is_err());
+        // Rejected planes leave the camera as it was
+        assert_eq!(camera.clip_planes(), (0.1, 100.0));
+    }
+
+    #[test]
+    fn side_by_side_viewports_tile_the_window() {
+        let size = PhysicalSize::new(1001, 480);
+        let viewports = Viewport::side_by_side(&[0, 1, 2]);
+        let mut right_edge = 0;
+        for viewport in &viewports {
+            viewport.check().unwrap();
+            let (x, y, width, height) = viewport.rect(size);
+            assert_eq!((x, y, height), (right_edge, 0, 480));
+            right_edge = x + width;
+        }
+        assert_eq!(right_edge, 1001);
+        let past_the_edge = Viewport {
+            x: 0.5,
+            width: 0.6,
+            ..Viewport::full(0)
+        };
+        assert!(past_the_edge.check().is_err());
+        let flat = Viewport {
+            height: 0.0,
+            ..Viewport::full(0)
+        };
+        assert!(flat.check().is_err());
+    }
+
+    #[test]
+    fn set_up_rejects_up_along_the_view() {
+        let mut camera = Camera::looking_at(
+            cgmath::Point3::new(0.0, 10.0, 0.0),
+            cgmath::Point3::new(0.0, 0.0, 0.0),
+        );
+        assert!(camera.set_up(cgmath::Vector3::unit_y()).is_err());
+        assert!(camera.set_up(-cgmath::Vector3::unit_y()).is_err());
+        assert!(camera.set_up(cgmath::Vector3::new(0.0, 0.0, 0.0)).is_err());
+        camera.set_up(cgmath::Vector3::new(0.0, 0.0, 2.0)).unwrap();
+        let view_proj: [[f32; 4]; 4] = camera.build_view_projection_matrix().into();
+        assert!(view_proj.iter().flatten().all(|value| value.is_finite()));
     }
 }
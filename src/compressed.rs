@@ -0,0 +1,292 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+// Requested with the device when the adapter has them. Desktop GPUs tend to
+// only have BC and mobile ones ETC2 and ASTC, so files usually come in both.
+pub const FEATURES: wgpu::Features = wgpu::Features::TEXTURE_COMPRESSION_BC
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC);
+
+// Decoded in place of a compressed file the device can't sample, from the
+// same path with the first of these extensions that exists
+const FALLBACK_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "webp"];
+
+// Whether `path` names a KTX2 or DDS file, going by its extension
+pub fn is_compressed_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("ktx2") || extension.eq_ignore_ascii_case("dds")
+        })
+}
+
+// The uncompressed image next to the compressed file at `path`
+pub fn fallback_path(path: &Path) -> Option<PathBuf> {
+    FALLBACK_EXTENSIONS
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|fallback| fallback.is_file())
+}
+
+// A 2D texture read from a KTX2 or DDS file, with its texels already in the
+// format they're uploaded in. Mip levels come from the file, nothing is
+// generated for them.
+pub struct CompressedImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub layers: u32,
+    // Largest first, each holding every layer one after the other
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let is_ktx2 = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"));
+        let image = if is_ktx2 {
+            Self::from_ktx2(&bytes)
+        } else {
+            Self::from_dds(&bytes)
+        };
+        image.with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes)?;
+        let header = reader.header();
+        ensure!(
+            header.supercompression_scheme.is_none(),
+            "supercompressed KTX2 files aren't supported, got {:?}",
+            header.supercompression_scheme
+        );
+        ensure!(
+            header.pixel_depth <= 1 && header.face_count == 1,
+            "only 2D KTX2 textures are supported"
+        );
+        let format = header.format.and_then(ktx2_format).with_context(|| {
+            format!(
+                "unsupported KTX2 format {:?}, expected an sRGB color format",
+                header.format
+            )
+        })?;
+        Self::new(
+            format,
+            header.pixel_width,
+            header.pixel_height,
+            header.layer_count.max(1),
+            reader.levels().map(|level| level.data.to_vec()).collect(),
+        )
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self> {
+        let dds = ddsfile::Dds::read(bytes)?;
+        ensure!(dds.get_depth() <= 1, "only 2D DDS textures are supported");
+        let format = dds
+            .get_dxgi_format()
+            .and_then(dxgi_format)
+            .with_context(|| {
+                format!(
+                    "unsupported DDS format {:?}, expected an sRGB color format",
+                    dds.get_dxgi_format()
+                )
+            })?;
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let layers = dds.get_num_array_layers().max(1);
+        // DDS keeps each layer's mip chain together, wgpu wants each level's layers
+        let mut levels = vec![Vec::new(); dds.get_num_mipmap_levels().max(1) as usize];
+        for layer in 0..layers {
+            let mut data = dds.get_data(layer)?;
+            for (mip_level, level) in levels.iter_mut().enumerate() {
+                let size = level_size(format, width >> mip_level, height >> mip_level, 1);
+                ensure!(data.len() >= size, "DDS layer {layer} is cut short");
+                let (level_data, rest) = data.split_at(size);
+                level.extend_from_slice(level_data);
+                data = rest;
+            }
+        }
+        Self::new(format, width, height, layers, levels)
+    }
+
+    fn new(
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        layers: u32,
+        levels: Vec<Vec<u8>>,
+    ) -> Result<Self> {
+        let (block_width, block_height) = format.block_dimensions();
+        ensure!(
+            width > 0
+                && width.is_multiple_of(block_width)
+                && height > 0
+                && height.is_multiple_of(block_height),
+            "{width}x{height} isn't a whole number of {format:?}'s {block_width}x{block_height} blocks"
+        );
+        ensure!(!levels.is_empty(), "the file has no mip levels");
+        for (mip_level, level) in levels.iter().enumerate() {
+            let size = level_size(format, width >> mip_level, height >> mip_level, layers);
+            ensure!(
+                level.len() == size,
+                "mip level {mip_level} has {} bytes, {format:?} needs {size}",
+                level.len()
+            );
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            layers,
+            levels,
+        })
+    }
+
+    // Bytes in a row of blocks and rows of blocks in a layer, at `mip_level`
+    pub fn level_layout(&self, mip_level: u32) -> (u32, u32) {
+        let (width, height) = level_dimensions(self.format, self.width, self.height, mip_level);
+        let (block_width, block_height) = self.format.block_dimensions();
+        let block_size = self.format.block_size(None).unwrap_or(0);
+        (width / block_width * block_size, height / block_height)
+    }
+
+    // Width and height of `mip_level` rounded up to whole blocks, which is
+    // what copies into a compressed texture have to cover
+    pub fn level_extent(&self, mip_level: u32) -> (u32, u32) {
+        level_dimensions(self.format, self.width, self.height, mip_level)
+    }
+}
+
+fn level_dimensions(format: TextureFormat, width: u32, height: u32, mip_level: u32) -> (u32, u32) {
+    let (block_width, block_height) = format.block_dimensions();
+    (
+        (width >> mip_level).max(1).next_multiple_of(block_width),
+        (height >> mip_level).max(1).next_multiple_of(block_height),
+    )
+}
+
+fn level_size(format: TextureFormat, width: u32, height: u32, layers: u32) -> usize {
+    let (width, height) = level_dimensions(format, width, height, 0);
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_size(None).unwrap_or(0);
+    (width / block_width * (height / block_height) * block_size * layers) as usize
+}
+
+// Only sRGB formats, the shader expects color textures to be decoded to linear
+// when they're sampled. Linear ones, like every one and two channel format,
+// would come out too bright.
+fn ktx2_format(format: ktx2::Format) -> Option<TextureFormat> {
+    use ktx2::Format as K;
+    let astc = |block| TextureFormat::Astc {
+        block,
+        channel: AstcChannel::UnormSrgb,
+    };
+    Some(match format {
+        K::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        K::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        K::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        K::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        K::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4),
+        K::ASTC_5x4_SRGB_BLOCK => astc(AstcBlock::B5x4),
+        K::ASTC_5x5_SRGB_BLOCK => astc(AstcBlock::B5x5),
+        K::ASTC_6x5_SRGB_BLOCK => astc(AstcBlock::B6x5),
+        K::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6),
+        K::ASTC_8x5_SRGB_BLOCK => astc(AstcBlock::B8x5),
+        K::ASTC_8x6_SRGB_BLOCK => astc(AstcBlock::B8x6),
+        K::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8),
+        K::ASTC_10x5_SRGB_BLOCK => astc(AstcBlock::B10x5),
+        K::ASTC_10x6_SRGB_BLOCK => astc(AstcBlock::B10x6),
+        K::ASTC_10x8_SRGB_BLOCK => astc(AstcBlock::B10x8),
+        K::ASTC_10x10_SRGB_BLOCK => astc(AstcBlock::B10x10),
+        K::ASTC_12x10_SRGB_BLOCK => astc(AstcBlock::B12x10),
+        K::ASTC_12x12_SRGB_BLOCK => astc(AstcBlock::B12x12),
+        _ => return None,
+    })
+}
+
+// DDS only has BC formats among the compressed ones
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    Some(match format {
+        D::R8G8B8A8_UNorm_sRGB => TextureFormat::Rgba8UnormSrgb,
+        D::BC1_UNorm_sRGB => TextureFormat::Bc1RgbaUnormSrgb,
+        D::BC2_UNorm_sRGB => TextureFormat::Bc2RgbaUnormSrgb,
+        D::BC3_UNorm_sRGB => TextureFormat::Bc3RgbaUnormSrgb,
+        D::BC7_UNorm_sRGB => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dds_layers_are_regrouped_by_level() {
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: 8,
+            width: 8,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC1_UNorm_sRGB,
+            mipmap_levels: Some(2),
+            array_layers: Some(2),
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })
+        .unwrap();
+        // 4 blocks of 8 bytes at level 0, 1 at level 1, per layer
+        for layer in 0..2u8 {
+            let data = dds.get_mut_data(layer as u32).unwrap();
+            assert_eq!(data.len(), 40);
+            data[..32].fill(layer);
+            data[32..].fill(10 + layer);
+        }
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+
+        let image = CompressedImage::from_dds(&bytes).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(image.layers, 2);
+        assert_eq!(image.levels[0], [[0; 32], [1; 32]].concat());
+        assert_eq!(image.levels[1], [[10; 8], [11; 8]].concat());
+        assert_eq!(image.level_layout(1), (8, 1));
+        assert_eq!(image.level_extent(1), (4, 4));
+    }
+
+    #[test]
+    fn linear_formats_are_rejected() {
+        let dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC7_UNorm,
+            mipmap_levels: None,
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })
+        .unwrap();
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+
+        assert!(CompressedImage::from_dds(&bytes).is_err());
+    }
+
+    #[test]
+    fn partial_blocks_are_rejected() {
+        let result = CompressedImage::new(TextureFormat::Bc7RgbaUnorm, 6, 4, 1, vec![vec![0; 32]]);
+        assert!(result.is_err());
+    }
+}
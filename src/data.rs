@@ -1,3 +1,7 @@
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use cgmath::{InnerSpace, Matrix3, SquareMatrix, Vector3};
 use wgpu::util::DeviceExt;
 
 #[repr(C)]
@@ -5,11 +9,32 @@ use wgpu::util::DeviceExt;
 pub struct VertexData {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    normal: [f32; 3],
+    // Points along +u of the tex_coords, w is the handedness of the bitangent,
+    // `cross(normal, tangent.xyz) * tangent.w`, which mirrored UVs flip
+    tangent: [f32; 4],
 }
 
+// Placeholder until `compute_tangents` runs over the mesh, the shader ignores the
+// normal map of vertices that still have it
+const NO_TANGENT: [f32; 4] = [0.0; 4];
+
 impl VertexData {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2];
+    pub fn new(position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> Self {
+        Self {
+            position,
+            tex_coords,
+            normal,
+            tangent: NO_TANGENT,
+        }
+    }
+
+    const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x3,
+        3 => Float32x4
+    ];
 
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
@@ -20,73 +45,263 @@ impl VertexData {
     }
 }
 
-// Cube vertices
+// Cube vertices, every face has its own four so each can carry the face normal
+// and the whole texture. Seen from outside, u runs left to right and v bottom to
+// top on every face, the top and bottom faces turned to carry on from the front
+// face's edges.
 const VERTICES: &[VertexData] = &[
     // Front face
     VertexData {
         position: [-0.5, -0.5,  0.5],
         tex_coords: [0.0, 0.0],
+        normal: [ 0.0,  0.0,  1.0],
+        tangent: NO_TANGENT,
     }, // 0: front bottom left
     VertexData {
         position: [ 0.5, -0.5,  0.5],
         tex_coords: [1.0, 0.0],
+        normal: [ 0.0,  0.0,  1.0],
+        tangent: NO_TANGENT,
     }, // 1: front bottom right
     VertexData {
         position: [ 0.5,  0.5,  0.5],
         tex_coords: [1.0, 1.0],
+        normal: [ 0.0,  0.0,  1.0],
+        tangent: NO_TANGENT,
     }, // 2: front top right
     VertexData {
         position: [-0.5,  0.5,  0.5],
         tex_coords: [0.0, 1.0],
+        normal: [ 0.0,  0.0,  1.0],
+        tangent: NO_TANGENT,
     }, // 3: front top left
 
     // Back face
     VertexData {
         position: [-0.5, -0.5, -0.5],
         tex_coords: [1.0, 0.0],
+        normal: [ 0.0,  0.0, -1.0],
+        tangent: NO_TANGENT,
     }, // 4: back bottom left
     VertexData {
         position: [ 0.5, -0.5, -0.5],
         tex_coords: [0.0, 0.0],
+        normal: [ 0.0,  0.0, -1.0],
+        tangent: NO_TANGENT,
     }, // 5: back bottom right
     VertexData {
         position: [ 0.5,  0.5, -0.5],
         tex_coords: [0.0, 1.0],
+        normal: [ 0.0,  0.0, -1.0],
+        tangent: NO_TANGENT,
     }, // 6: back top right
     VertexData {
         position: [-0.5,  0.5, -0.5],
         tex_coords: [1.0, 1.0],
+        normal: [ 0.0,  0.0, -1.0],
+        tangent: NO_TANGENT,
     }, // 7: back top left
+
+    // Left face
+    VertexData {
+        position: [-0.5,  0.5, -0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [-1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 8: back top left
+    VertexData {
+        position: [-0.5,  0.5,  0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [-1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 9: front top left
+    VertexData {
+        position: [-0.5, -0.5,  0.5],
+        tex_coords: [1.0, 0.0],
+        normal: [-1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 10: front bottom left
+    VertexData {
+        position: [-0.5, -0.5, -0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [-1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 11: back bottom left
+
+    // Right face
+    VertexData {
+        position: [ 0.5, -0.5,  0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [ 1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 12: front bottom right
+    VertexData {
+        position: [ 0.5, -0.5, -0.5],
+        tex_coords: [1.0, 0.0],
+        normal: [ 1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 13: back bottom right
+    VertexData {
+        position: [ 0.5,  0.5, -0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [ 1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 14: back top right
+    VertexData {
+        position: [ 0.5,  0.5,  0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [ 1.0,  0.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 15: front top right
+
+    // Bottom face
+    VertexData {
+        position: [-0.5, -0.5, -0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [ 0.0, -1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 16: back bottom left
+    VertexData {
+        position: [-0.5, -0.5,  0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [ 0.0, -1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 17: front bottom left
+    VertexData {
+        position: [ 0.5, -0.5,  0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [ 0.0, -1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 18: front bottom right
+    VertexData {
+        position: [ 0.5, -0.5, -0.5],
+        tex_coords: [1.0, 0.0],
+        normal: [ 0.0, -1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 19: back bottom right
+
+    // Top face
+    VertexData {
+        position: [-0.5,  0.5,  0.5],
+        tex_coords: [0.0, 0.0],
+        normal: [ 0.0,  1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 20: front top left
+    VertexData {
+        position: [-0.5,  0.5, -0.5],
+        tex_coords: [0.0, 1.0],
+        normal: [ 0.0,  1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 21: back top left
+    VertexData {
+        position: [ 0.5,  0.5, -0.5],
+        tex_coords: [1.0, 1.0],
+        normal: [ 0.0,  1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 22: back top right
+    VertexData {
+        position: [ 0.5,  0.5,  0.5],
+        tex_coords: [1.0, 0.0],
+        normal: [ 0.0,  1.0,  0.0],
+        tangent: NO_TANGENT,
+    }, // 23: front top right
 ];
 
+// Counter-clockwise when the face is seen from outside the cube, so back faces
+// can be culled
 const INDICES: &[u16] = &[
     // Front face
     0, 1, 2,  2, 3, 0,
     // Back face
-    4, 5, 6,  6, 7, 4,
+    4, 7, 6,  6, 5, 4,
     // Left face
-    7, 3, 0,  0, 4, 7,
+    8, 11, 10,  10, 9, 8,
     // Right face
-    1, 5, 6,  6, 2, 1,
+    12, 13, 14,  14, 15, 12,
     // Bottom face
-    4, 0, 1,  1, 5, 4,
+    16, 19, 18,  18, 17, 16,
     // Top face
-    3, 7, 6,  6, 2, 3,
+    20, 23, 22,  22, 21, 20,
 ];
 
+// Fills in the tangents of an indexed triangle list from its positions, normals
+// and tex_coords. Every triangle adds its tangent and bitangent to its three
+// vertices, so vertices shared between triangles end up with the average, then
+// each tangent is made perpendicular to its vertex's normal. Triangles whose
+// tex_coords have no area don't contribute, a vertex without any other triangle
+// gets an arbitrary tangent perpendicular to its normal.
+pub fn compute_tangents<I: Copy + Into<u32>>(vertices: &mut [VertexData], indices: &[I]) {
+    let mut tangents = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    let mut bitangents = tangents.clone();
+
+    for triangle in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [0, 1, 2].map(|corner| triangle[corner].into() as usize);
+        let [v0, v1, v2] = [i0, i1, i2].map(|i| vertices[i]);
+
+        let edge1 = Vector3::from(v1.position) - Vector3::from(v0.position);
+        let edge2 = Vector3::from(v2.position) - Vector3::from(v0.position);
+        let [du1, dv1] = [0, 1].map(|axis| v1.tex_coords[axis] - v0.tex_coords[axis]);
+        let [du2, dv2] = [0, 1].map(|axis| v2.tex_coords[axis] - v0.tex_coords[axis]);
+
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let tangent = (edge1 * dv2 - edge2 * dv1) / det;
+        let bitangent = (edge2 * du1 - edge1 * du2) / det;
+        for i in [i0, i1, i2] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vector3::from(vertex.normal);
+        // Gram-Schmidt, drop the part of the tangent along the normal
+        let mut orthogonal = tangent - normal * normal.dot(tangent);
+        if orthogonal.magnitude2() < f32::EPSILON {
+            // Any axis that isn't parallel to the normal will do
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            orthogonal = axis - normal * normal.dot(axis);
+        }
+        let tangent = orthogonal.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
+
 pub struct VertexState {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
+    pub index_format: wgpu::IndexFormat,
     pub num_vertices: u32,
     pub num_indices: u32,
+    // Model space box around the vertices, what instances of this geometry are
+    // culled, picked and framed by
+    pub bounds: (cgmath::Point3<f32>, cgmath::Point3<f32>),
+    // How many vertices / indices the buffers can hold. Only dynamic buffers are
+    // ever rewritten, static ones are created exactly as large as their data.
+    vertex_capacity: u32,
+    index_capacity: u32,
 }
 
 impl VertexState {
     pub fn new(device: &wgpu::Device) -> Self {
+        let mut vertices = VERTICES.to_vec();
+        compute_tangents(&mut vertices, INDICES);
+
         Self {
             vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: "vertex_buffer".into(),
-                contents: bytemuck::cast_slice(VERTICES),
+                contents: bytemuck::cast_slice(&vertices),
                 usage: wgpu::BufferUsages::VERTEX,
             }),
             index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -94,8 +309,325 @@ impl VertexState {
                 contents: bytemuck::cast_slice(INDICES),
                 usage: wgpu::BufferUsages::INDEX,
             }),
+            index_format: wgpu::IndexFormat::Uint16,
             num_vertices: VERTICES.len() as u32,
             num_indices: INDICES.len() as u32,
+            bounds: vertex_bounds(&vertices),
+            vertex_capacity: VERTICES.len() as u32,
+            index_capacity: INDICES.len() as u32,
         }
     }
+
+    // Empty buffers for geometry that changes at runtime, filled with
+    // `update_vertices` and `update_indices`. They grow when data outgrows them.
+    pub fn new_dynamic(device: &wgpu::Device, vertex_capacity: u32, index_capacity: u32) -> Self {
+        Self {
+            vertex_buffer: create_dynamic_buffer::<VertexData>(
+                device,
+                "dynamic vertex buffer",
+                wgpu::BufferUsages::VERTEX,
+                vertex_capacity,
+            ),
+            index_buffer: create_dynamic_buffer::<u32>(
+                device,
+                "dynamic index buffer",
+                wgpu::BufferUsages::INDEX,
+                index_capacity,
+            ),
+            index_format: wgpu::IndexFormat::Uint32,
+            num_vertices: 0,
+            num_indices: 0,
+            bounds: vertex_bounds(&[]),
+            vertex_capacity,
+            index_capacity,
+        }
+    }
+
+    pub fn update_vertices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: &[VertexData],
+    ) -> Result<()> {
+        ensure!(
+            self.vertex_buffer
+                .usage()
+                .contains(wgpu::BufferUsages::COPY_DST),
+            "vertex buffer is static, create it with VertexState::new_dynamic to update it"
+        );
+
+        let len = vertices.len() as u32;
+        if len > self.vertex_capacity {
+            // Double so a slowly growing mesh doesn't reallocate every frame
+            self.vertex_capacity = len.max(self.vertex_capacity * 2);
+            self.vertex_buffer = create_dynamic_buffer::<VertexData>(
+                device,
+                "dynamic vertex buffer",
+                wgpu::BufferUsages::VERTEX,
+                self.vertex_capacity,
+            );
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+        self.num_vertices = len;
+        self.bounds = vertex_bounds(vertices);
+        Ok(())
+    }
+
+    pub fn update_indices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        indices: &[u32],
+    ) -> Result<()> {
+        ensure!(
+            self.index_buffer
+                .usage()
+                .contains(wgpu::BufferUsages::COPY_DST),
+            "index buffer is static, create it with VertexState::new_dynamic to update it"
+        );
+
+        let len = indices.len() as u32;
+        if len > self.index_capacity {
+            self.index_capacity = len.max(self.index_capacity * 2);
+            self.index_buffer = create_dynamic_buffer::<u32>(
+                device,
+                "dynamic index buffer",
+                wgpu::BufferUsages::INDEX,
+                self.index_capacity,
+            );
+        }
+        queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(indices));
+        self.num_indices = len;
+        Ok(())
+    }
+
+    // Loads the first mesh of a glTF file, only meshes made of a single triangle
+    // list primitive are supported. The material's base color texture is returned
+    // as well, when it has one. The geometry is turned from `up_axis` up to the
+    // renderer's Y up.
+    pub fn from_gltf(
+        device: &wgpu::Device,
+        path: impl AsRef<Path>,
+        up_axis: UpAxis,
+    ) -> Result<(Self, Option<image::DynamicImage>)> {
+        let path = path.as_ref();
+        let (document, buffers, images) =
+            gltf::import(path).with_context(|| format!("failed to import {}", path.display()))?;
+
+        let mesh = document
+            .meshes()
+            .next()
+            .with_context(|| format!("{} has no meshes", path.display()))?;
+        let mesh_name = mesh.name().unwrap_or("unnamed");
+        let primitives = mesh.primitives().collect::<Vec<_>>();
+        ensure!(
+            primitives.len() == 1,
+            "mesh {mesh_name:?} has {} primitives, only single primitive meshes are supported",
+            primitives.len()
+        );
+        let primitive = &primitives[0];
+        ensure!(
+            primitive.mode() == gltf::mesh::Mode::Triangles,
+            "mesh {mesh_name:?} is drawn as {:?}, only triangle lists are supported",
+            primitive.mode()
+        );
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions = reader
+            .read_positions()
+            .with_context(|| format!("mesh {mesh_name:?} has no positions"))?;
+        let normals = reader
+            .read_normals()
+            .with_context(|| format!("mesh {mesh_name:?} has no normals"))?
+            .collect::<Vec<_>>();
+        // Untextured meshes still need some coordinates for the shared vertex layout
+        let tex_coords = reader
+            .read_tex_coords(0)
+            .map(|tex_coords| tex_coords.into_f32().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let tangents = reader
+            .read_tangents()
+            .map(|tangents| tangents.collect::<Vec<_>>());
+
+        let to_y_up = up_axis.to_y_up();
+        let turn = |v: [f32; 3]| -> [f32; 3] { (to_y_up * Vector3::from(v)).into() };
+        let mut vertices = positions
+            .enumerate()
+            .map(|(i, position)| {
+                let [x, y, z, w] = tangents
+                    .as_ref()
+                    .and_then(|tangents| tangents.get(i).copied())
+                    .unwrap_or(NO_TANGENT);
+                let [x, y, z] = turn([x, y, z]);
+                VertexData {
+                    position: turn(position),
+                    tex_coords: tex_coords.get(i).copied().unwrap_or_default(),
+                    normal: turn(normals.get(i).copied().unwrap_or_default()),
+                    // A rotation keeps the bitangent's handedness
+                    tangent: [x, y, z, w],
+                }
+            })
+            .collect::<Vec<_>>();
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= vertices.len())
+        {
+            bail!(
+                "mesh {mesh_name:?} has index {index}, past its {} vertices",
+                vertices.len()
+            );
+        }
+        // Tangents exported with the model match its normal maps best, only
+        // compute them when there aren't any
+        if tangents.is_none() {
+            compute_tangents(&mut vertices, &indices);
+        }
+
+        let texture = primitive
+            .material()
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .map(|info| gltf_image(&images[info.texture().source().index()]))
+            .transpose()?;
+
+        let state = Self {
+            vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(mesh_name),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }),
+            index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(mesh_name),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            num_vertices: vertices.len() as u32,
+            num_indices: indices.len() as u32,
+            bounds: vertex_bounds(&vertices),
+            vertex_capacity: vertices.len() as u32,
+            index_capacity: indices.len() as u32,
+        };
+        Ok((state, texture))
+    }
+}
+
+// The axis a model was authored with pointing up. glTF is Y up by definition,
+// but files exported from Z up tools like Blender without converting aren't rare.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UpAxis {
+    #[default]
+    Y,
+    Z,
+}
+
+impl UpAxis {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "y" => Ok(UpAxis::Y),
+            "z" => Ok(UpAxis::Z),
+            other => bail!("unknown up axis {other}, expected y or z"),
+        }
+    }
+
+    fn to_y_up(self) -> Matrix3<f32> {
+        match self {
+            UpAxis::Y => Matrix3::identity(),
+            // -90 degrees around X takes +Z to +Y and +Y, Z up's forward, to -Z
+            UpAxis::Z => Matrix3::from_angle_x(cgmath::Deg(-90.0)),
+        }
+    }
+}
+
+// A point at the origin when there are no vertices
+fn vertex_bounds(vertices: &[VertexData]) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+    let mut positions = vertices
+        .iter()
+        .map(|vertex| cgmath::Point3::from(vertex.position));
+    let first = positions
+        .next()
+        .unwrap_or(cgmath::Point3::new(0.0, 0.0, 0.0));
+    positions.fold((first, first), |bounds, position| {
+        crate::instance::merge_bounds(bounds, (position, position))
+    })
+}
+
+fn create_dynamic_buffer<T>(
+    device: &wgpu::Device,
+    label: &str,
+    usage: wgpu::BufferUsages,
+    capacity: u32,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (capacity as usize * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn gltf_image(data: &gltf::image::Data) -> Result<image::DynamicImage> {
+    use gltf::image::Format;
+
+    let (width, height, pixels) = (data.width, data.height, data.pixels.clone());
+    let image = match data.format {
+        Format::R8 => image::GrayImage::from_raw(width, height, pixels).map(Into::into),
+        Format::R8G8 => image::GrayAlphaImage::from_raw(width, height, pixels).map(Into::into),
+        Format::R8G8B8 => image::RgbImage::from_raw(width, height, pixels).map(Into::into),
+        Format::R8G8B8A8 => image::RgbaImage::from_raw(width, height, pixels).map(Into::into),
+        format => bail!("unsupported glTF texture format {format:?}"),
+    };
+    image.context("glTF texture data is smaller than its dimensions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One face's four vertices, in the order they're declared
+    fn faces() -> impl Iterator<Item = &'static [VertexData]> {
+        VERTICES.chunks_exact(4)
+    }
+
+    #[test]
+    fn every_face_shows_the_whole_texture() {
+        for face in faces() {
+            let mut corners = face.iter().map(|v| v.tex_coords).collect::<Vec<_>>();
+            corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(corners, [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]]);
+        }
+    }
+
+    #[test]
+    fn every_face_has_its_own_normal() {
+        for face in faces() {
+            assert!(face.iter().all(|v| v.normal == face[0].normal));
+        }
+        let mut normals = faces().map(|face| face[0].normal).collect::<Vec<_>>();
+        normals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        normals.dedup();
+        assert_eq!(normals.len(), 6);
+    }
+
+    #[test]
+    fn no_face_is_mirrored() {
+        let mut vertices = VERTICES.to_vec();
+        compute_tangents(&mut vertices, INDICES);
+        // A mirrored face flips the bitangent's handedness
+        assert!(vertices.iter().all(|v| v.tangent[3] == 1.0));
+    }
+
+    #[test]
+    fn z_up_turns_upright() {
+        let to_y_up = UpAxis::Z.to_y_up();
+        let up = to_y_up * Vector3::unit_z();
+        let forward = to_y_up * Vector3::unit_y();
+        assert!((up - Vector3::unit_y()).magnitude() < 1e-6);
+        assert!((forward + Vector3::unit_z()).magnitude() < 1e-6);
+        assert_eq!(UpAxis::Y.to_y_up(), Matrix3::identity());
+    }
 }
@@ -0,0 +1,151 @@
+use crate::axis::{LinePipelines, LineVertex};
+
+// Vertices a frame can draw when `DEBUG_DRAW_MAX_VERTICES` isn't set, a line takes two
+pub const DEFAULT_MAX_VERTICES: usize = 65536;
+
+// World space lines and points from user code, e.g. a picking ray or a mesh's
+// normals. Whatever was added before a frame is drawn in its scene pass, tested
+// against the meshes' depth like the axis gizmo, and cleared once it's submitted.
+// Past `max_vertices` the rest of the frame's shapes are dropped.
+pub struct DebugDraw {
+    lines: Vec<LineVertex>,
+    points: Vec<LineVertex>,
+    max_vertices: usize,
+    // Shapes that didn't fit this frame, warned about when it's cleared
+    dropped: usize,
+    // Lines first, then points, rewritten every frame
+    vertex_buffer: wgpu::Buffer,
+    pipelines: LinePipelines,
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        target_format: wgpu::TextureFormat,
+        sample_count: u32,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+        max_vertices: usize,
+    ) -> Self {
+        let max_vertices = max_vertices.max(1);
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("debug draw vertex buffer"),
+            size: (max_vertices * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipelines = LinePipelines::new(
+            device,
+            "debug draw",
+            camera_bind_group_layout,
+            target_format,
+            sample_count,
+            scene_depth_stencil,
+            &[
+                wgpu::PrimitiveTopology::LineList,
+                wgpu::PrimitiveTopology::PointList,
+            ],
+        );
+
+        Self {
+            lines: Vec::new(),
+            points: Vec::new(),
+            max_vertices,
+            dropped: 0,
+            vertex_buffer,
+            pipelines,
+        }
+    }
+
+    // `color` is linear RGB
+    pub fn line(&mut self, a: cgmath::Point3<f32>, b: cgmath::Point3<f32>, color: [f32; 3]) {
+        if self.len() + 2 > self.max_vertices {
+            self.dropped += 1;
+            return;
+        }
+        self.lines.extend([a, b].map(|position| LineVertex {
+            position: position.into(),
+            color,
+        }));
+    }
+
+    pub fn point(&mut self, position: cgmath::Point3<f32>, color: [f32; 3]) {
+        if self.len() + 1 > self.max_vertices {
+            self.dropped += 1;
+            return;
+        }
+        self.points.push(LineVertex {
+            position: position.into(),
+            color,
+        });
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len() + self.points.len()
+    }
+
+    pub fn set_depth_stencil(
+        &mut self,
+        device: &wgpu::Device,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+    ) {
+        self.pipelines
+            .set_depth_stencil(device, scene_depth_stencil);
+    }
+
+    pub fn set_target_format(
+        &mut self,
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        scene_depth_stencil: Option<wgpu::DepthStencilState>,
+    ) {
+        self.pipelines
+            .set_target_format(device, target_format, scene_depth_stencil);
+    }
+
+    // Before the scene pass is recorded
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        let points_offset = std::mem::size_of_val(self.lines.as_slice()) as wgpu::BufferAddress;
+        for (offset, vertices) in [(0, &self.lines), (points_offset, &self.points)] {
+            if !vertices.is_empty() {
+                queue.write_buffer(&self.vertex_buffer, offset, bytemuck::cast_slice(vertices));
+            }
+        }
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.len() == 0 {
+            return;
+        }
+        let lines = self.lines.len() as u32;
+        rpass.set_bind_group(0, camera_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        if lines > 0 {
+            rpass.set_pipeline(self.pipelines.get(wgpu::PrimitiveTopology::LineList));
+            rpass.draw(0..lines, 0..1);
+        }
+        if !self.points.is_empty() {
+            rpass.set_pipeline(self.pipelines.get(wgpu::PrimitiveTopology::PointList));
+            rpass.draw(lines..self.len() as u32, 0..1);
+        }
+    }
+
+    // Once the frame that drew the shapes is submitted
+    pub fn clear(&mut self) {
+        if self.dropped > 0 {
+            log::warn!(
+                "Dropped {} debug shapes over the limit of {} vertices, see DEBUG_DRAW_MAX_VERTICES",
+                self.dropped,
+                self.max_vertices
+            );
+        }
+        self.lines.clear();
+        self.points.clear();
+        self.dropped = 0;
+    }
+}
@@ -0,0 +1,43 @@
+struct DepthVisUniform {
+    near: f32,
+    far: f32,
+    reverse_z: u32,
+}
+
+// Bound as a plain float texture, the GL backend can't `textureLoad` from depth textures
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<uniform> params: DepthVisUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// A single triangle that covers the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var depth = textureLoad(t_depth, vec2<i32>(in.clip_position.xy), 0).x;
+    if params.reverse_z != 0u {
+        depth = 1.0 - depth;
+    }
+
+    // OPENGL_TO_WGPU_MATRIX remapped z from [-1, 1] to [0, 1], undo that first
+    let z_ndc = depth * 2.0 - 1.0;
+    // Invert the perspective projection to get the view-space distance back
+    let linear = (2.0 * params.near * params.far)
+        / (params.far + params.near - z_ndc * (params.far - params.near));
+    let value = clamp((linear - params.near) / (params.far - params.near), 0.0, 1.0);
+
+    return vec4<f32>(value, value, value, 1.0);
+}
@@ -0,0 +1,93 @@
+use anyhow::{ensure, Result};
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use winit::dpi::PhysicalSize;
+
+use crate::instance::{Layout, SpinMode};
+use crate::mesh::Mesh;
+use crate::{
+    alpha_mode_from_env, backends_from_env, choose_alpha_mode, choose_present_mode,
+    choose_swapchain_format, hdr_output_from_env, max_frames_in_flight_from_env,
+    present_modes_from_env, request_adapter, App, FrameStats, PipelineConfig, RenderState,
+};
+
+// Draws the scene into a window owned by another windowing stack (SDL, Tauri, an
+// existing app) rather than one created by winit. There's no event loop here, the
+// host calls `resize` when its window changes size and `render` for each frame.
+pub struct EmbeddedRenderer {
+    surface: wgpu::Surface,
+    render_state: RenderState,
+    meshes: Vec<Mesh>,
+}
+
+impl EmbeddedRenderer {
+    /// # Safety
+    ///
+    /// `window` must stay valid for as long as the returned renderer exists.
+    pub unsafe fn new<W>(window: &W, width: u32, height: u32) -> Result<Self>
+    where
+        W: HasRawWindowHandle + HasRawDisplayHandle,
+    {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: backends_from_env(),
+            ..Default::default()
+        });
+        // The surface keeps the instance alive
+        let surface = instance.create_surface(window)?;
+        let adapter = pollster::block_on(request_adapter(&instance, Some(&surface)))?;
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        let swapchain_format =
+            choose_swapchain_format(&surface_caps.formats, hdr_output_from_env());
+        let mut render_state = pollster::block_on(App::init_render_state(
+            &adapter,
+            swapchain_format,
+            PipelineConfig::default(),
+        ))?;
+        render_state.present_mode =
+            choose_present_mode(&surface_caps.present_modes, &present_modes_from_env());
+        // The host window has to be created transparent for anything but Opaque
+        render_state.set_alpha_mode(choose_alpha_mode(
+            &surface_caps.alpha_modes,
+            alpha_mode_from_env(),
+        ));
+        render_state
+            .frames_in_flight
+            .set_max(max_frames_in_flight_from_env());
+        let meshes = render_state.load_scene(None, Layout::default(), SpinMode::default());
+
+        let mut renderer = Self {
+            surface,
+            render_state,
+            meshes,
+        };
+        renderer.resize(width, height);
+        Ok(renderer)
+    }
+
+    // A zero sized surface can't be configured, minimized windows keep the old size
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.render_state
+            .configure_surface(&self.surface, PhysicalSize::new(width, height));
+    }
+
+    // Fails when the surface is outdated or lost, which a `resize` fixes, and once
+    // the device is lost, after which the renderer has to be recreated
+    pub fn render(&mut self) -> Result<()> {
+        ensure!(!self.render_state.is_device_lost(), "the device was lost");
+        self.render_state
+            .frames_in_flight
+            .wait_for_slot(&self.render_state.device);
+        let frame = self.surface.get_current_texture()?;
+        let dt = self.render_state.advance_clock();
+        self.render_state.draw_frame(frame, &mut self.meshes, dt)?;
+        Ok(())
+    }
+
+    // What the last `render` drew
+    pub fn frame_stats(&self) -> FrameStats {
+        self.render_state.frame_stats
+    }
+}
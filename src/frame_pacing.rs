@@ -0,0 +1,52 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Two lets the CPU record a frame while the GPU draws the last one
+pub const DEFAULT_MAX_FRAMES_IN_FLIGHT: u32 = 2;
+
+// Frames submitted to the queue whose GPU work hasn't finished yet. Waiting for
+// a free slot before starting the next frame keeps the CPU from running ahead
+// of the GPU by more than `max` frames, which is latency the swapchain would
+// otherwise add by blocking somewhere less predictable.
+pub struct FramesInFlight {
+    max: u32,
+    // Oldest first, each flag is set once its submission is done
+    submitted: VecDeque<(wgpu::SubmissionIndex, Arc<AtomicBool>)>,
+}
+
+impl FramesInFlight {
+    pub fn new(max: u32) -> Self {
+        Self {
+            max: max.max(1),
+            submitted: VecDeque::new(),
+        }
+    }
+
+    // At least 1, where the GPU finishes every frame before the next one starts
+    pub fn set_max(&mut self, max: u32) {
+        self.max = max.max(1);
+    }
+
+    // Right after the frame's last submit
+    pub fn submitted(&mut self, queue: &wgpu::Queue, index: wgpu::SubmissionIndex) {
+        let done = Arc::new(AtomicBool::new(false));
+        let flag = done.clone();
+        queue.on_submitted_work_done(move || flag.store(true, Ordering::Relaxed));
+        self.submitted.push_back((index, done));
+    }
+
+    // Blocks until fewer than `max` frames are in flight
+    pub fn wait_for_slot(&mut self, device: &wgpu::Device) {
+        // Runs the callbacks of whatever already finished
+        device.poll(wgpu::Maintain::Poll);
+        self.submitted
+            .retain(|(_, done)| !done.load(Ordering::Relaxed));
+        while self.submitted.len() >= self.max as usize {
+            let Some((index, _)) = self.submitted.pop_front() else {
+                break;
+            };
+            device.poll(wgpu::Maintain::WaitForSubmissionIndex(index));
+        }
+    }
+}
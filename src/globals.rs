@@ -0,0 +1,67 @@
+use std::num::NonZeroU64;
+
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+// Matches `GlobalsUniform` in shader.wgsl, the resolution's vec2 lands on its
+// 8 byte alignment
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlobalsUniform {
+    time_secs: f32,
+    frame: u32,
+    resolution: [f32; 2],
+}
+
+// Time, frame count and resolution for effects in the scene shader, bound
+// next to the camera, see `CameraState::new`. Only uploaded while `enabled`,
+// shaders that don't animate can leave it off and read zeroes.
+pub struct GlobalsState {
+    pub enabled: bool,
+    uniform: GlobalsUniform,
+    pub buffer: wgpu::Buffer,
+}
+
+impl GlobalsState {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uniform = GlobalsUniform::zeroed();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("globals uniform"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+        }
+    }
+
+    // For the group that binds `buffer` at `binding`
+    pub fn layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(std::mem::size_of::<GlobalsUniform>() as u64),
+            },
+            count: None,
+        }
+    }
+
+    // Once per frame with the frame's `dt`, the same one the instances animate
+    // by, so the time stops and starts with them
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32, size: winit::dpi::PhysicalSize<u32>) {
+        if !self.enabled {
+            return;
+        }
+        self.uniform.time_secs += dt;
+        self.uniform.resolution = [size.width as f32, size.height as f32];
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.uniform));
+        // The first frame drawn is frame 0
+        self.uniform.frame = self.uniform.frame.wrapping_add(1);
+    }
+}
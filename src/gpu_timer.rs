@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const QUERY_COUNT: u32 = 2;
+const BUFFER_SIZE: wgpu::BufferAddress =
+    QUERY_COUNT as wgpu::BufferAddress * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+// Measures the GPU time of a frame with a pair of timestamp queries. The result
+// is read back a frame or more later, without ever waiting on the GPU.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    // The readback buffer is waiting on a `map_async` started by `end_frame`
+    pending: bool,
+    mapped: Arc<AtomicBool>,
+    last_frame_ms: Option<f32>,
+}
+
+impl GpuTimer {
+    // None when the device wasn't created with TIMESTAMP_QUERY
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("frame timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp resolve buffer"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp readback buffer"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            pending: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+            last_frame_ms: None,
+        })
+    }
+
+    pub fn begin_frame(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        self.read_back(device);
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        // The readback buffer can't be copied into while it's being mapped, this
+        // frame's timing is skipped then
+        if !self.pending {
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                BUFFER_SIZE,
+            );
+        }
+    }
+
+    // Call after the frame's encoder is submitted
+    pub fn after_submit(&mut self) {
+        if self.pending {
+            return;
+        }
+        self.pending = true;
+
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Release),
+                Err(e) => log::error!("Failed to map the timestamp readback buffer: {e}"),
+            });
+    }
+
+    fn read_back(&mut self, device: &wgpu::Device) {
+        if !self.pending {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            let ticks = timestamps[1].wrapping_sub(timestamps[0]);
+            self.last_frame_ms = Some(ticks as f32 * self.period / 1_000_000.0);
+        }
+        self.readback_buffer.unmap();
+        self.pending = false;
+    }
+
+    // GPU time of the most recently measured frame, in milliseconds
+    pub fn last_frame_ms(&self) -> Option<f32> {
+        self.last_frame_ms
+    }
+}
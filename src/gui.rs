@@ -0,0 +1,500 @@
+use std::time::Instant;
+
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::{CursorIcon, Window};
+
+use crate::mesh::Mesh;
+use crate::{FovAxis, RenderState};
+
+// A UI frame built by `run`, waiting to be drawn by `render`
+struct GuiFrame {
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+// Lines scrolled by a mouse wheel notch, in points
+const SCROLL_LINE_HEIGHT: f32 = 50.0;
+
+// The system clipboard, where arboard has one. egui-winit keeps it the same way.
+struct Clipboard {
+    #[cfg(not(target_os = "android"))]
+    arboard: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    fn new() -> Self {
+        Self {
+            #[cfg(not(target_os = "android"))]
+            arboard: arboard::Clipboard::new()
+                .map_err(|e| log::warn!("No clipboard for the debug panel: {e}"))
+                .ok(),
+        }
+    }
+
+    fn get(&mut self) -> Option<String> {
+        #[cfg(not(target_os = "android"))]
+        if let Some(clipboard) = &mut self.arboard {
+            match clipboard.get_text() {
+                Ok(text) => return Some(text),
+                Err(e) => log::warn!("Failed to paste: {e}"),
+            }
+        }
+        None
+    }
+
+    fn set(&mut self, text: String) {
+        #[cfg(not(target_os = "android"))]
+        if let Some(clipboard) = &mut self.arboard {
+            if let Err(e) = clipboard.set_text(text) {
+                log::warn!("Failed to copy: {e}");
+            }
+        }
+        #[cfg(target_os = "android")]
+        let _ = text;
+    }
+}
+
+// Collects winit's window events into egui's input for the next frame. There's
+// no egui-winit release for winit 0.30 that works with this egui, so this does
+// its job for what the debug panel needs: the pointer, scrolling, the keys for
+// editing a slider's value, text and the clipboard.
+struct EguiInput {
+    raw: egui::RawInput,
+    pixels_per_point: f32,
+    pointer_pos: Option<egui::Pos2>,
+    start_time: Instant,
+    clipboard: Clipboard,
+}
+
+impl EguiInput {
+    fn new(window: &Window, max_texture_side: usize) -> Self {
+        Self {
+            raw: egui::RawInput {
+                max_texture_side: Some(max_texture_side),
+                ..Default::default()
+            },
+            pixels_per_point: window.scale_factor() as f32,
+            pointer_pos: None,
+            start_time: Instant::now(),
+            clipboard: Clipboard::new(),
+        }
+    }
+
+    fn on_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.pixels_per_point = *scale_factor as f32;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let pos = egui::pos2(
+                    position.x as f32 / self.pixels_per_point,
+                    position.y as f32 / self.pixels_per_point,
+                );
+                self.pointer_pos = Some(pos);
+                self.raw.events.push(egui::Event::PointerMoved(pos));
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer_pos = None;
+                self.raw.events.push(egui::Event::PointerGone);
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    _ => return,
+                };
+                if let Some(pos) = self.pointer_pos {
+                    self.raw.events.push(egui::Event::PointerButton {
+                        pos,
+                        button,
+                        pressed: *state == ElementState::Pressed,
+                        modifiers: self.raw.modifiers,
+                    });
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => egui::vec2(*x, *y) * SCROLL_LINE_HEIGHT,
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        egui::vec2(delta.x as f32, delta.y as f32) / self.pixels_per_point
+                    }
+                };
+                self.raw.events.push(egui::Event::Scroll(delta));
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                let state = modifiers.state();
+                self.raw.modifiers = egui::Modifiers {
+                    alt: state.alt_key(),
+                    ctrl: state.control_key(),
+                    shift: state.shift_key(),
+                    mac_cmd: cfg!(target_os = "macos") && state.super_key(),
+                    command: if cfg!(target_os = "macos") {
+                        state.super_key()
+                    } else {
+                        state.control_key()
+                    },
+                };
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if pressed {
+                    if let Some(command) = self.clipboard_command(&event.logical_key) {
+                        self.raw.events.push(command);
+                        return;
+                    }
+                }
+                if let Some(key) = egui_key(&event.logical_key) {
+                    self.raw.events.push(egui::Event::Key {
+                        key,
+                        pressed,
+                        repeat: false,
+                        modifiers: self.raw.modifiers,
+                    });
+                }
+                // Shortcuts don't type anything
+                let text = event.text.as_ref().filter(|text| {
+                    pressed
+                        && !self.raw.modifiers.ctrl
+                        && !self.raw.modifiers.mac_cmd
+                        && text.chars().all(|c| !c.is_control())
+                });
+                if let Some(text) = text {
+                    self.raw.events.push(egui::Event::Text(text.to_string()));
+                }
+            }
+            WindowEvent::Focused(focused) => {
+                self.raw.focused = *focused;
+            }
+            _ => {}
+        }
+    }
+
+    // Copy, cut and paste, from their own keys or the usual shortcuts
+    fn clipboard_command(&mut self, key: &Key) -> Option<egui::Event> {
+        let command = self.raw.modifiers.command;
+        match key.as_ref() {
+            Key::Named(NamedKey::Copy) => Some(egui::Event::Copy),
+            Key::Named(NamedKey::Cut) => Some(egui::Event::Cut),
+            Key::Named(NamedKey::Paste) => self.clipboard.get().map(egui::Event::Paste),
+            Key::Character("c" | "C") if command => Some(egui::Event::Copy),
+            Key::Character("x" | "X") if command => Some(egui::Event::Cut),
+            Key::Character("v" | "V") if command => self.clipboard.get().map(egui::Event::Paste),
+            _ => None,
+        }
+    }
+
+    // The input gathered since the last call, for a frame of `window`
+    fn take(&mut self, window: &Window) -> egui::RawInput {
+        let size = window.inner_size();
+        self.raw.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(size.width as f32, size.height as f32) / self.pixels_per_point,
+        ));
+        self.raw.pixels_per_point = Some(self.pixels_per_point);
+        self.raw.time = Some(self.start_time.elapsed().as_secs_f64());
+        self.raw.take()
+    }
+}
+
+fn egui_key(key: &Key) -> Option<egui::Key> {
+    let Key::Named(key) = key else {
+        return None;
+    };
+    Some(match key {
+        NamedKey::ArrowDown => egui::Key::ArrowDown,
+        NamedKey::ArrowLeft => egui::Key::ArrowLeft,
+        NamedKey::ArrowRight => egui::Key::ArrowRight,
+        NamedKey::ArrowUp => egui::Key::ArrowUp,
+        NamedKey::Escape => egui::Key::Escape,
+        NamedKey::Tab => egui::Key::Tab,
+        NamedKey::Backspace => egui::Key::Backspace,
+        NamedKey::Enter => egui::Key::Enter,
+        NamedKey::Space => egui::Key::Space,
+        NamedKey::Delete => egui::Key::Delete,
+        NamedKey::Home => egui::Key::Home,
+        NamedKey::End => egui::Key::End,
+        NamedKey::PageUp => egui::Key::PageUp,
+        NamedKey::PageDown => egui::Key::PageDown,
+        _ => return None,
+    })
+}
+
+// `None` has no winit icon, the cursor is only hidden by mouse look
+fn winit_cursor(icon: egui::CursorIcon) -> Option<CursorIcon> {
+    Some(match icon {
+        egui::CursorIcon::None => return None,
+        egui::CursorIcon::Default => CursorIcon::Default,
+        egui::CursorIcon::ContextMenu => CursorIcon::ContextMenu,
+        egui::CursorIcon::Help => CursorIcon::Help,
+        egui::CursorIcon::PointingHand => CursorIcon::Pointer,
+        egui::CursorIcon::Progress => CursorIcon::Progress,
+        egui::CursorIcon::Wait => CursorIcon::Wait,
+        egui::CursorIcon::Cell => CursorIcon::Cell,
+        egui::CursorIcon::Crosshair => CursorIcon::Crosshair,
+        egui::CursorIcon::Text => CursorIcon::Text,
+        egui::CursorIcon::VerticalText => CursorIcon::VerticalText,
+        egui::CursorIcon::Alias => CursorIcon::Alias,
+        egui::CursorIcon::Copy => CursorIcon::Copy,
+        egui::CursorIcon::Move => CursorIcon::Move,
+        egui::CursorIcon::NoDrop => CursorIcon::NoDrop,
+        egui::CursorIcon::NotAllowed => CursorIcon::NotAllowed,
+        egui::CursorIcon::Grab => CursorIcon::Grab,
+        egui::CursorIcon::Grabbing => CursorIcon::Grabbing,
+        egui::CursorIcon::AllScroll => CursorIcon::AllScroll,
+        egui::CursorIcon::ResizeHorizontal => CursorIcon::EwResize,
+        egui::CursorIcon::ResizeNeSw => CursorIcon::NeswResize,
+        egui::CursorIcon::ResizeNwSe => CursorIcon::NwseResize,
+        egui::CursorIcon::ResizeVertical => CursorIcon::NsResize,
+        egui::CursorIcon::ResizeEast => CursorIcon::EResize,
+        egui::CursorIcon::ResizeSouthEast => CursorIcon::SeResize,
+        egui::CursorIcon::ResizeSouth => CursorIcon::SResize,
+        egui::CursorIcon::ResizeSouthWest => CursorIcon::SwResize,
+        egui::CursorIcon::ResizeWest => CursorIcon::WResize,
+        egui::CursorIcon::ResizeNorthWest => CursorIcon::NwResize,
+        egui::CursorIcon::ResizeNorth => CursorIcon::NResize,
+        egui::CursorIcon::ResizeNorthEast => CursorIcon::NeResize,
+        egui::CursorIcon::ResizeColumn => CursorIcon::ColResize,
+        egui::CursorIcon::ResizeRow => CursorIcon::RowResize,
+        egui::CursorIcon::ZoomIn => CursorIcon::ZoomIn,
+        egui::CursorIcon::ZoomOut => CursorIcon::ZoomOut,
+    })
+}
+
+// egui's input, UI and renderer state. The renderer's textures belong to the
+// device, so this lives in `RenderState` and a new device starts with a fresh
+// context, which uploads its font texture again.
+pub struct GuiState {
+    context: egui::Context,
+    input: EguiInput,
+    renderer: egui_wgpu::Renderer,
+    frame: Option<GuiFrame>,
+    // Only changed on the window when egui asks for a different one
+    cursor_icon: egui::CursorIcon,
+}
+
+impl GuiState {
+    pub fn new(window: &Window, device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Self {
+        let max_texture_side = device.limits().max_texture_dimension_2d as usize;
+        Self {
+            context: egui::Context::default(),
+            input: EguiInput::new(window, max_texture_side),
+            renderer: egui_wgpu::Renderer::new(device, target_format, None, 1),
+            frame: None,
+            cursor_icon: egui::CursorIcon::Default,
+        }
+    }
+
+    // True when egui used the event and the scene shouldn't react to it
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        self.input.on_event(event);
+        match event {
+            // Button releases always reach the scene, otherwise a camera drag that
+            // ends over a window never stops
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                ..
+            } => false,
+            WindowEvent::MouseInput { .. } | WindowEvent::MouseWheel { .. } => {
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::CursorMoved { .. } => self.context.is_using_pointer(),
+            WindowEvent::KeyboardInput { .. } => self.context.wants_keyboard_input(),
+            _ => false,
+        }
+    }
+
+    pub fn run(&mut self, window: &Window, build_ui: impl FnOnce(&egui::Context)) {
+        let input = self.input.take(window);
+        let output = self.context.run(input, build_ui);
+        let platform_output = output.platform_output;
+        if platform_output.cursor_icon != self.cursor_icon {
+            self.cursor_icon = platform_output.cursor_icon;
+            if let Some(icon) = winit_cursor(self.cursor_icon) {
+                window.set_cursor(icon);
+            }
+        }
+        if !platform_output.copied_text.is_empty() {
+            self.input.clipboard.set(platform_output.copied_text);
+        }
+
+        self.frame = Some(GuiFrame {
+            paint_jobs: self.context.tessellate(output.shapes),
+            textures_delta: output.textures_delta,
+            pixels_per_point: self.context.pixels_per_point(),
+        });
+    }
+
+    // Draws the last frame from `run` over whatever is already in `view`
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        let Some(frame) = self.frame.take() else {
+            return;
+        };
+        let screen_descriptor = egui_wgpu::renderer::ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: frame.pixels_per_point,
+        };
+
+        for (id, delta) in &frame.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        // Only paint callbacks record into their own command buffers, and there
+        // aren't any. Submitting them ahead of the frame's encoder keeps their order.
+        let callback_commands = self.renderer.update_buffers(
+            device,
+            queue,
+            encoder,
+            &frame.paint_jobs,
+            &screen_descriptor,
+        );
+        if !callback_commands.is_empty() {
+            queue.submit(callback_commands);
+        }
+
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("gui pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer
+                .render(&mut rpass, &frame.paint_jobs, &screen_descriptor);
+        }
+
+        for id in &frame.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+// Sliders for the values worth tweaking while the scene runs
+pub fn debug_panel(ctx: &egui::Context, render_state: &mut RenderState, meshes: &mut [Mesh]) {
+    egui::Window::new("Debug").show(ctx, |ui| {
+        let camera = render_state.camera_state.camera_mut();
+        let mut fov = camera.fov();
+        if ui
+            .add(egui::Slider::new(&mut fov, 10.0..=120.0).text("FOV"))
+            .changed()
+        {
+            if let Err(e) = camera.set_fov(fov) {
+                log::warn!("{e}");
+            }
+        }
+        let mut fov_axis = camera.fov_axis();
+        ui.horizontal(|ui| {
+            ui.label("FOV axis");
+            ui.radio_value(&mut fov_axis, FovAxis::Vertical, "Vertical");
+            ui.radio_value(&mut fov_axis, FovAxis::Horizontal, "Horizontal");
+        });
+        camera.set_fov_axis(fov_axis);
+
+        let mut alpha_cutoff = render_state.camera_state.alpha_cutoff;
+        if ui
+            .add(egui::Slider::new(&mut alpha_cutoff, 0.0..=1.0).text("Alpha cutoff"))
+            .changed()
+        {
+            if let Err(e) = render_state.set_alpha_cutoff(alpha_cutoff) {
+                log::warn!("{e}");
+            }
+        }
+
+        let mut exposure = render_state.camera_state.exposure;
+        if ui
+            .add(egui::Slider::new(&mut exposure, 0.0..=4.0).text("Exposure"))
+            .changed()
+        {
+            if let Err(e) = render_state.set_exposure(exposure) {
+                log::warn!("{e}");
+            }
+        }
+
+        let color = render_state.clear_color;
+        let mut rgb = [color.r as f32, color.g as f32, color.b as f32];
+        ui.horizontal(|ui| {
+            ui.label("Clear color");
+            if ui.color_edit_button_rgb(&mut rgb).changed() {
+                render_state.clear_color = wgpu::Color {
+                    r: rgb[0] as f64,
+                    g: rgb[1] as f64,
+                    b: rgb[2] as f64,
+                    a: 1.0,
+                };
+            }
+        });
+
+        if render_state.wireframe_overlay_pipeline.is_some() {
+            wireframe_overlay_controls(ui, render_state);
+        }
+
+        let stats = render_state.frame_stats;
+        ui.separator();
+        ui.label(format!(
+            "{} draw calls, {} instances ({} culled), {} triangles",
+            stats.draw_calls, stats.instances, stats.culled_instances, stats.triangles
+        ));
+
+        for (index, mesh) in meshes.iter_mut().enumerate() {
+            let instance_state = &mut mesh.instances;
+            ui.separator();
+            ui.label(format!("Mesh {index}"));
+            ui.add(
+                egui::Slider::new(&mut instance_state.rotation_speed_scale, 0.0..=4.0)
+                    .text("Rotation speed"),
+            );
+            let mut count = instance_state.active_count();
+            let max_count = instance_state.num_instances() as usize;
+            if ui
+                .add(egui::Slider::new(&mut count, 0..=max_count).text("Instances"))
+                .changed()
+            {
+                instance_state.set_active_count(count);
+            }
+        }
+    });
+}
+
+fn wireframe_overlay_controls(ui: &mut egui::Ui, render_state: &mut RenderState) {
+    ui.separator();
+    ui.checkbox(
+        &mut render_state.wireframe_overlay.enabled,
+        "Wireframe overlay",
+    );
+
+    let mut color = render_state.wireframe_overlay.color();
+    ui.horizontal(|ui| {
+        ui.label("Line color");
+        if ui.color_edit_button_rgba_unmultiplied(&mut color).changed() {
+            render_state
+                .wireframe_overlay
+                .set_color(&render_state.queue, color);
+        }
+    });
+
+    // Given for standard depth, negative pulls the lines towards the camera
+    let mut bias = render_state.wireframe_overlay.depth_bias();
+    let constant_changed = ui
+        .add(egui::Slider::new(&mut bias.constant, -16..=0).text("Constant bias"))
+        .changed();
+    let slope_changed = ui
+        .add(egui::Slider::new(&mut bias.slope_scale, -4.0..=0.0).text("Slope bias"))
+        .changed();
+    if constant_changed || slope_changed {
+        render_state.set_wireframe_overlay_bias(bias);
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use image::RgbaImage;
+use winit::dpi::PhysicalSize;
+
+use crate::instance::{Layout, SpinMode};
+use crate::{backends_from_env, request_adapter, texture, App, PipelineConfig};
+
+// Size of the frames `run_headless` returns
+pub const HEADLESS_SIZE: PhysicalSize<u32> = PhysicalSize::new(256, 256);
+
+// A fixed seed and time step keep the frames identical between runs
+const HEADLESS_INSTANCE_SEED: u64 = 0;
+const HEADLESS_FRAME_DELTA: Duration = Duration::from_millis(16);
+
+const FRAME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Renders `frames` frames of the scene without a window or display and reads
+// each of them back. Scene loading follows the same environment variables as
+// the app. Fails when there's no adapter at all, e.g. on CI machines without a GPU,
+// and when `shader.wgsl` doesn't compile.
+pub fn run_headless(frames: u32) -> Result<Vec<RgbaImage>> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: backends_from_env(),
+        ..Default::default()
+    });
+    let adapter = pollster::block_on(request_adapter(&instance, None))?;
+    let mut render_state = pollster::block_on(App::init_render_state(
+        &adapter,
+        FRAME_FORMAT,
+        PipelineConfig::default(),
+    ))?;
+    render_state.surface_size = HEADLESS_SIZE;
+    render_state.fixed_frame_delta = Some(HEADLESS_FRAME_DELTA);
+    let mut meshes = render_state.load_scene(
+        Some(HEADLESS_INSTANCE_SEED),
+        Layout::default(),
+        SpinMode::default(),
+    );
+
+    let device = &render_state.device;
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("headless frame"),
+        size: wgpu::Extent3d {
+            width: HEADLESS_SIZE.width,
+            height: HEADLESS_SIZE.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FRAME_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let readback = FrameReadback::new(device);
+
+    let mut images = Vec::with_capacity(frames as usize);
+    for _ in 0..frames {
+        render_state
+            .frames_in_flight
+            .wait_for_slot(&render_state.device);
+        let dt = render_state.advance_clock();
+        render_state.render_frame(&view, &mut meshes, dt);
+        ensure!(!render_state.is_device_lost(), "the device was lost");
+        images.push(readback.read(&render_state.device, &render_state.queue, &target)?);
+    }
+    Ok(images)
+}
+
+// Copies a frame into a mappable buffer, whose rows are padded to wgpu's copy alignment
+struct FrameReadback {
+    buffer: wgpu::Buffer,
+    padded_bytes_per_row: u32,
+}
+
+impl FrameReadback {
+    fn new(device: &wgpu::Device) -> Self {
+        let padded_bytes_per_row = texture::align_bytes_per_row(HEADLESS_SIZE.width);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("headless readback"),
+            size: (padded_bytes_per_row * HEADLESS_SIZE.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            padded_bytes_per_row,
+        }
+    }
+
+    fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> Result<RgbaImage> {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .context("the readback buffer was never mapped")?
+            .context("mapping the readback buffer")?;
+
+        let row_bytes = (HEADLESS_SIZE.width * texture::BYTES_PER_PIXEL) as usize;
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(self.padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..row_bytes])
+            .copied()
+            .collect();
+        self.buffer.unmap();
+        RgbaImage::from_raw(HEADLESS_SIZE.width, HEADLESS_SIZE.height, pixels)
+            .context("readback has the wrong size")
+    }
+}
@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+
+// Watches a shader file on disk so it can be rebuilt without recompiling.
+// Only used by debug builds, release builds always use the embedded source.
+pub struct ShaderWatcher {
+    path: PathBuf,
+    changed: Arc<AtomicBool>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file_name = path
+            .file_name()
+            .context("shader path has no file name")?
+            .to_owned();
+
+        let changed = Arc::new(AtomicBool::new(false));
+        let flag = changed.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let is_shader = event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == Some(file_name.as_os_str()));
+                if is_shader && (event.kind.is_modify() || event.kind.is_create()) {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            })?;
+
+        // Editors often save by replacing the file, which a watch on the file
+        // itself wouldn't survive, so watch the directory instead
+        let dir = path
+            .parent()
+            .context("shader path has no parent directory")?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            path,
+            changed,
+            _watcher: watcher,
+        })
+    }
+
+    // The new source if the file changed since the last call
+    pub fn poll(&self) -> Option<String> {
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return None;
+        }
+
+        match std::fs::read_to_string(&self.path) {
+            Ok(source) => Some(source),
+            Err(e) => {
+                log::error!("Failed to read {}: {e}", self.path.display());
+                None
+            }
+        }
+    }
+}
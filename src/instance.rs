@@ -1,18 +1,65 @@
-use cgmath::{InnerSpace, Matrix4, Rotation3, Zero};
+use std::num::NonZeroU64;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use cgmath::{
+    ElementWise, EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Rotation, Rotation3,
+    SquareMatrix, Zero,
+};
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+
+use crate::camera::Frustum;
 
-struct Instance {
+// Fields stay private, every change has to go through `InstanceState`, either
+// its setters or `instances_mut`, so it knows to upload them again
+pub struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
+    scale: cgmath::Vector3<f32>,
+    // Degrees per second around `rotation_axis`, which is normalized
     rotation_speed: f32,
     rotation_axis: cgmath::Vector3<f32>,
+    tex_index: u32,
+    // Hidden instances keep their slot and animation, they're just left out of the upload
+    visible: bool,
+}
+
+// One instance in a scene file, which is a JSON list of them. Only the position
+// is required, the rest default to an unrotated, unscaled instance that doesn't spin.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct SceneInstance {
+    pub position: [f32; 3],
+    // Degrees around X, Y and Z, combined the way `cgmath::Euler` does
+    #[serde(default)]
+    pub rotation_euler: [f32; 3],
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+    // Degrees per second around `rotation_axis`
+    #[serde(default)]
+    pub rotation_speed: f32,
+    // Normalized when loaded, so it only has to point the right way
+    #[serde(default = "default_rotation_axis")]
+    pub rotation_axis: [f32; 3],
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn default_rotation_axis() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
+    // Inverse-transpose of the model matrix's upper 3x3, keeps normals
+    // perpendicular to the surface under non-uniform scale
+    normal_matrix: [[f32; 3]; 3],
+    tex_index: u32,
 }
 
 impl InstanceRaw {
@@ -42,102 +89,1086 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
 }
 
 impl Instance {
+    // World space position of the instance's center
+    pub fn position(&self) -> cgmath::Vector3<f32> {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: cgmath::Vector3<f32>) {
+        self.position = position;
+    }
+
+    pub fn rotation(&self) -> cgmath::Quaternion<f32> {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, rotation: cgmath::Quaternion<f32>) {
+        self.rotation = rotation;
+    }
+
+    pub fn scale(&self) -> cgmath::Vector3<f32> {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: cgmath::Vector3<f32>) {
+        self.scale = scale;
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    fn from_scene(entry: &SceneInstance, tex_index: u32) -> Result<Self> {
+        let values = [entry.position, entry.rotation_euler, entry.scale];
+        ensure!(
+            values.iter().flatten().all(|value| value.is_finite()),
+            "position, rotation_euler and scale have to be finite"
+        );
+        let [x, y, z] = entry.rotation_euler.map(cgmath::Deg);
+        Ok(Self {
+            position: entry.position.into(),
+            rotation: cgmath::Euler { x, y, z }.into(),
+            scale: entry.scale.into(),
+            rotation_speed: entry.rotation_speed,
+            rotation_axis: validate_rotation(entry.rotation_axis.into(), entry.rotation_speed)?,
+            tex_index,
+            visible: true,
+        })
+    }
+
+    fn to_scene(&self) -> SceneInstance {
+        let euler = cgmath::Euler::from(self.rotation.normalize());
+        SceneInstance {
+            position: self.position.into(),
+            rotation_euler: [euler.x, euler.y, euler.z].map(|angle| cgmath::Deg::from(angle).0),
+            scale: self.scale.into(),
+            rotation_speed: self.rotation_speed,
+            rotation_axis: self.rotation_axis.into(),
+        }
+    }
+
     fn to_raw(&self) -> InstanceRaw {
+        let model = Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
+        let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        // A zero scale squashes the mesh flat, any normal is as good as another then
+        let normal_matrix = linear
+            .invert()
+            .map_or(Matrix3::identity(), |inverse| inverse.transpose());
+
         InstanceRaw {
-            model: (Matrix4::from_translation(self.position) * Matrix4::from(self.rotation)).into(),
+            model: model.into(),
+            normal_matrix: normal_matrix.into(),
+            tex_index: self.tex_index,
+        }
+    }
+
+    // World space box around the instance's copy of the mesh with the local
+    // `bounds`, see `VertexState::bounds`
+    fn bounding_box(
+        &self,
+        (local_min, local_max): (cgmath::Point3<f32>, cgmath::Point3<f32>),
+    ) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+        // Each world axis extent of a transformed box is the sum of the absolute
+        // contributions from each of its local axes
+        let linear = Matrix3::from(self.rotation) * Matrix3::from_diagonal(self.scale);
+        let local_half_extent = (local_max - local_min) * 0.5;
+        let half_extent = cgmath::Vector3::new(
+            linear.x.x.abs() * local_half_extent.x
+                + linear.y.x.abs() * local_half_extent.y
+                + linear.z.x.abs() * local_half_extent.z,
+            linear.x.y.abs() * local_half_extent.x
+                + linear.y.y.abs() * local_half_extent.y
+                + linear.z.y.abs() * local_half_extent.z,
+            linear.x.z.abs() * local_half_extent.x
+                + linear.y.z.abs() * local_half_extent.y
+                + linear.z.z.abs() * local_half_extent.z,
+        );
+        let local_center = local_min.midpoint(local_max);
+        let center = cgmath::Point3::from_vec(self.position + linear * local_center.to_vec());
+        (center - half_extent, center + half_extent)
+    }
+
+    // Distance from the instance's position to the farthest corner of the scaled
+    // local `bounds`, so culling never drops a partially visible one whatever its
+    // rotation
+    fn bounding_radius(
+        &self,
+        (local_min, local_max): (cgmath::Point3<f32>, cgmath::Point3<f32>),
+    ) -> f32 {
+        let farthest = cgmath::Vector3::new(
+            local_min.x.abs().max(local_max.x.abs()),
+            local_min.y.abs().max(local_max.y.abs()),
+            local_min.z.abs().max(local_max.z.abs()),
+        );
+        farthest.mul_element_wise(self.scale).magnitude()
+    }
+
+    // Distance along the ray to the instance's local `bounds`, if they're hit
+    fn ray_intersection(
+        &self,
+        origin: cgmath::Point3<f32>,
+        direction: cgmath::Vector3<f32>,
+        (local_min, local_max): (cgmath::Point3<f32>, cgmath::Point3<f32>),
+    ) -> Option<f32> {
+        // A zero scale squashes the instance flat and would divide by zero below,
+        // there's nothing to hit
+        if !(0..3).all(|axis| self.scale[axis] != 0.0 && self.scale[axis].is_finite()) {
+            return None;
         }
+        // In the instance's local space its bounds are an axis aligned box,
+        // so move the ray there and do a slab test. Dividing out the scale
+        // keeps t in world units since the direction is scaled the same way.
+        let inverse_rotation = self.rotation.normalize().conjugate();
+        let local_origin = inverse_rotation
+            .rotate_vector(origin.to_vec() - self.position)
+            .div_element_wise(self.scale);
+        let local_direction = inverse_rotation
+            .rotate_vector(direction)
+            .div_element_wise(self.scale);
+
+        let mut t_min = 0.0_f32;
+        let mut t_max = f32::INFINITY;
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / local_direction[axis];
+            let t0 = (local_min[axis] - local_origin[axis]) * inverse_direction;
+            let t1 = (local_max[axis] - local_origin[axis]) * inverse_direction;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+
+        (t_min <= t_max).then_some(t_min)
     }
 }
 
-pub struct InstanceState {
-    pub instances: Vec<Instance>,
-    pub instance_buffer: wgpu::Buffer,
+// The smallest box containing both `(min, max)` boxes
+pub fn merge_bounds(
+    (min_a, max_a): (cgmath::Point3<f32>, cgmath::Point3<f32>),
+    (min_b, max_b): (cgmath::Point3<f32>, cgmath::Point3<f32>),
+) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+    (
+        cgmath::Point3::new(
+            min_a.x.min(min_b.x),
+            min_a.y.min(min_b.y),
+            min_a.z.min(min_b.z),
+        ),
+        cgmath::Point3::new(
+            max_a.x.max(max_b.x),
+            max_a.y.max(max_b.y),
+            max_a.z.max(max_b.z),
+        ),
+    )
 }
 
-impl InstanceState {
-    pub fn new(device: &wgpu::Device) -> Self {
-        let mut instances = Vec::new();
-        let mut rng = rand::rng();
-        
-        for z in 0..NUM_INSTANCES_PER_ROW {
-            for x in 0..NUM_INSTANCES_PER_ROW {
-                let position = cgmath::Vector3 {
-                    x: x as f32 * 2.0,
-                    y: 0.0,
-                    z: z as f32 * 2.0,
-                } - INSTANCE_DISPLACEMENT;
-
-                let rotation = if position.is_zero() {
-                    cgmath::Quaternion::from_axis_angle(
-                        cgmath::Vector3::unit_z(),
-                        cgmath::Deg(0.0),
-                    )
+// A `cols` by `rows` grid on the XZ plane, columns along X and rows along Z,
+// centered on `origin`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GridConfig {
+    pub rows: u32,
+    pub cols: u32,
+    // Distance between neighbouring instances' centers
+    pub spacing: f32,
+    pub origin: cgmath::Vector3<f32>,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            rows: 10,
+            cols: 10,
+            spacing: INSTANCE_SPACING,
+            origin: cgmath::Vector3::zero(),
+        }
+    }
+}
+
+impl GridConfig {
+    // Row by row, from -X -Z to +X +Z
+    pub fn positions(&self) -> Vec<cgmath::Vector3<f32>> {
+        let spacing = self.spacing;
+        let corner = self.origin
+            - cgmath::Vector3::new(
+                centering_offset(self.cols, spacing),
+                0.0,
+                centering_offset(self.rows, spacing),
+            );
+        (0..self.rows)
+            .flat_map(|z| (0..self.cols).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                corner + cgmath::Vector3::new(x as f32 * spacing, 0.0, z as f32 * spacing)
+            })
+            .collect()
+    }
+}
+
+// Where `InstanceState` puts its instances. Grids follow their `GridConfig`,
+// the other layouts put neighbours `INSTANCE_SPACING` apart around the origin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Layout {
+    Grid2D(GridConfig),
+    // `per_side` cubed, filling a box
+    Grid3D { per_side: u32 },
+    // `count` evenly around a ring on the XZ plane
+    Circle { count: u32 },
+    // `count` along the X axis
+    Line { count: u32 },
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Grid2D(GridConfig::default())
+    }
+}
+
+impl Layout {
+    // `spec` is the layout's name and size, e.g. `grid2d:10`, `grid3d:5`,
+    // `circle:32` or `line:20`. Grids can also be given as `grid2d:COLSxROWS`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let Some((name, size)) = spec.split_once(':') else {
+            anyhow::bail!("expected name:size");
+        };
+        let parse_size = |size: &str| -> Result<u32> {
+            let size = size.trim().parse()?;
+            ensure!(size > 0, "a layout needs at least one instance");
+            Ok(size)
+        };
+        let name = name.trim().to_ascii_lowercase();
+        if name == "grid2d" {
+            let (cols, rows) = match size.split_once('x') {
+                Some((cols, rows)) => (parse_size(cols)?, parse_size(rows)?),
+                None => (parse_size(size)?, parse_size(size)?),
+            };
+            return Ok(Layout::Grid2D(GridConfig {
+                rows,
+                cols,
+                ..GridConfig::default()
+            }));
+        }
+        let size = parse_size(size)?;
+        match name.as_str() {
+            "grid3d" => Ok(Layout::Grid3D { per_side: size }),
+            "circle" => Ok(Layout::Circle { count: size }),
+            "line" => Ok(Layout::Line { count: size }),
+            other => {
+                anyhow::bail!("unknown layout {other}, expected grid2d, grid3d, circle or line")
+            }
+        }
+    }
+
+    // The number the layout was parsed with, columns, instances per side or in total
+    pub fn size(self) -> u32 {
+        match self {
+            Layout::Grid2D(GridConfig { cols: size, .. })
+            | Layout::Grid3D { per_side: size }
+            | Layout::Circle { count: size }
+            | Layout::Line { count: size } => size,
+        }
+    }
+
+    // The same kind of layout with a different size, at least 1. A grid only
+    // changes its columns, keeping its rows and the rest of its config.
+    pub fn with_size(self, size: u32) -> Self {
+        let size = size.max(1);
+        match self {
+            Layout::Grid2D(grid) => Layout::Grid2D(GridConfig { cols: size, ..grid }),
+            Layout::Grid3D { .. } => Layout::Grid3D { per_side: size },
+            Layout::Circle { .. } => Layout::Circle { count: size },
+            Layout::Line { .. } => Layout::Line { count: size },
+        }
+    }
+
+    pub fn positions(self) -> Vec<cgmath::Vector3<f32>> {
+        match self {
+            Layout::Grid2D(grid) => grid.positions(),
+            Layout::Grid3D { per_side } => {
+                let offset = centering_offset(per_side, INSTANCE_SPACING);
+                (0..per_side)
+                    .flat_map(|y| (0..per_side).map(move |z| (y, z)))
+                    .flat_map(|(y, z)| (0..per_side).map(move |x| (x, y, z)))
+                    .map(|(x, y, z)| {
+                        cgmath::Vector3::new(
+                            x as f32 * INSTANCE_SPACING - offset,
+                            y as f32 * INSTANCE_SPACING - offset,
+                            z as f32 * INSTANCE_SPACING - offset,
+                        )
+                    })
+                    .collect()
+            }
+            Layout::Circle { count } => {
+                // Neighbours are a chord apart, a single instance sits at the center
+                let step = std::f32::consts::TAU / count as f32;
+                let radius = if count > 1 {
+                    INSTANCE_SPACING / (2.0 * (step / 2.0).sin())
                 } else {
-                    cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+                    0.0
                 };
+                (0..count)
+                    .map(|i| {
+                        let (sin, cos) = (i as f32 * step).sin_cos();
+                        cgmath::Vector3::new(cos * radius, 0.0, sin * radius)
+                    })
+                    .collect()
+            }
+            Layout::Line { count } => {
+                let offset = centering_offset(count, INSTANCE_SPACING);
+                (0..count)
+                    .map(|x| cgmath::Vector3::new(x as f32 * INSTANCE_SPACING - offset, 0.0, 0.0))
+                    .collect()
+            }
+        }
+    }
+}
 
-                // Generate random rotation axis for each instance
-                let rotation_axis = cgmath::Vector3::new(
-                    rng.random_range(-1.0..1.0),
-                    rng.random_range(-1.0..1.0),
-                    rng.random_range(-1.0..1.0),
-                ).normalize();
-
-                instances.push(Instance { 
-                    position, 
-                    rotation,
-                    rotation_speed: 20.0, // 20 degrees per frame
-                    rotation_axis,
-                });
+// How `InstanceState::new` picks the axes the instances spin around
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum SpinMode {
+    // A random axis per instance
+    #[default]
+    Random,
+    // Every instance around the same axis, so they all turn in sync
+    Uniform(cgmath::Vector3<f32>),
+}
+
+impl SpinMode {
+    // `spec` is `random`, `up` for world up, or an axis as `x,y,z`
+    pub fn parse(spec: &str) -> Result<Self> {
+        match spec.trim().to_ascii_lowercase().as_str() {
+            "random" => Ok(SpinMode::Random),
+            "up" => Ok(SpinMode::Uniform(cgmath::Vector3::unit_y())),
+            axis => {
+                let axis = parse_axis(axis).context("expected random, up or an axis")?;
+                Ok(SpinMode::Uniform(validate_rotation(axis, 0.0)?))
             }
         }
+    }
+}
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("instance buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+// An axis given as `x,y,z`
+pub fn parse_axis(spec: &str) -> Result<cgmath::Vector3<f32>> {
+    let parts = spec
+        .split(',')
+        .map(|part| part.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let [x, y, z] = parts[..] else {
+        anyhow::bail!("expected an axis as x,y,z");
+    };
+    Ok(cgmath::Vector3::new(x, y, z))
+}
+
+// Moves `count` instances `spacing` apart so the middle one, or the gap
+// between the middle two, lands on the origin
+fn centering_offset(count: u32, spacing: f32) -> f32 {
+    count.saturating_sub(1) as f32 * spacing * 0.5
+}
+
+// The instances `layout` places, spinning as `spin` says
+fn generate_instances(
+    num_textures: u32,
+    layout: Layout,
+    spin: SpinMode,
+    rng: &mut impl Rng,
+) -> Vec<Instance> {
+    let uniform_axis = match spin {
+        SpinMode::Random => None,
+        SpinMode::Uniform(axis) => Some(validate_rotation(axis, 0.0).unwrap_or_else(|e| {
+            log::warn!("{e:#}, spinning around world up");
+            cgmath::Vector3::unit_y()
+        })),
+    };
+    let mut instances = Vec::new();
+
+    for position in layout.positions() {
+        let rotation = if position.is_zero() {
+            cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
+        } else {
+            cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+        };
+
+        // Generate random rotation axis for each instance
+        let rotation_axis = uniform_axis.unwrap_or_else(|| {
+            cgmath::Vector3::new(
+                rng.random_range(-1.0..1.0),
+                rng.random_range(-1.0..1.0),
+                rng.random_range(-1.0..1.0),
+            )
+            .normalize()
         });
 
+        instances.push(Instance {
+            position,
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            rotation_speed: DEFAULT_ROTATION_SPEED,
+            rotation_axis,
+            // Cycle through the available texture layers
+            tex_index: instances.len() as u32 % num_textures.max(1),
+            visible: true,
+        });
+    }
+
+    instances
+}
+
+// Gives the first instances the generated positions and adds or drops the rest
+fn move_to_layout(instances: &mut Vec<Instance>, generated: Vec<Instance>) {
+    instances.truncate(generated.len());
+    for (instance, generated) in instances.iter_mut().zip(&generated) {
+        instance.position = generated.position;
+    }
+    let kept = instances.len();
+    instances.extend(generated.into_iter().skip(kept));
+}
+
+pub struct InstanceState {
+    instances: Vec<Instance>,
+    pub instance_buffer: wgpu::Buffer,
+    // Instances `instance_buffer` has room for, it's replaced by a bigger one
+    // when an upload doesn't fit
+    capacity: usize,
+    // Texture layers pushed instances cycle through
+    num_textures: u32,
+    // Set whenever `instances` changes so the next `update` re-uploads them
+    dirty: bool,
+    // Whether the last upload was frustum culled, and how many instances it kept
+    culled: bool,
+    num_visible: u32,
+    num_culled: u32,
+    // Uploads go through the belt so they're recorded into the frame's encoder
+    // instead of stalling in `queue.write_buffer`. `update` writes into it,
+    // `finish_upload` has to be called before that encoder is submitted and
+    // `recall_upload` after, so the staging buffers can be reused next frame.
+    staging_belt: wgpu::util::StagingBelt,
+    // Reused between updates to avoid reallocating for every upload
+    staging_data: Vec<InstanceRaw>,
+    // Only the first `active_count` instances are drawn, picked and bounded
+    active_count: usize,
+    // Multiplies every instance's rotation speed
+    pub rotation_speed_scale: f32,
+    // How the instances were generated from a layout, `None` once they no longer
+    // match one: loaded from a scene, or pushed or removed since
+    spin: Option<SpinMode>,
+    // `DrawIndexedIndirect` args for drawing the uploaded instances, rewritten by
+    // `write_indirect_args` whenever its index count or `num_visible` changes.
+    // A compute pass can write the instance count here instead.
+    #[cfg(feature = "indirect-draw")]
+    pub indirect_buffer: wgpu::Buffer,
+    #[cfg(feature = "indirect-draw")]
+    indirect_args: Option<(u32, u32)>,
+}
+
+impl InstanceState {
+    // With `SpinMode::Random`, rotation axes that differ on every run
+    pub fn new(device: &wgpu::Device, num_textures: u32, layout: Layout, spin: SpinMode) -> Self {
+        Self::with_rng(device, num_textures, layout, spin, &mut rand::rng())
+    }
+
+    // The same seed gives the same rotation axes on every run and platform, as
+    // long as the `rand` version stays the same. `StdRng` is free to change its
+    // algorithm between `rand` releases.
+    pub fn new_seeded(
+        device: &wgpu::Device,
+        seed: u64,
+        num_textures: u32,
+        layout: Layout,
+        spin: SpinMode,
+    ) -> Self {
+        Self::with_rng(
+            device,
+            num_textures,
+            layout,
+            spin,
+            &mut rand::rngs::StdRng::seed_from_u64(seed),
+        )
+    }
+
+    fn with_rng(
+        device: &wgpu::Device,
+        num_textures: u32,
+        layout: Layout,
+        spin: SpinMode,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let instances = generate_instances(num_textures, layout, spin, rng);
+        let mut state = Self::from_instances(device, instances, num_textures);
+        state.spin = Some(spin);
+        state
+    }
+
+    // Moves the instances to `layout`'s positions, keeping their rotation, spin,
+    // scale and visibility. Instances are added or removed at the end to match
+    // its size, new ones spin the way `new_seeded` would make them with `seed`.
+    // Fails when the instances didn't come from a layout, rather than losing them.
+    pub fn set_layout(&mut self, layout: Layout, seed: Option<u64>) -> Result<()> {
+        let Some(spin) = self.spin else {
+            bail!("the instances weren't generated from a layout");
+        };
+        let generated = match seed {
+            Some(seed) => generate_instances(
+                self.num_textures,
+                layout,
+                spin,
+                &mut rand::rngs::StdRng::seed_from_u64(seed),
+            ),
+            None => generate_instances(self.num_textures, layout, spin, &mut rand::rng()),
+        };
+        let all_active = self.active_count == self.instances.len();
+        move_to_layout(&mut self.instances, generated);
+        self.active_count = if all_active {
+            self.instances.len()
+        } else {
+            self.active_count.min(self.instances.len())
+        };
+        self.dirty = true;
+        Ok(())
+    }
+
+    // Instances authored in a scene file, see `SceneInstance`. The texture
+    // layers are cycled through in the file's order.
+    pub fn from_scene(
+        device: &wgpu::Device,
+        path: impl AsRef<Path>,
+        num_textures: u32,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let entries: Vec<SceneInstance> = serde_json::from_str(&json)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let instances = entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                Instance::from_scene(entry, index as u32 % num_textures.max(1))
+                    .with_context(|| format!("invalid instance {index} in {}", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::from_instances(device, instances, num_textures))
+    }
+
+    // Every instance as a scene file entry, hidden ones included, with their
+    // current rotation
+    pub fn to_scene(&self) -> Vec<SceneInstance> {
+        self.instances.iter().map(Instance::to_scene).collect()
+    }
+
+    // Writes what `from_scene` loads
+    pub fn save_scene(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.to_scene())?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    fn from_instances(device: &wgpu::Device, instances: Vec<Instance>, num_textures: u32) -> Self {
+        let instances_len = instances.len() as u32;
+        let active_count = instances.len();
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let capacity = instance_data.len().max(1);
+        let instance_buffer = create_instance_buffer(device, &instance_data, capacity);
+
         Self {
             instances,
             instance_buffer,
+            capacity,
+            num_textures,
+            dirty: false,
+            culled: false,
+            num_visible: instances_len,
+            num_culled: 0,
+            // One chunk fits a whole upload
+            staging_belt: wgpu::util::StagingBelt::new(std::mem::size_of_val(
+                instance_data.as_slice(),
+            ) as wgpu::BufferAddress),
+            staging_data: instance_data,
+            active_count,
+            rotation_speed_scale: 1.0,
+            spin: None,
+            #[cfg(feature = "indirect-draw")]
+            indirect_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("instance indirect buffer"),
+                size: std::mem::size_of::<[u32; 5]>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            #[cfg(feature = "indirect-draw")]
+            indirect_args: None,
         }
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
+    // World positions of every instance, in index order
+    pub fn positions(&self) -> impl Iterator<Item = cgmath::Vector3<f32>> + '_ {
+        self.instances.iter().map(Instance::position)
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    // The instances are uploaded again on the next `update`, whether or not
+    // anything was actually changed
+    pub fn instances_mut(&mut self) -> &mut [Instance] {
+        self.dirty = true;
+        &mut self.instances
+    }
+
+    pub fn get_transform(
+        &self,
+        index: usize,
+    ) -> Option<(cgmath::Vector3<f32>, cgmath::Quaternion<f32>)> {
+        self.instances
+            .get(index)
+            .map(|instance| (instance.position, instance.rotation))
+    }
+
+    pub fn set_transform(
+        &mut self,
+        index: usize,
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+    ) -> Result<()> {
+        let count = self.instances.len();
+        ensure!(
+            index < count,
+            "instance index {index} out of range, there are {count} instances"
+        );
+
+        let instance = &mut self.instances[index];
+        instance.position = position;
+        instance.rotation = rotation;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn set_rotation(
+        &mut self,
+        index: usize,
+        axis: cgmath::Vector3<f32>,
+        speed_deg_per_sec: f32,
+    ) -> Result<()> {
+        let count = self.instances.len();
+        ensure!(
+            index < count,
+            "instance index {index} out of range, there are {count} instances"
+        );
+        let axis = validate_rotation(axis, speed_deg_per_sec)?;
+
+        let instance = &mut self.instances[index];
+        instance.rotation_axis = axis;
+        instance.rotation_speed = speed_deg_per_sec;
+        Ok(())
+    }
+
+    // Spins every instance the same way
+    pub fn set_all_rotation(
+        &mut self,
+        axis: cgmath::Vector3<f32>,
+        speed_deg_per_sec: f32,
+    ) -> Result<()> {
+        let axis = validate_rotation(axis, speed_deg_per_sec)?;
+        for instance in &mut self.instances {
+            instance.rotation_axis = axis;
+            instance.rotation_speed = speed_deg_per_sec;
+        }
+        Ok(())
+    }
+
+    pub fn get_scale(&self, index: usize) -> Option<cgmath::Vector3<f32>> {
+        self.instances.get(index).map(|instance| instance.scale)
+    }
+
+    // Scale along the instance's local axes, applied before its rotation
+    pub fn set_scale(&mut self, index: usize, scale: cgmath::Vector3<f32>) -> Result<()> {
+        let count = self.instances.len();
+        ensure!(
+            index < count,
+            "instance index {index} out of range, there are {count} instances"
+        );
+
+        self.instances[index].scale = scale;
+        self.dirty = true;
+        Ok(())
+    }
+
+    // Hidden instances are skipped when packing the upload, so showing and hiding
+    // them only changes the draw count and never reallocates the buffer
+    pub fn set_visible(&mut self, index: usize, visible: bool) -> Result<()> {
+        let count = self.instances.len();
+        ensure!(
+            index < count,
+            "instance index {index} out of range, there are {count} instances"
+        );
+
+        let instance = &mut self.instances[index];
+        self.dirty |= instance.visible != visible;
+        instance.visible = visible;
+        Ok(())
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.active_count
+    }
+
+    // Hides the instances past `count`, which is clamped to the number of instances
+    pub fn set_active_count(&mut self, count: usize) {
+        self.active_count = count.min(self.instances.len());
+        self.dirty = true;
+    }
+
+    // Adds a non-spinning instance and returns its index. It goes right after the
+    // active instances so it's drawn, shifting any hidden ones up by one. The
+    // buffer grows on the next `update` if it's full.
+    pub fn push_instance(
+        &mut self,
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+    ) -> usize {
+        let index = self.active_count;
+        self.instances.insert(
+            index,
+            Instance {
+                position,
+                rotation,
+                scale,
+                rotation_speed: 0.0,
+                rotation_axis: cgmath::Vector3::unit_y(),
+                tex_index: index as u32 % self.num_textures.max(1),
+                visible: true,
+            },
+        );
+        self.active_count += 1;
+        self.spin = None;
+        self.dirty = true;
+        index
+    }
+
+    // Instances after `index` move down by one. The buffer keeps its size.
+    pub fn remove_instance(&mut self, index: usize) -> Result<Instance> {
+        let count = self.instances.len();
+        ensure!(
+            index < count,
+            "instance index {index} out of range, there are {count} instances"
+        );
+
+        if index < self.active_count {
+            self.active_count -= 1;
+        }
+        self.spin = None;
+        self.dirty = true;
+        Ok(self.instances.remove(index))
+    }
+
+    // Active and not hidden with `set_visible`, by their index
+    fn shown_instances(&self) -> impl Iterator<Item = (usize, &Instance)> {
+        self.instances[..self.active_count]
+            .iter()
+            .enumerate()
+            .filter(|(_, instance)| instance.visible)
+    }
+
+    // Only the shown instances are uploaded, packed at the start of the buffer,
+    // and with a frustum only the ones inside it. `num_visible` is how many to draw. They're
+    // culled by the `local_bounds` of the geometry they're drawn with.
+    // `dt` is the time since the last update in seconds
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        frustum: Option<&Frustum>,
+        local_bounds: (cgmath::Point3<f32>, cgmath::Point3<f32>),
+        dt: f32,
+    ) {
         // Update rotation for each instance
         for instance in &mut self.instances {
+            let speed = instance.rotation_speed * self.rotation_speed_scale;
             let rotation_delta = cgmath::Quaternion::from_axis_angle(
                 instance.rotation_axis,
-                cgmath::Deg(instance.rotation_speed)
+                cgmath::Deg(speed * dt),
             );
             instance.rotation = rotation_delta * instance.rotation;
+            self.dirty |= speed != 0.0;
+        }
+
+        // The visible set depends on the camera as well, so it's rebuilt every frame
+        // while culling, and once more after culling is switched off
+        if !self.dirty && !self.culled && frustum.is_none() {
+            return;
         }
+        self.dirty = false;
+        self.culled = frustum.is_some();
 
         // Update the buffer with new instance data
-        let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&instance_data),
+        self.staging_data.clear();
+        let mut num_shown = 0;
+        self.staging_data.extend(
+            self.instances[..self.active_count]
+                .iter()
+                .filter(|instance| instance.visible)
+                .inspect(|_| num_shown += 1)
+                .filter(|instance| {
+                    frustum.is_none_or(|frustum| {
+                        frustum.intersects_sphere(
+                            instance.position,
+                            instance.bounding_radius(local_bounds),
+                        )
+                    })
+                })
+                .map(Instance::to_raw),
         );
+        self.num_visible = self.staging_data.len() as u32;
+        self.num_culled = num_shown - self.num_visible;
+
+        if self.staging_data.len() > self.capacity {
+            // Doubling keeps a spawner from reallocating every frame. The new
+            // buffer is created with the data, there's nothing left to copy.
+            self.capacity = self.staging_data.len().max(self.capacity * 2);
+            self.instance_buffer =
+                create_instance_buffer(device, &self.staging_data, self.capacity);
+            return;
+        }
+
+        let data: &[u8] = bytemuck::cast_slice(&self.staging_data);
+        // Nothing to copy when everything was culled
+        let Some(size) = NonZeroU64::new(data.len() as u64) else {
+            return;
+        };
+        self.staging_belt
+            .write_buffer(encoder, &self.instance_buffer, 0, size, device)
+            .copy_from_slice(data);
+    }
+
+    // Writes the args for drawing `index_count` indices of every uploaded instance
+    // into `indirect_buffer`, through the same staging belt as `update`
+    #[cfg(feature = "indirect-draw")]
+    pub fn write_indirect_args(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        index_count: u32,
+    ) {
+        let args = (index_count, self.num_visible);
+        if self.indirect_args == Some(args) {
+            return;
+        }
+        self.indirect_args = Some(args);
+        let args = wgpu::util::DrawIndexedIndirect {
+            vertex_count: index_count,
+            instance_count: self.num_visible,
+            base_index: 0,
+            vertex_offset: 0,
+            base_instance: 0,
+        };
+        let data = args.as_bytes();
+        self.staging_belt
+            .write_buffer(
+                encoder,
+                &self.indirect_buffer,
+                0,
+                NonZeroU64::new(data.len() as u64).unwrap(),
+                device,
+            )
+            .copy_from_slice(data);
+    }
+
+    // Closes the staging buffers written by `update`, call before submitting its encoder
+    pub fn finish_upload(&mut self) {
+        self.staging_belt.finish();
+    }
+
+    // Reclaims the staging buffers once their copies are submitted
+    pub fn recall_upload(&mut self) {
+        self.staging_belt.recall();
+    }
+
+    // Index of the nearest instance whose `local_bounds` are hit by the ray
+    pub fn pick(
+        &self,
+        origin: cgmath::Point3<f32>,
+        direction: cgmath::Vector3<f32>,
+        local_bounds: (cgmath::Point3<f32>, cgmath::Point3<f32>),
+    ) -> Option<(usize, f32)> {
+        self.shown_instances()
+            .filter_map(|(index, instance)| {
+                instance
+                    .ray_intersection(origin, direction, local_bounds)
+                    .map(|t| (index, t))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    // World space box around every instance's `local_bounds`, taking rotation and
+    // scale into account. None when there are no instances.
+    pub fn bounding_box(
+        &self,
+        local_bounds: (cgmath::Point3<f32>, cgmath::Point3<f32>),
+    ) -> Option<(cgmath::Point3<f32>, cgmath::Point3<f32>)> {
+        self.shown_instances()
+            .map(|(_, instance)| instance.bounding_box(local_bounds))
+            .reduce(merge_bounds)
     }
 
     pub fn num_instances(&self) -> u32 {
         self.instances.len() as u32
     }
+
+    // Number of instances uploaded by the last `update`, this is the draw count
+    pub fn num_visible(&self) -> u32 {
+        self.num_visible
+    }
+
+    // Shown instances the last `update` left out for being outside the frustum
+    pub fn num_culled(&self) -> u32 {
+        self.num_culled
+    }
+}
+
+// Holds `capacity` instances, `data` first and zeroes after it
+fn create_instance_buffer(
+    device: &wgpu::Device,
+    data: &[InstanceRaw],
+    capacity: usize,
+) -> wgpu::Buffer {
+    let mut contents = data.to_vec();
+    contents.resize(capacity, bytemuck::Zeroable::zeroed());
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("instance buffer"),
+        contents: bytemuck::cast_slice(&contents),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
 }
 
-const NUM_INSTANCES_PER_ROW: u32 = 10;
-const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
-    NUM_INSTANCES_PER_ROW as f32 * 2.0 * 0.5,
-    0.0,
-    NUM_INSTANCES_PER_ROW as f32 * 2.0 * 0.5,
-);
+// Normalizes the axis, rejecting ones that can't be
+fn validate_rotation(
+    axis: cgmath::Vector3<f32>,
+    speed_deg_per_sec: f32,
+) -> Result<cgmath::Vector3<f32>> {
+    ensure!(
+        axis.magnitude2() > f32::EPSILON && axis.magnitude2().is_finite(),
+        "rotation axis {axis:?} can't be normalized"
+    );
+    ensure!(
+        speed_deg_per_sec.is_finite(),
+        "rotation speed {speed_deg_per_sec} isn't finite"
+    );
+    Ok(axis.normalize())
+}
+
+// A brisk spin, 20 degrees per frame at 60 FPS
+const DEFAULT_ROTATION_SPEED: f32 = 20.0 * 60.0;
+// Distance between neighbouring instances' centers, unless a `GridConfig` says otherwise
+const INSTANCE_SPACING: f32 = 2.0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_by_one_grid_is_at_its_origin() {
+        let origin = cgmath::Vector3::new(3.0, -1.0, 7.5);
+        let grid = GridConfig {
+            rows: 1,
+            cols: 1,
+            spacing: 4.0,
+            origin,
+        };
+        assert_eq!(grid.positions(), vec![origin]);
+    }
+
+    #[test]
+    fn grid_is_centered_on_its_origin() {
+        let origin = cgmath::Vector3::new(1.0, 2.0, 3.0);
+        let grid = GridConfig {
+            rows: 3,
+            cols: 4,
+            spacing: 1.5,
+            origin,
+        };
+        let positions = grid.positions();
+        assert_eq!(positions.len(), 12);
+        let center = positions.iter().sum::<cgmath::Vector3<f32>>() / positions.len() as f32;
+        assert!((center - origin).magnitude() < 1e-5);
+        // Columns along X, one spacing apart
+        let step = cgmath::Vector3::new(1.5, 0.0, 0.0);
+        assert_eq!(positions[1] - positions[0], step);
+        let row = cgmath::Vector3::new(0.0, 0.0, 1.5);
+        assert_eq!(positions[4] - positions[0], row);
+    }
+
+    #[test]
+    fn flat_instances_are_not_hit() {
+        let entry: SceneInstance =
+            serde_json::from_str(r#"{ "position": [0, 0, 0], "scale": [1, 0, 1] }"#).unwrap();
+        let instance = Instance::from_scene(&entry, 0).unwrap();
+        let bounds = (
+            cgmath::Point3::new(-1.0, -1.0, -1.0),
+            cgmath::Point3::new(1.0, 1.0, 1.0),
+        );
+        let origin = cgmath::Point3::new(0.0, 5.0, 0.0);
+        let down = -cgmath::Vector3::unit_y();
+        assert_eq!(instance.ray_intersection(origin, down, bounds), None);
+    }
+
+    #[test]
+    fn relayout_keeps_the_instances_it_moves() {
+        let rng = &mut rand::rngs::StdRng::seed_from_u64(0);
+        let mut instances = generate_instances(1, Layout::Line { count: 3 }, SpinMode::Random, rng);
+        instances[0].scale = cgmath::Vector3::new(2.0, 2.0, 2.0);
+        instances[1].visible = false;
+        let axes = instances
+            .iter()
+            .map(|i| i.rotation_axis)
+            .collect::<Vec<_>>();
+
+        let bigger = generate_instances(1, Layout::Line { count: 5 }, SpinMode::Random, rng);
+        let positions = bigger.iter().map(|i| i.position).collect::<Vec<_>>();
+        move_to_layout(&mut instances, bigger);
+        assert_eq!(instances.len(), 5);
+        assert_eq!(
+            instances.iter().map(|i| i.position).collect::<Vec<_>>(),
+            positions
+        );
+        assert_eq!(instances[0].scale, cgmath::Vector3::new(2.0, 2.0, 2.0));
+        assert!(!instances[1].visible);
+        assert_eq!(instances[2].rotation_axis, axes[2]);
+
+        let smaller = generate_instances(1, Layout::Line { count: 2 }, SpinMode::Random, rng);
+        move_to_layout(&mut instances, smaller);
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].scale, cgmath::Vector3::new(2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn growing_after_shrinking_restores_the_layout() {
+        for layout in ["grid2d:20x3", "grid2d:4", "grid3d:5", "circle:32", "line:2"] {
+            let layout = Layout::parse(layout).unwrap();
+            let shrunk = layout.with_size(layout.size() - 1);
+            assert_eq!(shrunk.with_size(shrunk.size() + 1), layout);
+        }
+    }
+}
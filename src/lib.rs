@@ -1,133 +1,1604 @@
 use std::borrow::Cow;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
+use app_context::{AppContext, UpdateFn};
 use instance::InstanceState;
 use log::trace;
+use mesh::Mesh;
+use stencil::{StencilConfig, StencilMask};
 
 use texture::Texture;
 use wgpu::TextureFormat;
 use wgpu::{Adapter, Device, Instance, PipelineLayout, Queue, RenderPipeline, ShaderModule};
 
-use winit::platform::run_return::EventLoopExtRunReturn;
 use winit::{
-    event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopWindowTarget},
+    application::ApplicationHandler,
+    dpi::{LogicalSize, PhysicalPosition},
+    event::{DeviceEvent, DeviceId, ElementState, KeyEvent, MouseButton, StartCause, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+    keyboard::{Key, ModifiersState, NamedKey},
+    window::{CursorGrabMode, Fullscreen, WindowId},
 };
 
+pub mod app_context;
+mod axis;
+pub mod bind_groups;
 mod camera;
-mod data;
-mod instance;
-mod texture;
+mod compressed;
+pub mod data;
+mod debug_draw;
+mod depth_vis;
+pub mod embed;
+mod frame_pacing;
+mod globals;
+#[cfg(feature = "gpu-timing")]
+mod gpu_timer;
+#[cfg(feature = "gui")]
+mod gui;
+pub mod headless;
+#[cfg(all(debug_assertions, not(target_os = "android")))]
+mod hot_reload;
+pub mod instance;
+pub mod light;
+mod mesh;
+mod postprocess;
+mod shadow;
+pub mod skybox;
+mod stats;
+mod stencil;
+pub mod texture;
+mod wireframe_overlay;
+
+// Reachable through `AppContext::camera_mut`
+pub use camera::{Camera, FovAxis, Viewport};
+// Reachable through `AppContext::debug_draw`
+pub use debug_draw::DebugDraw;
+pub use stats::FrameStats;
 
 struct RenderState {
     device: Device,
     queue: Queue,
-    _shader: ShaderModule,
+    shader: ShaderModule,
     target_format: TextureFormat,
-    _pipeline_layout: PipelineLayout,
+    // Samples per pixel in the scene pass, 1 without multisampling. Its pipelines
+    // and its color and depth attachments are all created with this, a mismatch
+    // fails validation.
+    sample_count: u32,
+    // Shared by every scene pipeline, see `bind_groups` for its groups
+    pipeline_layout: PipelineLayout,
+    pipeline_config: PipelineConfig,
     render_pipeline: RenderPipeline,
+    // Only present when the adapter supports `POLYGON_MODE_LINE`
+    wireframe_pipeline: Option<RenderPipeline>,
+    // Draws `wireframe_overlay` over the filled meshes, also needs `POLYGON_MODE_LINE`
+    wireframe_overlay_pipeline: Option<RenderPipeline>,
+    wireframe_overlay: wireframe_overlay::WireframeOverlay,
+    // Writes only depth, used ahead of the main pass when `depth_prepass` is set.
+    // None without a depth test.
+    depth_prepass_pipeline: Option<RenderPipeline>,
+    // Draws the scene's depth into `shadow`'s map from the directional light
+    shadow_pipeline: RenderPipeline,
+    shadow: shadow::ShadowState,
     texture_state: texture::TextureData,
+    // Used for every texture loaded after startup too
+    filter_preset: texture::FilterPreset,
+    // Decoding in the background, swapped in for `texture_state` once ready
+    pending_texture: Option<texture::PendingTexture>,
     camera_state: camera::CameraState,
+    light_state: light::LightState,
+    user_bind_groups: bind_groups::UserBindGroups,
+    depth_vis: depth_vis::DepthVisState,
+    skybox: skybox::SkyboxState,
+    // Draws the demo shape the scene gets masked to while `stencil` is set
+    stencil_mask: StencilMask,
+    axis_gizmo: axis::AxisGizmo,
+    debug_draw: DebugDraw,
+    post_process: postprocess::PostProcessState,
+    // From `TONE_MAP`, the post process picks one for the target format when None
+    tone_map: Option<postprocess::ToneMap>,
+    #[cfg(all(debug_assertions, not(target_os = "android")))]
+    shader_watcher: Option<hot_reload::ShaderWatcher>,
+    #[cfg(feature = "gpu-timing")]
+    gpu_timer: Option<gpu_timer::GpuTimer>,
+    // Whether meshes are drawn with `Mesh::draw_indirect`, false when the adapter
+    // can't execute indirect draws
+    #[cfg(feature = "indirect-draw")]
+    indirect_draw: bool,
+    // Created once there's a window to take input from
+    #[cfg(feature = "gui")]
+    gui: Option<gui::GuiState>,
+    // Chosen from the surface's supported modes by `choose_present_mode`
+    present_mode: wgpu::PresentMode,
+    // Waited on before each frame, see `FramesInFlight`
+    frames_in_flight: frame_pacing::FramesInFlight,
+    // Chosen from the surface's supported modes by `choose_alpha_mode`, see `set_alpha_mode`
+    alpha_mode: wgpu::CompositeAlphaMode,
+    // Size the surface was last configured with, the size of every frame
+    surface_size: winit::dpi::PhysicalSize<u32>,
+    // Size dependent targets, recreated only when the surface size changes
+    render_targets: Option<RenderTargets>,
+    // Draw the linearized depth buffer instead of the shaded scene
+    show_depth: bool,
+    wireframe: bool,
+    // Only upload and draw the instances inside the camera's frustum
+    frustum_culling: bool,
+    // What the last frame drew, see `FrameStats`
+    frame_stats: FrameStats,
+    // Set from the device's error handler, checked before every frame
+    device_lost: Arc<AtomicBool>,
+    // Blends using the texture's alpha. Instances aren't sorted, so overlapping
+    // transparent cubes only composite correctly when drawn back-to-front.
+    alpha_blending: bool,
+    // Lay down depth for all instances first, so the main pass shades each pixel
+    // once. Skipped with alpha blending or an alpha cutoff, where discarded texels
+    // must not write depth, and in wireframe mode, where lines don't match the filled depth.
+    depth_prepass: bool,
+    // Off for flat 2D scenes, which are drawn in order with no depth attachment
+    // at all. There's no depth pre-pass, depth visualization or stencil then.
+    depth_test: bool,
+    depth_mode: DepthMode,
+    // Stencil test of the scene's meshes, None leaves the depth buffer without
+    // a stencil aspect
+    stencil: Option<StencilConfig>,
+    // Shows wherever neither the skybox nor a mesh covers the frame
+    clear_color: wgpu::Color,
+    // See `SceneLoadOps`
+    scene_load_ops: SceneLoadOps,
+    // When instances were last animated
+    last_update: Option<Instant>,
+    // Animates every frame by this much instead of the real time passed, so
+    // headless runs draw the same frames every time
+    fixed_frame_delta: Option<Duration>,
+}
+
+// The scene is drawn into `color` rather than straight to the swapchain, so post
+// processing can sample it afterwards
+struct RenderTargets {
+    size: winit::dpi::PhysicalSize<u32>,
+    color: Texture,
+    // The scene pass draws here instead and resolves into `color`, only while
+    // multisampling
+    multisampled_color: Option<Texture>,
+    // None without a depth test
+    depth: Option<Texture>,
+}
+
+impl RenderTargets {
+    // The scene pass's color attachment and the view it resolves into
+    fn scene_color(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        match &self.multisampled_color {
+            Some(multisampled) => (&multisampled.view, Some(&self.color.view)),
+            None => (&self.color.view, None),
+        }
+    }
+}
+
+// Whether the scene pass starts an attachment over or from what the last frame
+// left in it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AttachmentLoad {
+    #[default]
+    Clear,
+    Load,
+}
+
+impl AttachmentLoad {
+    fn load_op<V>(self, clear_value: V) -> wgpu::LoadOp<V> {
+        match self {
+            AttachmentLoad::Clear => wgpu::LoadOp::Clear(clear_value),
+            AttachmentLoad::Load => wgpu::LoadOp::Load,
+        }
+    }
+}
+
+// Loading the color lets effects build up over frames, like trails or
+// feedback. It depends on the scene being drawn into `RenderTargets`, offscreen
+// textures that are kept from frame to frame and post processed onto the
+// surface. A surface texture's contents are undefined once it was presented on
+// many backends, so drawing straight into it couldn't load anything. The
+// targets are recreated, transparent black, when the window is resized or the
+// depth buffer's format changes. The skybox covers whatever was loaded, and
+// the stencil is cleared either way.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SceneLoadOps {
+    pub color: AttachmentLoad,
+    pub depth: AttachmentLoad,
+}
+
+impl SceneLoadOps {
+    // The attachments to load, e.g. `color` or `color,depth`, `clear` for none
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut load_ops = Self::default();
+        for attachment in spec.split(',') {
+            match attachment.trim().to_ascii_lowercase().as_str() {
+                "color" => load_ops.color = AttachmentLoad::Load,
+                "depth" => load_ops.depth = AttachmentLoad::Load,
+                "clear" | "" => {}
+                other => anyhow::bail!("unknown attachment {other}, expected color or depth"),
+            }
+        }
+        Ok(load_ops)
+    }
+}
+
+// Entry points of the scene shader, so a custom shader doesn't have to follow
+// the names used in `shader.wgsl`, and how its triangles are culled
+#[derive(Clone, Debug)]
+struct PipelineConfig {
+    vertex_entry_point: String,
+    fragment_entry_point: String,
+    // Used instead of `fragment_entry_point` when alpha blending
+    blended_fragment_entry_point: String,
+    // Colors the lines of the wireframe overlay
+    wireframe_overlay_fragment_entry_point: String,
+    // Culling relies on consistent winding, the cube's faces are all
+    // counter-clockwise from outside. Instances with a negative scale are
+    // mirrored, which flips their winding and culls their front faces instead.
+    cull_mode: Option<wgpu::Face>,
+    front_face: wgpu::FrontFace,
+    // How the meshes' indices are assembled, see `RenderState::set_topology`
+    topology: wgpu::PrimitiveTopology,
+    // Set exactly when `topology` is a strip, to the meshes' index format
+    strip_index_format: Option<wgpu::IndexFormat>,
+}
+
+impl PipelineConfig {
+    fn primitive_state(&self, polygon_mode: wgpu::PolygonMode) -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState {
+            topology: self.topology,
+            strip_index_format: self.strip_index_format,
+            front_face: self.front_face,
+            cull_mode: self.cull_mode,
+            polygon_mode,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            vertex_entry_point: "vs_main".to_string(),
+            fragment_entry_point: "fs_main".to_string(),
+            blended_fragment_entry_point: "fs_blended".to_string(),
+            wireframe_overlay_fragment_entry_point: "fs_wireframe_overlay".to_string(),
+            cull_mode: Some(wgpu::Face::Back),
+            front_face: wgpu::FrontFace::Ccw,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    config: &PipelineConfig,
+    target_format: TextureFormat,
+    sample_count: u32,
+    polygon_mode: wgpu::PolygonMode,
+    alpha_blending: bool,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+) -> RenderPipeline {
+    let (fragment_entry_point, blend) = if alpha_blending {
+        (
+            &config.blended_fragment_entry_point,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        )
+    } else {
+        (&config.fragment_entry_point, None)
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: &config.vertex_entry_point,
+            buffers: &mesh::buffer_layouts(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: config.primitive_state(polygon_mode),
+        depth_stencil,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+// Standard depth stores 0 at the near plane and 1 at the far plane. Since a
+// perspective projection already crowds most of the range close to the near
+// plane, the float format's extra precision near 0 is wasted there and distant
+// surfaces z-fight. Reverse-Z stores 1 at the near plane and 0 at the far plane,
+// so the two roughly cancel out and precision stays even over the whole range.
+// The cost is remembering that "closer" means "greater" everywhere depth is used.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DepthMode {
+    Standard,
+    ReverseZ,
+}
+
+impl DepthMode {
+    // The value meaning "nothing drawn yet", the far plane
+    fn clear_value(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+
+    fn compare(self) -> wgpu::CompareFunction {
+        match self {
+            DepthMode::Standard => wgpu::CompareFunction::Less,
+            DepthMode::ReverseZ => wgpu::CompareFunction::Greater,
+        }
+    }
+
+    fn depth_stencil_state(
+        self,
+        format: TextureFormat,
+        after_depth_prepass: bool,
+    ) -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format,
+            // The pre-pass already stored the nearest depth of every pixel, only the
+            // fragments that produced it pass. This relies on both pipelines
+            // computing bit identical positions, hence `@invariant` in the shader.
+            depth_write_enabled: !after_depth_prepass,
+            depth_compare: if after_depth_prepass {
+                wgpu::CompareFunction::Equal
+            } else {
+                self.compare()
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }
+    }
+}
+
+// The scene shader's bind group layouts, in group order
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline_layout(
+    device: &Device,
+    texture_state: &texture::TextureData,
+    camera_state: &camera::CameraState,
+    light_state: &light::LightState,
+    wireframe_overlay: &wireframe_overlay::WireframeOverlay,
+    user_bind_groups: &bind_groups::UserBindGroups,
+) -> PipelineLayout {
+    let bind_group_layouts = [
+        &texture_state.bind_group_layout,
+        &camera_state.bind_group_layout,
+        &light_state.bind_group_layout,
+        &wireframe_overlay.bind_group_layout,
+    ]
+    .into_iter()
+    .chain(user_bind_groups.layouts())
+    .collect::<Vec<_>>();
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &bind_group_layouts,
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_depth_prepass_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    config: &PipelineConfig,
+    sample_count: u32,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+) -> Option<RenderPipeline> {
+    Some(create_depth_only_pipeline(
+        device,
+        Some("depth prepass pipeline"),
+        layout,
+        shader,
+        config,
+        sample_count,
+        depth_stencil?,
+    ))
+}
+
+// Depth only, no fragment stage and so no texture sampling or lighting
+fn create_depth_only_pipeline(
+    device: &Device,
+    label: Option<&str>,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    config: &PipelineConfig,
+    sample_count: u32,
+    depth_stencil: wgpu::DepthStencilState,
+) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: &config.vertex_entry_point,
+            buffers: &mesh::buffer_layouts(),
+        },
+        fragment: None,
+        primitive: config.primitive_state(wgpu::PolygonMode::Fill),
+        depth_stencil: Some(depth_stencil),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
 }
 
 impl RenderState {
-    fn update_uniforms(&mut self, aspect_ratio: f32, instance_state: &mut InstanceState) {
-        // Update instance rotations first
-        instance_state.update(&self.queue);
-        
-        // Update camera uniform buffer
-        self.camera_state.camera.update_aspect_ratio(aspect_ratio);
-        self.camera_state.update();
-        self.queue.write_buffer(
-            &self.camera_state.buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_state.uniform]),
+    // Last measured GPU time of a frame, None until the first measurement
+    // arrives or when the adapter can't do timestamp queries
+    #[cfg(feature = "gpu-timing")]
+    fn gpu_frame_time_ms(&self) -> Option<f32> {
+        self.gpu_timer.as_ref().and_then(|timer| timer.last_frame_ms())
+    }
+
+    fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    // The pre-pass would also lay down depth outside a stencil mask, hiding the
+    // skybox there
+    fn uses_depth_prepass(&self) -> bool {
+        self.depth_prepass
+            && self.depth_test
+            && !self.alpha_blending
+            && self.camera_state.alpha_cutoff == 0.0
+            && self.stencil.is_none()
+    }
+
+    // None when there's no depth attachment
+    fn depth_format(&self) -> Option<TextureFormat> {
+        if !self.depth_test {
+            None
+        } else if self.stencil.is_some() {
+            Some(stencil::DEPTH_STENCIL_FORMAT)
+        } else {
+            Some(TextureFormat::Depth32Float)
+        }
+    }
+
+    // Every pipeline drawn in the scene pass has to agree with it on whether
+    // there's a depth attachment
+    fn scene_depth_stencil_state(
+        &self,
+        after_depth_prepass: bool,
+    ) -> Option<wgpu::DepthStencilState> {
+        let format = self.depth_format()?;
+        Some(wgpu::DepthStencilState {
+            stencil: self
+                .stencil
+                .map_or_else(wgpu::StencilState::default, |stencil| stencil.state()),
+            ..self
+                .depth_mode
+                .depth_stencil_state(format, after_depth_prepass)
+        })
+    }
+
+    fn depth_prepass_depth_stencil_state(&self) -> Option<wgpu::DepthStencilState> {
+        let format = self.depth_format()?;
+        Some(self.depth_mode.depth_stencil_state(format, false))
+    }
+
+    // The fill pipeline, plus the wireframe one when the adapter supports it
+    fn create_scene_pipelines(
+        &self,
+        shader: &ShaderModule,
+    ) -> (RenderPipeline, Option<RenderPipeline>) {
+        let render_pipeline = create_render_pipeline(
+            &self.device,
+            &self.pipeline_layout,
+            shader,
+            &self.pipeline_config,
+            self.target_format,
+            self.sample_count,
+            wgpu::PolygonMode::Fill,
+            self.alpha_blending,
+            self.scene_depth_stencil_state(self.uses_depth_prepass()),
+        );
+        let wireframe_pipeline = self.wireframe_pipeline.as_ref().map(|_| {
+            create_render_pipeline(
+                &self.device,
+                &self.pipeline_layout,
+                shader,
+                &self.pipeline_config,
+                self.target_format,
+                self.sample_count,
+                wgpu::PolygonMode::Line,
+                self.alpha_blending,
+                self.scene_depth_stencil_state(false),
+            )
+        });
+        (render_pipeline, wireframe_pipeline)
+    }
+
+    // Drawn right after the filled meshes, so it has to match their depth test
+    fn create_wireframe_overlay_pipeline(&self, shader: &ShaderModule) -> Option<RenderPipeline> {
+        self.wireframe_overlay_pipeline.as_ref().map(|_| {
+            wireframe_overlay::create_pipeline(
+                &self.device,
+                &self.pipeline_layout,
+                shader,
+                &self.pipeline_config,
+                self.target_format,
+                self.sample_count,
+                self.scene_depth_stencil_state(false).map(|scene| {
+                    self.wireframe_overlay
+                        .depth_stencil_state(scene, self.depth_mode)
+                }),
+            )
+        })
+    }
+
+    fn set_wireframe_overlay_bias(&mut self, depth_bias: wgpu::DepthBiasState) {
+        self.wireframe_overlay.set_depth_bias(depth_bias);
+        self.wireframe_overlay_pipeline = self.create_wireframe_overlay_pipeline(&self.shader);
+    }
+
+    // Anything but Opaque lets the window behind show through wherever nothing
+    // is drawn, so the background is cleared to transparent
+    fn set_alpha_mode(&mut self, alpha_mode: wgpu::CompositeAlphaMode) {
+        self.alpha_mode = alpha_mode;
+        self.camera_state.premultiply_alpha = alpha_mode == wgpu::CompositeAlphaMode::PreMultiplied;
+        if alpha_mode != wgpu::CompositeAlphaMode::Opaque {
+            self.clear_color = wgpu::Color::TRANSPARENT;
+        }
+    }
+
+    fn set_alpha_blending(&mut self, enabled: bool) {
+        self.alpha_blending = enabled;
+        (self.render_pipeline, self.wireframe_pipeline) = self.create_scene_pipelines(&self.shader);
+    }
+
+    fn set_depth_prepass(&mut self, enabled: bool) {
+        self.depth_prepass = enabled;
+        (self.render_pipeline, self.wireframe_pipeline) = self.create_scene_pipelines(&self.shader);
+    }
+
+    fn set_depth_mode(&mut self, depth_mode: DepthMode) {
+        self.depth_mode = depth_mode;
+        self.camera_state.reverse_z = depth_mode == DepthMode::ReverseZ;
+        self.depth_vis.reverse_z = depth_mode == DepthMode::ReverseZ;
+        self.rebuild_pipelines();
+    }
+
+    // Texels with less alpha than `cutoff` are discarded, 0 disables the test
+    fn set_alpha_cutoff(&mut self, cutoff: f32) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&cutoff),
+            "alpha cutoff {cutoff} has to be between 0 and 1"
         );
+        self.camera_state.alpha_cutoff = cutoff;
+        // Toggles the depth pre-pass, which the scene pipelines depend on
+        (self.render_pipeline, self.wireframe_pipeline) = self.create_scene_pipelines(&self.shader);
+        Ok(())
+    }
+
+    // Multiplies the shaded color of the meshes, applied before `tone_map`
+    fn set_exposure(&mut self, exposure: f32) -> anyhow::Result<()> {
+        check_exposure(exposure)?;
+        self.camera_state.exposure = exposure;
+        Ok(())
+    }
+
+    fn set_cull_mode(&mut self, cull_mode: Option<wgpu::Face>) {
+        self.pipeline_config.cull_mode = cull_mode;
+        self.rebuild_pipelines();
+    }
+
+    // Strips restart at the maximum index of `strip_index_format`, which has to
+    // be the index format of every mesh drawn. List topologies take None.
+    fn set_topology(
+        &mut self,
+        topology: wgpu::PrimitiveTopology,
+        strip_index_format: Option<wgpu::IndexFormat>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            topology.is_strip() == strip_index_format.is_some(),
+            "{topology:?} needs a strip index format exactly when it's a strip topology, got {strip_index_format:?}"
+        );
+        self.pipeline_config.topology = topology;
+        self.pipeline_config.strip_index_format = strip_index_format;
+        self.rebuild_pipelines();
+        Ok(())
+    }
+
+    // Turning the depth test off drops the stencil test as well, it lives in the
+    // depth buffer
+    fn set_depth_test(&mut self, enabled: bool) {
+        self.depth_test = enabled;
+        if !enabled {
+            self.stencil = None;
+        }
+        self.update_depth_attachment();
+    }
+
+    // Switches the depth buffer to a format with a stencil aspect while set.
+    // The stencil is cleared to 0 every frame and `StencilMask` writes
+    // `reference` before the meshes are drawn. Needs the depth test.
+    fn set_stencil(&mut self, stencil: Option<StencilConfig>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            stencil.is_none() || self.depth_test,
+            "the stencil mask needs the depth buffer, which is off"
+        );
+        self.stencil = stencil;
+        self.update_depth_attachment();
+        Ok(())
+    }
+
+    // After the depth attachment's format changed, or whether there is one
+    fn update_depth_attachment(&mut self) {
+        self.rebuild_pipelines();
+        self.skybox
+            .set_depth_format(&self.device, self.depth_format());
+        // Recreated with the new depth format on the next frame
+        self.render_targets = None;
+    }
+
+    // Surfaces can come back with a different format, e.g. on Android after a
+    // resume. Everything drawing into the frame was built for `target_format`
+    // and is rebuilt for `format`, the debug panel is left to the caller as it
+    // needs the window.
+    fn rebuild_for_format(&mut self, format: TextureFormat) {
+        log::info!(
+            "WGPU: rebuilding for swapchain format {format:?}, was {:?}",
+            self.target_format
+        );
+        self.target_format = format;
+        (self.render_pipeline, self.wireframe_pipeline) = self.create_scene_pipelines(&self.shader);
+        self.wireframe_overlay_pipeline = self.create_wireframe_overlay_pipeline(&self.shader);
+        self.axis_gizmo.set_target_format(
+            &self.device,
+            format,
+            self.scene_depth_stencil_state(false),
+        );
+        self.debug_draw.set_target_format(
+            &self.device,
+            format,
+            self.scene_depth_stencil_state(false),
+        );
+        self.skybox
+            .set_target_format(&self.device, format, self.depth_format());
+        self.stencil_mask = StencilMask::new(&self.device, format, self.sample_count);
+        let reverse_z = self.depth_vis.reverse_z;
+        self.depth_vis = depth_vis::DepthVisState::new(&self.device, format);
+        self.depth_vis.reverse_z = reverse_z;
+        let effect = self.post_process.effect;
+        self.post_process = postprocess::PostProcessState::new(&self.device, format, self.tone_map);
+        self.post_process.effect = effect;
+        // The color target has the old format, and the new post process has no source yet
+        self.render_targets = None;
+    }
+
+    // Picks up bind groups added since the pipelines were built
+    fn rebuild_pipelines_if_bind_groups_changed(&mut self) {
+        if !self.user_bind_groups.take_layouts_changed() {
+            return;
+        }
+        self.pipeline_layout = create_pipeline_layout(
+            &self.device,
+            &self.texture_state,
+            &self.camera_state,
+            &self.light_state,
+            &self.wireframe_overlay,
+            &self.user_bind_groups,
+        );
+        self.rebuild_pipelines();
+    }
+
+    // The scene pipelines and the depth pre-pass, shadow, wireframe overlay and
+    // axis gizmo pipelines, which have to match them
+    fn rebuild_pipelines(&mut self) {
+        (self.render_pipeline, self.wireframe_pipeline) = self.create_scene_pipelines(&self.shader);
+        self.wireframe_overlay_pipeline = self.create_wireframe_overlay_pipeline(&self.shader);
+        self.depth_prepass_pipeline = create_depth_prepass_pipeline(
+            &self.device,
+            &self.pipeline_layout,
+            &self.shader,
+            &self.pipeline_config,
+            self.sample_count,
+            self.depth_prepass_depth_stencil_state(),
+        );
+        self.shadow_pipeline =
+            self.shadow
+                .create_pipeline(&self.device, &self.shader, &self.pipeline_config);
+        self.axis_gizmo
+            .set_depth_stencil(&self.device, self.scene_depth_stencil_state(false));
+        self.debug_draw
+            .set_depth_stencil(&self.device, self.scene_depth_stencil_state(false));
+    }
+
+    // Rebuilds the shader and scene pipelines when `shader.wgsl` is edited on disk.
+    // A broken shader is logged and the last good pipelines are kept.
+    #[cfg(all(debug_assertions, not(target_os = "android")))]
+    fn reload_shader_if_changed(&mut self) {
+        let Some(source) = self
+            .shader_watcher
+            .as_ref()
+            .and_then(|watcher| watcher.poll())
+        else {
+            return;
+        };
+
+        log::info!("Reloading shader.wgsl");
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+            });
+        let pipelines = self.create_scene_pipelines(&shader);
+        let wireframe_overlay_pipeline = self.create_wireframe_overlay_pipeline(&shader);
+        let depth_prepass_pipeline = create_depth_prepass_pipeline(
+            &self.device,
+            &self.pipeline_layout,
+            &shader,
+            &self.pipeline_config,
+            self.sample_count,
+            self.depth_prepass_depth_stencil_state(),
+        );
+        let shadow_pipeline =
+            self.shadow
+                .create_pipeline(&self.device, &shader, &self.pipeline_config);
+        if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+            log::error!("Shader reload failed, keeping the previous pipeline: {error}");
+            return;
+        }
+
+        self.shader = shader;
+        (self.render_pipeline, self.wireframe_pipeline) = pipelines;
+        self.wireframe_overlay_pipeline = wireframe_overlay_pipeline;
+        self.depth_prepass_pipeline = depth_prepass_pipeline;
+        self.shadow_pipeline = shadow_pipeline;
+    }
+
+    // Faces are ordered +X, -X, +Y, -Y, +Z, -Z, see `skybox::FACE_NAMES`
+    fn set_skybox(&mut self, faces: [image::DynamicImage; 6]) -> anyhow::Result<()> {
+        self.skybox.set_faces(&self.device, &self.queue, faces)
+    }
+
+    // Replaces the cube textures with the model's base color texture, if it has one
+    fn load_gltf_model(
+        &mut self,
+        path: &str,
+        up_axis: data::UpAxis,
+    ) -> anyhow::Result<data::VertexState> {
+        let (mesh, texture) = data::VertexState::from_gltf(&self.device, path, up_axis)?;
+        if let Some(texture) = texture {
+            self.texture_state = texture::TextureData::from_images(
+                &self.device,
+                &self.queue,
+                vec![texture],
+                self.filter_preset,
+            )?;
+        }
+        Ok(mesh)
+    }
+
+    fn configure_surface(&mut self, surface: &wgpu::Surface, size: winit::dpi::PhysicalSize<u32>) {
+        // A minimized window can be 0x0, which wgpu can't configure. The last
+        // configuration stays until the window has a size again.
+        if size.width == 0 || size.height == 0 {
+            log::debug!("WGPU: not configuring the surface at {size:?}");
+            return;
+        }
+        // Every pipeline drawing to the surface was built for `target_format`, the
+        // format and its view format have to stay the one `choose_swapchain_format` picked
+        let swapchain_format = self.target_format;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: swapchain_format,
+            width: size.width,
+            height: size.height,
+            present_mode: self.present_mode,
+            alpha_mode: self.alpha_mode,
+            view_formats: vec![swapchain_format],
+        };
+
+        log::info!(
+            "WGPU: Configuring surface swapchain: format = {swapchain_format:?}, size = {size:?}, present mode = {:?}",
+            self.present_mode
+        );
+        surface.configure(&self.device, &config);
+        self.surface_size = size;
+    }
+
+    // `spec` is `path,cols,rows,fps`, e.g. `explosion.png,4,4,12`
+    fn load_sprite_sheet(&mut self, spec: &str) -> anyhow::Result<()> {
+        let parts: Vec<_> = spec.split(',').map(str::trim).collect();
+        let [path, cols, rows, fps] = parts[..] else {
+            anyhow::bail!("expected path,cols,rows,fps");
+        };
+        let image = image::open(path)?;
+        self.texture_state = texture::TextureData::sprite_sheet(
+            &self.device,
+            &self.queue,
+            image,
+            cols.parse()?,
+            rows.parse()?,
+            fps.parse()?,
+            self.filter_preset,
+        )?;
+        Ok(())
+    }
+
+    // Loads the skybox, texture, model, instance scene and wireframe overlay
+    // settings named by the environment, and returns the meshes to draw
+    fn load_scene(
+        &mut self,
+        instance_seed: Option<u64>,
+        instance_layout: instance::Layout,
+        instance_spin: instance::SpinMode,
+    ) -> Vec<Mesh> {
+        if let Ok(dir) = std::env::var("SKYBOX_DIR") {
+            match skybox::load_faces(&dir).and_then(|faces| self.set_skybox(faces)) {
+                Ok(()) => log::info!("Loaded skybox from {dir}"),
+                Err(e) => log::error!("Failed to load skybox: {e:#}"),
+            }
+        }
+        if let Ok(spec) = std::env::var("SPRITE_SHEET") {
+            match self.load_sprite_sheet(&spec) {
+                Ok(()) => log::info!("Loaded sprite sheet {spec}"),
+                Err(e) => log::error!("Failed to load sprite sheet {spec}: {e:#}"),
+            }
+        } else if let Ok(path) = std::env::var("TEXTURE_PATH") {
+            if let Err(e) = self.load_texture_async(&path) {
+                log::error!("Failed to load texture {path}: {e:#}");
+            }
+        }
+        if let Ok(spec) = std::env::var("WIREFRAME_COLOR") {
+            match wireframe_overlay::parse_color(&spec) {
+                Ok(color) => self.wireframe_overlay.set_color(&self.queue, color),
+                Err(e) => log::error!("Invalid WIREFRAME_COLOR {spec}: {e:#}"),
+            }
+        }
+        if let Ok(spec) = std::env::var("WIREFRAME_BIAS") {
+            match wireframe_overlay::parse_depth_bias(&spec) {
+                Ok(bias) => self.set_wireframe_overlay_bias(bias),
+                Err(e) => log::error!("Invalid WIREFRAME_BIAS {spec}: {e:#}"),
+            }
+        }
+        // Falls back to the cube if the model can't be loaded
+        let mut model = None;
+        if let Ok(path) = std::env::var("GLTF_MODEL") {
+            let up_axis = match std::env::var("GLTF_UP_AXIS") {
+                Ok(name) => data::UpAxis::parse(&name).unwrap_or_else(|e| {
+                    log::error!("Invalid GLTF_UP_AXIS: {e:#}");
+                    data::UpAxis::default()
+                }),
+                Err(_) => data::UpAxis::default(),
+            };
+            match self.load_gltf_model(&path, up_axis) {
+                Ok(mesh) => {
+                    log::info!("Loaded glTF model {path}");
+                    model = Some(mesh);
+                }
+                Err(e) => log::error!("Failed to load glTF model {path}: {e:#}"),
+            }
+        }
+
+        let vertex_state = model.unwrap_or_else(|| data::VertexState::new(&self.device));
+        let num_textures = self.texture_state.num_layers();
+        // Falls back to the layout if the scene can't be loaded
+        let mut scene = None;
+        if let Ok(path) = std::env::var("INSTANCE_SCENE") {
+            match InstanceState::from_scene(&self.device, &path, num_textures) {
+                Ok(instance_state) => {
+                    log::info!("Loaded instance scene {path}");
+                    scene = Some(instance_state);
+                }
+                Err(e) => log::error!("Failed to load instance scene {path}: {e:#}"),
+            }
+        }
+        let instance_state = scene.unwrap_or_else(|| {
+            self.create_instance_state(instance_seed, instance_layout, instance_spin)
+        });
+        vec![Mesh::new(vertex_state, instance_state)]
+    }
+
+    fn create_instance_state(
+        &self,
+        instance_seed: Option<u64>,
+        instance_layout: instance::Layout,
+        instance_spin: instance::SpinMode,
+    ) -> InstanceState {
+        let num_textures = self.texture_state.num_layers();
+        match instance_seed {
+            Some(seed) => InstanceState::new_seeded(
+                &self.device,
+                seed,
+                num_textures,
+                instance_layout,
+                instance_spin,
+            ),
+            None => InstanceState::new(&self.device, num_textures, instance_layout, instance_spin),
+        }
+    }
+
+    // Draws with a placeholder until the image at `path` has been decoded
+    fn load_texture_async(&mut self, path: &str) -> anyhow::Result<()> {
+        let placeholder = Texture::placeholder(&self.device, &self.queue)?;
+        self.texture_state =
+            texture::TextureData::from_texture(&self.device, &self.queue, placeholder);
+        self.pending_texture = Some(Texture::from_path_async(path, self.device.features()));
+        Ok(())
+    }
+
+    fn poll_pending_texture(&mut self) {
+        let Some(pending) = &self.pending_texture else {
+            return;
+        };
+        let Some(result) = pending.poll(&self.device, &self.queue, self.filter_preset) else {
+            return;
+        };
+        match result {
+            Ok(texture) => {
+                log::info!("Loaded texture {}", pending.path().display());
+                self.texture_state =
+                    texture::TextureData::from_texture(&self.device, &self.queue, texture);
+            }
+            Err(e) => log::error!("Failed to load texture, keeping the placeholder: {e:#}"),
+        }
+        self.pending_texture = None;
+    }
+
+    // Only the depth visualization samples the depth buffer
+    fn depth_usage(&self) -> wgpu::TextureUsages {
+        if self.show_depth {
+            wgpu::TextureUsages::TEXTURE_BINDING
+        } else {
+            wgpu::TextureUsages::empty()
+        }
+    }
+
+    // Also recreated when the depth buffer's usage changes with `show_depth`
+    fn ensure_render_targets(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        let depth_usage = self.depth_usage();
+        if self.render_targets.as_ref().is_some_and(|targets| {
+            targets.size == size
+                && targets
+                    .depth
+                    .as_ref()
+                    .is_none_or(|depth| depth.texture.usage().contains(depth_usage))
+        }) {
+            return;
+        }
+
+        log::info!("WGPU: creating render targets, size = {size:?}");
+        let color = Texture::create_render_target(&self.device, size, self.target_format);
+        let multisampled_color = (self.sample_count > 1).then(|| {
+            Texture::create_multisampled_target(
+                &self.device,
+                size,
+                self.target_format,
+                self.sample_count,
+            )
+        });
+        let depth = self.depth_format().map(|format| {
+            Texture::create_depth_tex(&self.device, size, format, depth_usage, self.sample_count)
+        });
+        self.post_process.set_source(&self.device, &color);
+        self.render_targets = Some(RenderTargets {
+            size,
+            color,
+            multisampled_color,
+            depth,
+        });
+    }
+
+    // Time to animate this frame by. Runs on real time, capped so a long stall
+    // doesn't jump ahead. Called once per frame, before `draw_frame`.
+    fn advance_clock(&mut self) -> Duration {
+        let now = Instant::now();
+        let dt = self.fixed_frame_delta.unwrap_or_else(|| {
+            self.last_update
+                .map_or(Duration::ZERO, |last| now - last)
+                .min(MAX_FRAME_DELTA)
+        });
+        self.last_update = Some(now);
+        dt
+    }
+
+    // The next frame starts the clock again without animating, instead of
+    // making up for the time since the last one
+    fn restart_clock(&mut self) {
+        self.last_update = None;
+    }
+
+    fn update_uniforms(
+        &mut self,
+        aspect_ratio: f32,
+        meshes: &mut [Mesh],
+        encoder: &mut wgpu::CommandEncoder,
+        dt: Duration,
+    ) {
+        // Update the camera first, culling the instances needs its matrix
+        self.camera_state.update_aspect_ratio(aspect_ratio);
+        self.camera_state.update();
+
+        // One camera's frustum would cull what the other viewports see
+        let frustum = (self.frustum_culling && !self.camera_state.is_split_screen()).then(|| {
+            camera::Frustum::from_view_projection(
+                &self.camera_state.camera().build_view_projection_matrix(),
+            )
+        });
+        for mesh in meshes.iter_mut() {
+            mesh.update(&self.device, encoder, frustum.as_ref(), dt.as_secs_f32());
+        }
+        let scene_bounds = meshes
+            .iter()
+            .filter_map(Mesh::bounding_box)
+            .reduce(instance::merge_bounds);
+        self.shadow
+            .update(&self.queue, self.light_state.direction(), scene_bounds);
+        self.texture_state.update(&self.queue, dt.as_secs_f32());
+        self.skybox.update(&self.queue, self.camera_state.camera());
+        self.light_state.update(&self.queue);
+        self.camera_state
+            .globals
+            .update(&self.queue, dt.as_secs_f32(), self.surface_size);
+        self.camera_state.write_buffers(&self.queue);
+        self.debug_draw.upload(&self.queue);
     }
     
     fn setup_render_pass<'a>(
         &'a self,
         encoder: &'a mut wgpu::CommandEncoder,
-        view: &'a wgpu::TextureView,
-        depth_view: &'a wgpu::TextureView,
+        (view, resolve_target): (&'a wgpu::TextureView, Option<&'a wgpu::TextureView>),
+        depth_view: Option<&'a wgpu::TextureView>,
+        clear_depth: bool,
     ) -> wgpu::RenderPass<'a> {
         encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: None,
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                 view,
-                resolve_target: None,
+                resolve_target,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLUE),
+                    load: self.scene_load_ops.color.load_op(self.clear_color),
                     store: true,
                 },
             })],
+            depth_stencil_attachment: depth_view.map(|view| {
+                wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: if clear_depth {
+                            self.scene_load_ops
+                                .depth
+                                .load_op(self.depth_mode.clear_value())
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: true,
+                    }),
+                    stencil_ops: self.stencil.map(|_| wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: true,
+                    }),
+                }
+            }),
+        })
+    }
+
+    fn draw_depth_prepass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        meshes: &[Mesh],
+        size: winit::dpi::PhysicalSize<u32>,
+        stats: &mut FrameStats,
+    ) {
+        let Some(pipeline) = &self.depth_prepass_pipeline else {
+            return;
+        };
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("depth prepass"),
+            color_attachments: &[],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: self
+                        .scene_load_ops
+                        .depth
+                        .load_op(self.depth_mode.clear_value()),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        for (viewport, camera_bind_group) in self.camera_state.viewports() {
+            if !set_viewport(&mut rpass, viewport, size) {
+                continue;
+            }
+            self.bind_resources(&mut rpass, pipeline, camera_bind_group);
+            self.draw_meshes(&mut rpass, meshes, stats);
+        }
+    }
+
+    // Only the instances uploaded this frame cast shadows, with frustum culling
+    // that leaves out casters outside the camera's view
+    fn draw_shadow_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        meshes: &[Mesh],
+        stats: &mut FrameStats,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("shadow pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.shadow.map.view,
                 depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Clear(1.0),
                     store: true,
                 }),
                 stencil_ops: None,
             }),
-        })
+        });
+        rpass.set_pipeline(&self.shadow_pipeline);
+        rpass.set_bind_group(0, &self.texture_state.bind_group, &[]);
+        rpass.set_bind_group(1, &self.shadow.light_camera_bind_group, &[]);
+        self.draw_meshes(&mut rpass, meshes, stats);
+    }
+
+    // Draws every mesh with whatever's bound, counting the draws into `stats`
+    fn draw_meshes<'a>(
+        &self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        meshes: &'a [Mesh],
+        stats: &mut FrameStats,
+    ) {
+        for mesh in meshes {
+            #[cfg(feature = "indirect-draw")]
+            if self.indirect_draw {
+                mesh.draw_indirect(rpass);
+            } else {
+                mesh.draw(rpass);
+            }
+            #[cfg(not(feature = "indirect-draw"))]
+            mesh.draw(rpass);
+            stats.add_draw(
+                mesh.geometry.num_indices,
+                mesh.instances.num_visible(),
+                self.pipeline_config.topology,
+            );
+        }
     }
     
+    fn scene_pipeline(&self) -> &RenderPipeline {
+        match &self.wireframe_pipeline {
+            Some(wireframe_pipeline) if self.wireframe => wireframe_pipeline,
+            _ => &self.render_pipeline,
+        }
+    }
+
+    // Binds what every mesh shares, `Mesh::draw` binds the per mesh buffers.
+    // `camera_bind_group` is the viewport's, see `CameraState::viewports`.
     fn bind_resources<'a>(
         &'a self,
         rpass: &mut wgpu::RenderPass<'a>,
-        vertex_state: &'a data::VertexState,
-        instance_state: &'a InstanceState,
+        pipeline: &'a RenderPipeline,
+        camera_bind_group: &'a wgpu::BindGroup,
     ) {
-        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_pipeline(pipeline);
         rpass.set_bind_group(0, &self.texture_state.bind_group, &[]);
-        rpass.set_bind_group(1, &self.camera_state.bind_group, &[]);
-        rpass.set_vertex_buffer(0, vertex_state.vertex_buffer.slice(..));
-        rpass.set_vertex_buffer(1, instance_state.instance_buffer.slice(..));
-        rpass.set_index_buffer(vertex_state.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        rpass.set_bind_group(1, camera_bind_group, &[]);
+        rpass.set_bind_group(2, &self.light_state.bind_group, &[]);
+        rpass.set_bind_group(3, &self.wireframe_overlay.bind_group, &[]);
+        self.user_bind_groups.bind(rpass);
     }
+
     
+    // Builds this frame's debug panel, drawn at the end of `draw_frame`
+    #[cfg(feature = "gui")]
+    fn run_gui(&mut self, window: &winit::window::Window, meshes: &mut [Mesh]) {
+        // Taken out so the panel can borrow the rest of the render state
+        let Some(mut gui) = self.gui.take() else {
+            return;
+        };
+        gui.run(window, |ctx| gui::debug_panel(ctx, self, meshes));
+        self.gui = Some(gui);
+    }
+
     fn draw_frame(
         &mut self,
         surface_texture: wgpu::SurfaceTexture,
-        vertex_state: &data::VertexState,
-        instance_state: &mut InstanceState,
+        meshes: &mut [Mesh],
+        dt: Duration,
     ) -> Result<(), wgpu::SurfaceError> {
         let view = surface_texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
-        
-        // Use actual surface texture size for depth texture
-        let surface_size = surface_texture.texture.size();
-        let size = winit::dpi::PhysicalSize::new(surface_size.width, surface_size.height);
+        self.render_frame(&view, meshes, dt);
+        surface_texture.present();
+        Ok(())
+    }
+
+    // Updates and draws one frame into `view`, which has to be `surface_size`
+    // big with the `target_format` the pipelines were built for. `dt` comes from
+    // `advance_clock`.
+    fn render_frame(&mut self, view: &wgpu::TextureView, meshes: &mut [Mesh], dt: Duration) {
+        #[cfg(all(debug_assertions, not(target_os = "android")))]
+        self.reload_shader_if_changed();
+        self.poll_pending_texture();
+
+        let size = self.surface_size;
         let aspect_ratio = size.width as f32 / size.height as f32;
         
-        // Update all uniforms in one batch
-        self.update_uniforms(aspect_ratio, instance_state);
-        
-        let depth_tex = Texture::create_depth_tex(&self.device, size);
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        #[cfg(feature = "gpu-timing")]
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.begin_frame(&self.device, &mut encoder);
+        }
+
+        // Update all uniforms in one batch, the instance upload is recorded into the encoder
+        self.update_uniforms(aspect_ratio, meshes, &mut encoder, dt);
+        let mut stats = FrameStats {
+            culled_instances: meshes.iter().map(|mesh| mesh.instances.num_culled()).sum(),
+            ..FrameStats::default()
+        };
         
+        self.ensure_render_targets(size);
+        let targets = self.render_targets.as_ref().unwrap();
+
+        if self.shadow.is_active() {
+            self.draw_shadow_pass(&mut encoder, meshes, &mut stats);
+        }
+        
+        // The wireframe pipeline always does its own depth test
+        let depth_view = targets.depth.as_ref().map(|depth| &depth.view);
+        let depth_prepass = self.uses_depth_prepass() && !self.wireframe;
+        if let (true, Some(depth_view)) = (depth_prepass, depth_view) {
+            self.draw_depth_prepass(&mut encoder, depth_view, meshes, size, &mut stats);
+        }
+
         {
-            let mut rpass = self.setup_render_pass(&mut encoder, &view, &depth_tex.view);
-            self.bind_resources(&mut rpass, vertex_state, instance_state);
-            rpass.draw_indexed(0..vertex_state.num_indices, 0, 0..instance_state.num_instances());
+            // Cleared once for the whole frame, then every viewport draws into its part
+            let mut rpass = self.setup_render_pass(
+                &mut encoder,
+                targets.scene_color(),
+                depth_view,
+                !depth_prepass,
+            );
+            if let Some(stencil) = self.stencil {
+                rpass.set_stencil_reference(stencil.reference);
+            }
+            for (viewport, camera_bind_group) in self.camera_state.viewports() {
+                if !set_viewport(&mut rpass, viewport, size) {
+                    continue;
+                }
+                if self.stencil.is_some() {
+                    self.stencil_mask.draw(&mut rpass);
+                }
+                // The sky follows the active camera, in every viewport
+                self.skybox.draw(&mut rpass);
+                self.bind_resources(&mut rpass, self.scene_pipeline(), camera_bind_group);
+                self.draw_meshes(&mut rpass, meshes, &mut stats);
+                if let Some(pipeline) = &self.wireframe_overlay_pipeline {
+                    if self.wireframe_overlay.enabled && !self.wireframe {
+                        self.bind_resources(&mut rpass, pipeline, camera_bind_group);
+                        self.draw_meshes(&mut rpass, meshes, &mut stats);
+                    }
+                }
+                if self.axis_gizmo.enabled {
+                    self.axis_gizmo.draw(&mut rpass, camera_bind_group);
+                }
+                self.debug_draw.draw(&mut rpass, camera_bind_group);
+            }
+        }
+
+        if let (true, Some(depth)) = (self.show_depth, &targets.depth) {
+            // Sampling needs a view of only the depth aspect when there's a stencil
+            let depth_view = depth.texture.create_view(&wgpu::TextureViewDescriptor {
+                aspect: wgpu::TextureAspect::DepthOnly,
+                ..Default::default()
+            });
+            self.depth_vis.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &targets.color.view,
+                &depth_view,
+                self.camera_state.camera().clip_planes(),
+            );
+        }
+
+        self.post_process.render(&mut encoder, view);
+
+        #[cfg(feature = "gui")]
+        if let Some(gui) = &mut self.gui {
+            gui.render(&self.device, &self.queue, &mut encoder, view, size);
         }
         
-        self.queue.submit(Some(encoder.finish()));
-        surface_texture.present();
-        Ok(())
+        #[cfg(feature = "gpu-timing")]
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.end_frame(&mut encoder);
+        }
+
+        for mesh in meshes.iter_mut() {
+            mesh.instances.finish_upload();
+        }
+        let submission = self.queue.submit(Some(encoder.finish()));
+        self.frames_in_flight.submitted(&self.queue, submission);
+        for mesh in meshes.iter_mut() {
+            mesh.instances.recall_upload();
+        }
+        self.debug_draw.clear();
+        self.frame_stats = stats;
+        #[cfg(feature = "gpu-timing")]
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.after_submit();
+        }
+    }
+}
+
+// Restricts drawing to `viewport`, false when it's too small to cover a pixel
+fn set_viewport(
+    rpass: &mut wgpu::RenderPass,
+    viewport: Viewport,
+    size: winit::dpi::PhysicalSize<u32>,
+) -> bool {
+    let (x, y, width, height) = viewport.rect(size);
+    if width == 0 || height == 0 {
+        return false;
     }
+    rpass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+    true
+}
+
+const CAMERA_CONFIG_PATH: &str = "camera.json";
+// Saved and restored along with the camera
+const WINDOW_CONFIG_PATH: &str = "window.json";
+const MAX_FRAME_DELTA: Duration = Duration::from_millis(250);
+#[cfg(all(debug_assertions, not(target_os = "android")))]
+const SHADER_SOURCE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
+
+// Without a surface any adapter will do, headless rendering only draws to textures
+async fn request_adapter(
+    instance: &Instance,
+    surface: Option<&wgpu::Surface>,
+) -> anyhow::Result<Adapter> {
+    log::info!("WGPU: requesting a suitable adapter (compatible with our surface, if any)");
+    let Some(adapter) = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            // Request an adapter which can render to our surface
+            compatible_surface: surface,
+        })
+        .await
+    else {
+        log_available_adapters(instance, surface);
+        return Err(match surface {
+            Some(_) => anyhow::anyhow!("no adapter can render to the surface"),
+            None => anyhow::anyhow!("no adapter available"),
+        });
+    };
+
+    // The first thing needed for any bug report
+    let info = adapter.get_info();
+    log::info!(
+        "WGPU: using adapter {} ({:?}, {:?}), driver: {} {}",
+        info.name,
+        info.backend,
+        info.device_type,
+        info.driver,
+        info.driver_info
+    );
+    log::info!("WGPU: adapter limits: {:?}", adapter.limits());
+    Ok(adapter)
+}
+
+// Whether `error` comes from a call made on a lost device. wgpu-core wraps its
+// `DeviceError` in an error per call, these are the ones made every frame.
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    use wgpu_core::device::queue::{QueueSubmitError, QueueWriteError};
+    use wgpu_core::device::DeviceError;
+    use wgpu_core::present::{ConfigureSurfaceError, SurfaceError};
+
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        let device_error = error
+            .downcast_ref::<DeviceError>()
+            .or_else(|| match error.downcast_ref() {
+                Some(QueueSubmitError::Queue(e)) => Some(e),
+                _ => None,
+            })
+            .or_else(|| match error.downcast_ref() {
+                Some(QueueWriteError::Queue(e)) => Some(e),
+                _ => None,
+            })
+            .or_else(|| match error.downcast_ref() {
+                Some(ConfigureSurfaceError::Device(e)) => Some(e),
+                _ => None,
+            })
+            .or_else(|| match error.downcast_ref() {
+                Some(SurfaceError::Device(e)) => Some(e),
+                _ => None,
+            });
+        if matches!(device_error, Some(DeviceError::Lost)) {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+// Everything the instance can see, for when `request_adapter` found nothing
+// suitable. Common with broken drivers or in virtual machines without a GPU.
+fn log_available_adapters(instance: &Instance, surface: Option<&wgpu::Surface>) {
+    let mut adapters = instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .peekable();
+    if adapters.peek().is_none() {
+        log::error!(
+            "WGPU: no adapters found, check the graphics drivers or pick another backend \
+             with WGPU_BACKEND"
+        );
+        return;
+    }
+    for adapter in adapters {
+        let info = adapter.get_info();
+        let supported = surface.map_or("", |surface| {
+            if adapter.is_surface_supported(surface) {
+                ", can render to the surface"
+            } else {
+                ", can't render to the surface"
+            }
+        });
+        log::error!(
+            "WGPU: found adapter {} ({:?}, {:?}), driver: {} {}{supported}",
+            info.name,
+            info.backend,
+            info.device_type,
+            info.driver,
+            info.driver_info
+        );
+    }
+}
+
+// Color space flow: textures are uploaded as `Rgba8UnormSrgb`, so sampling decodes
+// them to linear values and the shader works in linear space. Writing to an sRGB
+// swapchain encodes back to sRGB on store. With a plain `Unorm` swapchain that
+// encode never happens, so the post process pass does it in its shader instead.
+// An sRGB format is still preferred whenever the surface offers one, the scene
+// target has the swapchain's format and 8 bits of linear color band in the darks.
+//
+// With HDR output the swapchain is `Rgba16Float` instead, which takes linear
+// color in extended range (scRGB) and needs no encode. The scene target shares
+// its format, so lighting brighter than 1 survives until the post process pass
+// tone maps it, see `postprocess::ToneMap`.
+//
+// Backends list their formats in different orders, so the 8 bit sRGB formats are
+// looked for by name first, then any other sRGB format, and only then whatever
+// the surface lists first. Otherwise Vulkan and DX12 can end up with different
+// formats for the same display.
+const PREFERRED_SWAPCHAIN_FORMATS: [TextureFormat; 2] =
+    [TextureFormat::Bgra8UnormSrgb, TextureFormat::Rgba8UnormSrgb];
+
+const HDR_SWAPCHAIN_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+// Falls back to the LDR formats when `hdr` is asked for but not offered
+fn choose_swapchain_format(formats: &[TextureFormat], hdr: bool) -> TextureFormat {
+    if hdr {
+        if formats.contains(&HDR_SWAPCHAIN_FORMAT) {
+            log::info!("WGPU: using the HDR swapchain format {HDR_SWAPCHAIN_FORMAT:?}");
+            return HDR_SWAPCHAIN_FORMAT;
+        }
+        log::warn!("WGPU: surface has no {HDR_SWAPCHAIN_FORMAT:?} format, using LDR output");
+    }
+    let format = PREFERRED_SWAPCHAIN_FORMATS
+        .into_iter()
+        .find(|format| formats.contains(format))
+        .or_else(|| formats.iter().copied().find(TextureFormat::is_srgb))
+        .unwrap_or(formats[0]);
+    if !format.is_srgb() {
+        log::warn!("WGPU: surface has no sRGB format, dark gradients may show banding");
+    }
+    format
 }
 
 struct SurfaceState {
-    window: winit::window::Window,
+    // Fields drop in declaration order, the surface has to go before its window
     surface: wgpu::Surface,
+    window: winit::window::Window,
 }
 
-struct App {
+// The windowed demo, the binary and Android entry points hand
+// `App::from_env()` to `run`
+pub struct App {
     instance: Instance,
     adapter: Option<Adapter>,
     surface_state: Option<SurfaceState>,
     render_state: Option<RenderState>,
-    vertex_state: Option<data::VertexState>,
-    instance_state: Option<InstanceState>,
+    // Drawn in order, empty until the render state exists
+    meshes: Vec<Mesh>,
+    // When set, redraws are throttled to this rate instead of running as fast
+    // as the present mode allows
+    target_fps: Option<u32>,
+    next_frame: Option<Instant>,
+    // Set by resizes and applied once by the next redraw, a drag resize sends
+    // far more of them than there are frames
+    resize_pending: bool,
+    // The window's, to find its size in pixels when it changes
+    scale_factor: f64,
+    cursor_position: Option<PhysicalPosition<f64>>,
+    camera_controller: camera::CameraController,
+    // Takes Shift + left drags and the arrow keys, everything else goes to the camera
+    light_controller: light::LightController,
+    modifiers: ModifiersState,
+    // Ctrl + right held, the cursor is grabbed and hidden and raw mouse motion
+    // turns the camera
+    mouse_look: bool,
+    // Makes the instances' random rotation axes reproducible
+    instance_seed: Option<u64>,
+    instance_layout: instance::Layout,
+    instance_spin: instance::SpinMode,
+    // Applied to every camera, which keep unit Y when None
+    world_up: Option<cgmath::Vector3<f32>>,
+    // See `set_viewports`
+    viewports: Vec<Viewport>,
+    // See `set_skybox`, replaces the one from `SKYBOX_DIR`
+    skybox_faces: Option<[image::DynamicImage; 6]>,
+    // Entry points the scene shader is expected to have
+    pipeline_config: PipelineConfig,
+    alpha_cutoff: Option<f32>,
+    // See `RenderState::depth_test`
+    depth_test: bool,
+    // See `enable_shader_globals`
+    shader_globals: bool,
+    // Triangle list when None
+    topology: Option<wgpu::PrimitiveTopology>,
+    // See `FramesInFlight`
+    max_frames_in_flight: u32,
+    // Present modes to try, in order of preference
+    present_modes: Vec<wgpu::PresentMode>,
+    // Used when the surface supports it, the window is transparent unless it's Opaque
+    alpha_mode: wgpu::CompositeAlphaMode,
+    // Index into the window's `available_monitors` to go fullscreen on, the
+    // monitor the window is on when None
+    fullscreen_monitor: Option<usize>,
+    // Percent of the primary monitor's size the window opens at, the platform
+    // picks the size when None
+    window_size_percent: Option<f64>,
+    // Outer position the window is moved to once it's created, the platform
+    // places it when None
+    window_position: Option<PhysicalPosition<i32>>,
+    // Asks for `HDR_SWAPCHAIN_FORMAT`, see `choose_swapchain_format`
+    hdr_output: bool,
+    // Kept for the render state rebuilt after a device loss, see `set_scene_load_ops`
+    scene_load_ops: SceneLoadOps,
+    // Kept for the render state rebuilt after a device loss, see `set_exposure`
+    exposure: f32,
+    // See `set_pause_when_unfocused`
+    pause_when_unfocused: bool,
+    // Set while the window doesn't have focus and rendering is paused for it
+    paused: bool,
+    // User logic run every frame, see `on_update`
+    on_update: Option<UpdateFn>,
+    // Reports the frame rate and `FrameStats` every second at debug level
+    stats_log: stats::StatsLog,
 }
 
 impl App {
@@ -137,45 +1608,370 @@ impl App {
             adapter: None,
             surface_state: None,
             render_state: None,
-            vertex_state: None,
-            instance_state: None,
+            meshes: Vec::new(),
+            target_fps: None,
+            next_frame: None,
+            resize_pending: false,
+            scale_factor: 1.0,
+            cursor_position: None,
+            camera_controller: camera::CameraController::new(),
+            light_controller: light::LightController::default(),
+            modifiers: ModifiersState::empty(),
+            mouse_look: false,
+            instance_seed: None,
+            instance_layout: instance::Layout::default(),
+            instance_spin: instance::SpinMode::default(),
+            world_up: None,
+            viewports: Vec::new(),
+            skybox_faces: None,
+            pipeline_config: PipelineConfig::default(),
+            alpha_cutoff: None,
+            depth_test: true,
+            shader_globals: false,
+            topology: None,
+            max_frames_in_flight: frame_pacing::DEFAULT_MAX_FRAMES_IN_FLIGHT,
+            present_modes: DEFAULT_PRESENT_MODES.to_vec(),
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            fullscreen_monitor: None,
+            window_size_percent: Some(DEFAULT_WINDOW_SIZE_PERCENT),
+            window_position: None,
+            hdr_output: false,
+            scene_load_ops: SceneLoadOps::default(),
+            exposure: 1.0,
+            pause_when_unfocused: true,
+            paused: false,
+            on_update: None,
+            stats_log: stats::StatsLog::default(),
+        }
+    }
+
+    // Configured from the environment variables listed in the README
+    pub fn from_env() -> Self {
+        // doesn't need to be re-considered later
+        let instance = Instance::new(wgpu::InstanceDescriptor {
+            backends: backends_from_env(),
+            ..Default::default()
+        });
+
+        let mut app = App::new(instance);
+        app.target_fps = std::env::var("TARGET_FPS")
+            .ok()
+            .and_then(|fps| fps.parse().ok());
+        if let Some(fps) = app.target_fps {
+            log::info!("Limiting frame rate to {fps} FPS");
+        }
+        app.instance_seed = std::env::var("INSTANCE_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok());
+        if let Some(seed) = app.instance_seed {
+            log::info!("Generating instances with seed {seed}");
+        }
+        if let Ok(spec) = std::env::var("INSTANCE_LAYOUT") {
+            match instance::Layout::parse(&spec) {
+                Ok(layout) => app.instance_layout = layout,
+                Err(e) => log::error!("Invalid INSTANCE_LAYOUT {spec}: {e:#}"),
+            }
+        }
+        if let Ok(spec) = std::env::var("WORLD_UP") {
+            app.world_up = parse_world_up(&spec);
+            if app.world_up.is_none() {
+                log::error!("Invalid WORLD_UP {spec}, expected y, z or an axis as x,y,z");
+            }
+        }
+        if let Ok(spec) = std::env::var("SPLIT_SCREEN") {
+            let cameras: Result<Vec<usize>, _> = spec
+                .split(',')
+                .map(|camera| camera.trim().parse())
+                .collect();
+            match cameras {
+                Ok(cameras) => app.viewports = Viewport::side_by_side(&cameras),
+                Err(_) => log::error!("Invalid SPLIT_SCREEN {spec}, expected indices like 0,1"),
+            }
+        }
+        if let Ok(spec) = std::env::var("INSTANCE_SPIN") {
+            match instance::SpinMode::parse(&spec) {
+                Ok(spin) => app.instance_spin = spin,
+                Err(e) => log::error!("Invalid INSTANCE_SPIN {spec}: {e:#}"),
+            }
+        }
+        app.present_modes = present_modes_from_env();
+        app.max_frames_in_flight = max_frames_in_flight_from_env();
+        app.alpha_mode = alpha_mode_from_env();
+        app.alpha_cutoff = std::env::var("ALPHA_CUTOFF")
+            .ok()
+            .and_then(|cutoff| cutoff.parse().ok());
+        if let Some(exposure) = std::env::var("EXPOSURE")
+            .ok()
+            .and_then(|exposure| exposure.parse().ok())
+        {
+            app.exposure = exposure;
+        }
+        app.depth_test = std::env::var("DEPTH_TEST").map_or(true, |value| {
+            !matches!(
+                value.trim().to_ascii_lowercase().as_str(),
+                "0" | "off" | "false"
+            )
+        });
+        if let Ok(name) = std::env::var("PRIMITIVE_TOPOLOGY") {
+            app.topology = parse_topology(&name);
+            if app.topology.is_none() {
+                log::error!(
+                    "Unknown PRIMITIVE_TOPOLOGY {name}, expected point_list, line_list, line_strip, triangle_list or triangle_strip"
+                );
+            }
+        }
+        app.fullscreen_monitor = std::env::var("FULLSCREEN_MONITOR")
+            .ok()
+            .and_then(|index| index.parse().ok());
+        app.hdr_output = hdr_output_from_env();
+        if let Ok(spec) = std::env::var("SCENE_LOAD") {
+            match SceneLoadOps::parse(&spec) {
+                Ok(load_ops) => app.scene_load_ops = load_ops,
+                Err(e) => log::error!("Invalid SCENE_LOAD {spec}: {e:#}"),
+            }
+        }
+        app.pause_when_unfocused = std::env::var("PAUSE_WHEN_UNFOCUSED").map_or(true, |value| {
+            !matches!(
+                value.trim().to_ascii_lowercase().as_str(),
+                "0" | "off" | "false"
+            )
+        });
+        if let Ok(spec) = std::env::var("WINDOW_POSITION") {
+            app.window_position = parse_window_position(&spec);
+            if app.window_position.is_none() {
+                log::error!("Invalid WINDOW_POSITION {spec}, expected x,y in physical pixels");
+            }
+        }
+        if let Ok(value) = std::env::var("WINDOW_SIZE_PERCENT") {
+            app.window_size_percent = parse_window_size_percent(&value).unwrap_or_else(|| {
+                log::error!("Invalid WINDOW_SIZE_PERCENT {value}, expected 1 to 100 or off");
+                Some(DEFAULT_WINDOW_SIZE_PERCENT)
+            });
+        }
+        app
+    }
+
+    // Runs `callback` every frame right before it's drawn, with the seconds
+    // since the last frame. Replaces any previous callback.
+    pub fn on_update(&mut self, callback: UpdateFn) {
+        self.on_update = Some(callback);
+    }
+
+    // Uploads the time, frame count and resolution `shader.wgsl` reads from
+    // `globals` every frame, they stay zero otherwise
+    pub fn enable_shader_globals(&mut self) {
+        self.shader_globals = true;
+    }
+
+    // Size of the surface as last configured, None while there's no render state.
+    // Controllers driving the app should read it rather than track resizes.
+    pub fn current_size(&self) -> Option<winit::dpi::PhysicalSize<u32>> {
+        self.render_state
+            .as_ref()
+            .map(|render_state| render_state.surface_size)
+    }
+
+    // The window's top left corner including its decorations, in physical
+    // pixels on the desktop. None without a window, and where the platform
+    // doesn't tell, like Wayland and Android.
+    pub fn window_position(&self) -> Option<PhysicalPosition<i32>> {
+        outer_position(&self.surface_state.as_ref()?.window)
+    }
+
+    // Moves the window now if there is one, and any window created later, e.g.
+    // after an Android resume. Platforms that don't let apps place their
+    // windows, like Wayland, ignore it.
+    pub fn set_window_position(&mut self, position: PhysicalPosition<i32>) {
+        self.window_position = Some(position);
+        if let Some(surface_state) = &self.surface_state {
+            surface_state.window.set_outer_position(position);
+        }
+    }
+
+    // Whether the scene pass clears its color and depth every frame or keeps
+    // what the last frame drew, see `SceneLoadOps`
+    pub fn set_scene_load_ops(&mut self, load_ops: SceneLoadOps) {
+        self.scene_load_ops = load_ops;
+        if let Some(rs) = &mut self.render_state {
+            rs.scene_load_ops = load_ops;
+        }
+    }
+
+    // Brightens or darkens the shaded scene by this factor, 1 by default. Works
+    // without an HDR target too, where the result is clamped to the display's range.
+    pub fn set_exposure(&mut self, exposure: f32) -> anyhow::Result<()> {
+        check_exposure(exposure)?;
+        self.exposure = exposure;
+        if let Some(rs) = &mut self.render_state {
+            rs.camera_state.exposure = exposure;
         }
+        Ok(())
+    }
+
+    // Stops drawing while the window doesn't have focus, on by default. Only
+    // redraws the platform asks for, e.g. after a resize, still happen, and the
+    // scene doesn't animate until the focus is back.
+    pub fn set_pause_when_unfocused(&mut self, pause: bool) {
+        self.pause_when_unfocused = pause;
+        if !pause {
+            self.set_focused(true);
+        }
+    }
+
+    // Splits the window between cameras, e.g. `Viewport::side_by_side(&[0, 1])`.
+    // An empty list goes back to the active camera filling the window. Kept
+    // for the render state rebuilt after a device loss.
+    pub fn set_viewports(&mut self, viewports: Vec<Viewport>) -> anyhow::Result<()> {
+        if let Some(rs) = &mut self.render_state {
+            rs.camera_state.set_viewports(&rs.device, &viewports)?;
+        }
+        self.viewports = viewports;
+        Ok(())
+    }
+
+    // Draws a cube map behind the scene, e.g. from `skybox::load_faces`. Faces
+    // are ordered +X, -X, +Y, -Y, +Z, -Z and must all be the same square size.
+    // Kept for the render state rebuilt after a device loss.
+    pub fn set_skybox(&mut self, faces: [image::DynamicImage; 6]) -> anyhow::Result<()> {
+        if let Some(rs) = &mut self.render_state {
+            rs.set_skybox(faces.clone())?;
+        }
+        self.skybox_faces = Some(faces);
+        Ok(())
+    }
+
+    // Draw calls, instances and triangles of the last frame drawn, e.g. to compare
+    // with and without frustum culling. None until there's a render state.
+    pub fn frame_stats(&self) -> Option<FrameStats> {
+        self.render_state.as_ref().map(|rs| rs.frame_stats)
+    }
+
+    // What the adapter supports, to check before relying on an optional feature
+    // like `TIMESTAMP_QUERY`. None until the first surface brought an adapter
+    // with it, and again after a device loss until it's replaced.
+    pub fn adapter_capabilities(
+        &self,
+    ) -> Option<(wgpu::Features, wgpu::Limits, wgpu::AdapterInfo)> {
+        let adapter = self.adapter.as_ref()?;
+        Some((adapter.features(), adapter.limits(), adapter.get_info()))
     }
 }
 
 impl App {
-    fn create_surface<T>(&mut self, event_loop: &EventLoopWindowTarget<T>) {
-        let window = winit::window::Window::new(event_loop).unwrap();
+    // Fails when no window can be created, or when the platform can't give wgpu
+    // a surface for it (currently only documented to happen with WebGL2)
+    fn create_surface(&mut self, event_loop: &ActiveEventLoop) -> anyhow::Result<()> {
+        let mut attributes = winit::window::Window::default_attributes()
+            .with_transparent(self.alpha_mode != wgpu::CompositeAlphaMode::Opaque);
+        if let Some(percent) = self.window_size_percent {
+            let size = initial_window_size(event_loop, percent);
+            log::info!("Opening a {}x{} window", size.width, size.height);
+            attributes = attributes.with_inner_size(size);
+        }
+        let window = event_loop.create_window(attributes)?;
+        self.scale_factor = window.scale_factor();
+        if let Some(position) = self.window_position {
+            log::info!("Moving the window to {}, {}", position.x, position.y);
+            window.set_outer_position(position);
+        }
         log::info!("WGPU: creating surface for native window");
 
-        // # Panics
-        // Currently create_surface is documented to only possibly fail with with WebGL2
-        let surface = unsafe {
-            self.instance
-                .create_surface(&window)
-                .expect("Failed to create surface")
-        };
+        // Safety: the surface must not outlive the window it was created from. Both
+        // live in `SurfaceState`, which is only ever dropped or replaced as a whole,
+        // and `surface` is declared first so it's dropped before `window`.
+        let surface = unsafe { self.instance.create_surface(&window)? };
         self.surface_state = Some(SurfaceState { window, surface });
+        Ok(())
     }
 
-    async fn init_render_state(adapter: &Adapter, target_format: TextureFormat) -> RenderState {
+    // Fails when there's no device, or with the validation error when
+    // `shader.wgsl` or a pipeline built from it doesn't compile
+    async fn init_render_state(
+        adapter: &Adapter,
+        target_format: TextureFormat,
+        pipeline_config: PipelineConfig,
+    ) -> anyhow::Result<RenderState> {
         log::info!("Initializing render state");
 
+        // Line polygon mode is optional, without it there's no wireframe pipeline
+        let supports_wireframe = adapter
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE);
+        let mut features = wgpu::Features::empty();
+        if supports_wireframe {
+            features |= wgpu::Features::POLYGON_MODE_LINE;
+        } else {
+            log::warn!("WGPU: adapter doesn't support POLYGON_MODE_LINE, wireframe mode is unavailable");
+        }
+        let sample_count = choose_sample_count(adapter, target_format, msaa_samples_from_env());
+        // Only 4 samples are guaranteed, other counts are checked against the
+        // adapter's own format features, which the device has to opt into
+        let adapter_format_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+        if sample_count > 1 && adapter.features().contains(adapter_format_features) {
+            features |= adapter_format_features;
+        }
+        log::info!("MSAA samples: {sample_count}");
+        // Textures in a format the device can't sample fall back to an uncompressed image
+        let texture_compression = adapter.features() & compressed::FEATURES;
+        features |= texture_compression;
+        log::info!("WGPU: texture compression: {texture_compression:?}");
+        #[cfg(feature = "gpu-timing")]
+        if adapter.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            features |= wgpu::Features::TIMESTAMP_QUERY;
+        } else {
+            log::warn!("WGPU: adapter doesn't support TIMESTAMP_QUERY, GPU frame timing is unavailable");
+        }
+        #[cfg(feature = "indirect-draw")]
+        let indirect_draw = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION);
+        #[cfg(feature = "indirect-draw")]
+        if !indirect_draw {
+            log::warn!("WGPU: adapter doesn't support INDIRECT_EXECUTION, meshes are drawn directly");
+        }
+
         log::info!("WGPU: requesting device");
         // Create the logical device and command queue
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    features,
                     // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
-                    limits: wgpu::Limits::downlevel_webgl2_defaults()
-                        .using_resolution(adapter.limits()),
+                    limits: wgpu::Limits {
+                        // Room for `UserBindGroups` past the renderer's own groups
+                        max_bind_groups: adapter.limits().max_bind_groups,
+                        ..wgpu::Limits::downlevel_webgl2_defaults()
+                            .using_resolution(adapter.limits())
+                    },
                 },
                 None,
             )
             .await
-            .expect("Failed to create device");
+            .context("failed to create a device")?;
+        log::info!("WGPU: requested features: {features:?}");
+        log::info!("WGPU: enabled features: {:?}", device.features());
+        log::info!("WGPU: device limits: {:?}", device.limits());
+
+        // wgpu 0.16 has no device lost callback, instead the loss shows up as an error
+        // from calls made on the device. Anything else is logged, the call that caused
+        // it did nothing and rendering goes on.
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            if is_device_lost(&error) {
+                if !lost.swap(true, Ordering::Relaxed) {
+                    log::error!("WGPU: {error}");
+                }
+            } else {
+                log::error!("WGPU: {error}");
+            }
+        }));
+
+        // Shader and pipeline errors end up here rather than in the uncaptured
+        // error handler, which would only log them, until the scope is popped below
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
 
         log::info!("WGPU: loading shader");
         // Load the shaders from disk
@@ -184,78 +1980,222 @@ impl App {
             source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader.wgsl"))),
         });
 
-        let texture_state = texture::TextureData::new(&device, &queue).unwrap();
+        let filter_preset = match std::env::var("TEXTURE_FILTER") {
+            Ok(name) => texture::FilterPreset::parse(&name).unwrap_or_else(|e| {
+                log::error!("Invalid TEXTURE_FILTER: {e:#}");
+                texture::FilterPreset::default()
+            }),
+            Err(_) => texture::FilterPreset::default(),
+        };
+        let normal_map_path = std::env::var("NORMAL_MAP").ok();
+        let texture_state = texture::TextureData::new(
+            &device,
+            &queue,
+            normal_map_path.as_deref().map(Path::new),
+            filter_preset,
+        )
+        .unwrap_or_else(|e| {
+            log::error!("Failed to load normal map, using a flat one: {e:#}");
+            texture::TextureData::new(&device, &queue, None, filter_preset).unwrap()
+        });
         let camera_state = camera::CameraState::new(&device);
+        let shadow_map_size = match std::env::var("SHADOW_MAP_SIZE") {
+            Ok(size) => size.trim().parse().unwrap_or_else(|_| {
+                log::error!("Invalid SHADOW_MAP_SIZE {size}, expected a size in texels");
+                shadow::DEFAULT_SHADOW_MAP_SIZE
+            }),
+            Err(_) => shadow::DEFAULT_SHADOW_MAP_SIZE,
+        };
+        let shadow = shadow::ShadowState::new(
+            &device,
+            shadow_map_size,
+            &texture_state.bind_group_layout,
+            &camera_state,
+        );
+        log::info!("Shadow map size: {0}x{0}", shadow.size());
+        let mut light_state = light::LightState::new(
+            &device,
+            &shadow::ShadowState::layout_entries(1),
+            &shadow.bind_group_entries(1),
+        );
+        // A warm and a cool light on opposite corners of the grid
+        light_state.add_light(light::PointLight::new(
+            [-6.0, 3.0, -6.0],
+            [1.0, 0.8, 0.6],
+            8.0,
+        ));
+        light_state.add_light(light::PointLight::new(
+            [6.0, 3.0, 6.0],
+            [0.5, 0.7, 1.0],
+            8.0,
+        ));
+        let depth_vis = depth_vis::DepthVisState::new(&device, target_format);
+        let skybox = skybox::SkyboxState::new(&device, target_format, sample_count);
+        let stencil_mask = StencilMask::new(&device, target_format, sample_count);
+        let axis_gizmo = axis::AxisGizmo::new(
+            &device,
+            &camera_state.bind_group_layout,
+            target_format,
+            sample_count,
+            Some(DepthMode::Standard.depth_stencil_state(TextureFormat::Depth32Float, false)),
+        );
+        let debug_draw = DebugDraw::new(
+            &device,
+            &camera_state.bind_group_layout,
+            target_format,
+            sample_count,
+            Some(DepthMode::Standard.depth_stencil_state(TextureFormat::Depth32Float, false)),
+            debug_draw_max_vertices_from_env(),
+        );
+        let tone_map = tone_map_from_env();
+        let post_process = postprocess::PostProcessState::new(&device, target_format, tone_map);
+        let wireframe_overlay = wireframe_overlay::WireframeOverlay::new(&device);
+
+        let user_bind_groups = bind_groups::UserBindGroups::new(&device);
 
         log::info!("WGPU: creating pipeline layout");
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[
-                &texture_state.bind_group_layout,
-                &camera_state.bind_group_layout,
-            ],
-            push_constant_ranges: &[],
+        let pipeline_layout = create_pipeline_layout(
+            &device,
+            &texture_state,
+            &camera_state,
+            &light_state,
+            &wireframe_overlay,
+            &user_bind_groups,
+        );
+
+        log::info!("WGPU: creating render pipeline");
+        let render_pipeline = create_render_pipeline(
+            &device,
+            &pipeline_layout,
+            &shader,
+            &pipeline_config,
+            target_format,
+            sample_count,
+            wgpu::PolygonMode::Fill,
+            false,
+            Some(DepthMode::Standard.depth_stencil_state(TextureFormat::Depth32Float, false)),
+        );
+        let wireframe_pipeline = supports_wireframe.then(|| {
+            create_render_pipeline(
+                &device,
+                &pipeline_layout,
+                &shader,
+                &pipeline_config,
+                target_format,
+                sample_count,
+                wgpu::PolygonMode::Line,
+                false,
+                Some(DepthMode::Standard.depth_stencil_state(TextureFormat::Depth32Float, false)),
+            )
+        });
+        let wireframe_overlay_pipeline = supports_wireframe.then(|| {
+            wireframe_overlay::create_pipeline(
+                &device,
+                &pipeline_layout,
+                &shader,
+                &pipeline_config,
+                target_format,
+                sample_count,
+                Some(wireframe_overlay.depth_stencil_state(
+                    DepthMode::Standard.depth_stencil_state(TextureFormat::Depth32Float, false),
+                    DepthMode::Standard,
+                )),
+            )
         });
+        let depth_prepass_pipeline = create_depth_prepass_pipeline(
+            &device,
+            &pipeline_layout,
+            &shader,
+            &pipeline_config,
+            sample_count,
+            Some(DepthMode::Standard.depth_stencil_state(TextureFormat::Depth32Float, false)),
+        );
+        let shadow_pipeline = shadow.create_pipeline(&device, &shader, &pipeline_config);
+        if let Some(error) = device.pop_error_scope().await {
+            anyhow::bail!("failed to build the render pipelines: {error}");
+        }
 
-        log::info!("WGPU: creating render pipeline");
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[data::VertexData::desc(), instance::InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(target_format.into())],
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        });
+        #[cfg(feature = "gpu-timing")]
+        let gpu_timer = gpu_timer::GpuTimer::new(&device, &queue);
 
-        RenderState {
+        Ok(RenderState {
             device,
             queue,
-            _shader: shader,
+            shader,
             target_format,
-            _pipeline_layout: pipeline_layout,
+            sample_count,
+            pipeline_layout,
+            pipeline_config,
             render_pipeline,
+            wireframe_pipeline,
+            wireframe_overlay_pipeline,
+            wireframe_overlay,
+            depth_prepass_pipeline,
+            shadow_pipeline,
+            shadow,
             texture_state,
+            filter_preset,
+            pending_texture: None,
             camera_state,
-        }
+            light_state,
+            user_bind_groups,
+            depth_vis,
+            skybox,
+            stencil_mask,
+            axis_gizmo,
+            debug_draw,
+            post_process,
+            tone_map,
+            #[cfg(all(debug_assertions, not(target_os = "android")))]
+            shader_watcher: match hot_reload::ShaderWatcher::new(SHADER_SOURCE_PATH) {
+                Ok(watcher) => {
+                    log::info!("Watching {SHADER_SOURCE_PATH} for changes");
+                    Some(watcher)
+                }
+                Err(e) => {
+                    log::warn!("Shader hot reloading disabled: {e}");
+                    None
+                }
+            },
+            #[cfg(feature = "gpu-timing")]
+            gpu_timer,
+            #[cfg(feature = "indirect-draw")]
+            indirect_draw,
+            #[cfg(feature = "gui")]
+            gui: None,
+            present_mode: wgpu::PresentMode::Fifo,
+            frames_in_flight: frame_pacing::FramesInFlight::new(
+                frame_pacing::DEFAULT_MAX_FRAMES_IN_FLIGHT,
+            ),
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            surface_size: winit::dpi::PhysicalSize::new(0, 0),
+            render_targets: None,
+            show_depth: false,
+            wireframe: false,
+            frustum_culling: false,
+            frame_stats: FrameStats::default(),
+            device_lost,
+            alpha_blending: false,
+            depth_prepass: false,
+            depth_test: true,
+            depth_mode: DepthMode::Standard,
+            stencil: None,
+            clear_color: wgpu::Color::BLUE,
+            scene_load_ops: SceneLoadOps::default(),
+            last_update: None,
+            fixed_frame_delta: None,
+        })
     }
 
     // We want to defer the initialization of our render state until
     // we have a surface so we can take its format into account.
     //
-    // After we've initialized our render state once though we
-    // expect all future surfaces will have the same format and we
-    // so this stat will remain valid.
-    async fn ensure_render_state_for_surface(&mut self) {
+    // After we've initialized our render state once it's kept for
+    // later surfaces, and only rebuilt for their format if that differs.
+    async fn ensure_render_state_for_surface(&mut self) -> anyhow::Result<()> {
         if let Some(surface_state) = &self.surface_state {
             if self.adapter.is_none() {
-                log::info!("WGPU: requesting a suitable adapter (compatible with our surface)");
-                let adapter = self
-                    .instance
-                    .request_adapter(&wgpu::RequestAdapterOptions {
-                        power_preference: wgpu::PowerPreference::default(),
-                        force_fallback_adapter: false,
-                        // Request an adapter which can render to our surface
-                        compatible_surface: Some(&surface_state.surface),
-                    })
-                    .await
-                    .expect("Failed to find an appropriate adapter");
-
+                let adapter = request_adapter(&self.instance, Some(&surface_state.surface)).await?;
                 self.adapter = Some(adapter);
             }
             let adapter: &Adapter = self.adapter.as_ref().unwrap();
@@ -263,40 +2203,90 @@ impl App {
             if self.render_state.is_none() {
                 log::info!("WGPU: finding supported swapchain format");
                 let surface_caps = surface_state.surface.get_capabilities(adapter);
-                let swapchain_format = surface_caps.formats[0];
-                let rs = Self::init_render_state(adapter, swapchain_format).await;
+                let swapchain_format =
+                    choose_swapchain_format(&surface_caps.formats, self.hdr_output);
+                let mut rs = Self::init_render_state(
+                    adapter,
+                    swapchain_format,
+                    self.pipeline_config.clone(),
+                )
+                .await?;
+                rs.present_mode =
+                    choose_present_mode(&surface_caps.present_modes, &self.present_modes);
+                rs.set_alpha_mode(choose_alpha_mode(
+                    &surface_caps.alpha_modes,
+                    self.alpha_mode,
+                ));
+                #[cfg(feature = "gui")]
+                {
+                    rs.gui = Some(gui::GuiState::new(
+                        &surface_state.window,
+                        &rs.device,
+                        swapchain_format,
+                    ));
+                }
+                if let Some(cutoff) = self.alpha_cutoff {
+                    if let Err(e) = rs.set_alpha_cutoff(cutoff) {
+                        log::error!("{e}");
+                    }
+                }
+                if let Err(e) = rs.set_exposure(self.exposure) {
+                    log::error!("{e}");
+                }
+                if !self.depth_test {
+                    rs.set_depth_test(false);
+                }
+                rs.camera_state.globals.enabled = self.shader_globals;
+                rs.scene_load_ops = self.scene_load_ops;
+                if let Some(up) = self.world_up {
+                    if let Err(e) = rs.camera_state.set_up(up) {
+                        log::error!("Keeping Y up: {e:#}");
+                    }
+                }
+                if let Err(e) = rs.camera_state.set_viewports(&rs.device, &self.viewports) {
+                    log::error!("Not splitting the screen: {e:#}");
+                }
+                rs.frames_in_flight.set_max(self.max_frames_in_flight);
+                // Initialize the meshes once
+                self.meshes =
+                    rs.load_scene(self.instance_seed, self.instance_layout, self.instance_spin);
+                if let Some(faces) = &self.skybox_faces {
+                    if let Err(e) = rs.set_skybox(faces.clone()) {
+                        log::error!("Failed to set the skybox: {e:#}");
+                    }
+                }
+                if let Some(topology) = self.topology {
+                    if let Err(e) = set_mesh_topology(&mut rs, &self.meshes, topology) {
+                        log::error!("Keeping triangle lists: {e:#}");
+                    }
+                }
                 self.render_state = Some(rs);
-
-                // Initialize vertex and instance state once
-                if let Some(ref render_state) = self.render_state {
-                    self.vertex_state = Some(data::VertexState::new(&render_state.device));
-                    self.instance_state = Some(InstanceState::new(&render_state.device));
+            } else if let Some(rs) = &mut self.render_state {
+                let surface_caps = surface_state.surface.get_capabilities(adapter);
+                let swapchain_format =
+                    choose_swapchain_format(&surface_caps.formats, self.hdr_output);
+                if swapchain_format != rs.target_format {
+                    rs.rebuild_for_format(swapchain_format);
+                    #[cfg(feature = "gui")]
+                    {
+                        rs.gui = Some(gui::GuiState::new(
+                            &surface_state.window,
+                            &rs.device,
+                            swapchain_format,
+                        ));
+                    }
                 }
             }
         }
+        Ok(())
     }
 
     fn configure_surface_swapchain(&mut self) {
-        if let (Some(render_state), Some(surface_state)) = (&self.render_state, &self.surface_state)
+        if let (Some(render_state), Some(surface_state)) =
+            (&mut self.render_state, &self.surface_state)
         {
-            let swapchain_format = render_state.target_format;
             let size = surface_state.window.inner_size();
-
-            let config = wgpu::SurfaceConfiguration {
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                format: swapchain_format,
-                width: size.width,
-                height: size.height,
-                //present_mode: wgpu::PresentMode::Mailbox,
-                present_mode: wgpu::PresentMode::Fifo,
-                alpha_mode: wgpu::CompositeAlphaMode::Opaque,
-                view_formats: vec![swapchain_format],
-            };
-
-            log::info!("WGPU: Configuring surface swapchain: format = {swapchain_format:?}, size = {size:?}");
-            surface_state
-                .surface
-                .configure(&render_state.device, &config);
+            render_state.configure_surface(&surface_state.surface, size);
         }
     }
 
@@ -307,100 +2297,954 @@ impl App {
         }
     }
 
-    fn resume<T>(&mut self, event_loop: &EventLoopWindowTarget<T>) {
+    // Borderless on `fullscreen_monitor`, falling back to the primary monitor
+    // when there's no monitor with that index
+    fn fullscreen_mode(&self, window: &winit::window::Window) -> Fullscreen {
+        let Some(index) = self.fullscreen_monitor else {
+            return Fullscreen::Borderless(None);
+        };
+        let monitor = window.available_monitors().nth(index).or_else(|| {
+            log::warn!(
+                "There's no monitor {index}, only {}, using the primary monitor",
+                window.available_monitors().count()
+            );
+            window.primary_monitor()
+        });
+        Fullscreen::Borderless(monitor)
+    }
+
+    fn toggle_fullscreen(&self) {
+        let Some(surface_state) = &self.surface_state else {
+            return;
+        };
+        let window = &surface_state.window;
+        if window.fullscreen().is_some() {
+            window.set_fullscreen(None);
+            log::info!("Leaving fullscreen");
+        } else {
+            let mode = self.fullscreen_mode(window);
+            log::info!("Going fullscreen: {mode:?}");
+            window.set_fullscreen(Some(mode));
+        }
+    }
+
+    // Pauses rendering when the window loses focus and `pause_when_unfocused`
+    // is set, and picks it up again without a jump in time once it's back
+    fn set_focused(&mut self, focused: bool) {
+        let paused = !focused && self.pause_when_unfocused;
+        if paused == self.paused {
+            return;
+        }
+        self.paused = paused;
+        if paused {
+            log::info!("Window lost focus, pausing rendering");
+            self.next_frame = None;
+        } else {
+            log::info!("Resuming rendering");
+            if let Some(render_state) = &mut self.render_state {
+                render_state.restart_clock();
+            }
+            self.queue_redraw();
+        }
+    }
+
+    // Locked keeps the cursor where it is, platforms without it (X11) can only
+    // confine it to the window. Either way it's hidden and stops mattering, the
+    // look deltas come from `DeviceEvent::MouseMotion`.
+    fn set_mouse_look(&mut self, enabled: bool) {
+        if self.mouse_look == enabled {
+            return;
+        }
+        self.mouse_look = enabled;
+        let Some(surface_state) = &self.surface_state else {
+            return;
+        };
+        let window = &surface_state.window;
+        let grab = if enabled {
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(err) = grab {
+            log::warn!("Failed to change the cursor grab: {err}");
+        }
+        window.set_cursor_visible(!enabled);
+        log::debug!("Mouse look: {enabled}");
+    }
+
+    fn handle_key(&mut self, key: &Key) {
+        // Works before there's anything to render
+        if *key == Key::Named(NamedKey::F11) {
+            self.toggle_fullscreen();
+            return;
+        }
+        let Some(render_state) = &mut self.render_state else {
+            return;
+        };
+        if self
+            .light_controller
+            .handle_key(&mut render_state.light_state, key)
+        {
+            log::debug!(
+                "Light direction: {:?}",
+                render_state.light_state.direction()
+            );
+            return;
+        }
+
+        // Letters are bound the same with Shift or Caps Lock
+        let lowercase;
+        let key = match key.as_ref() {
+            Key::Character(text) => {
+                lowercase = text.to_lowercase();
+                Key::Character(lowercase.as_str())
+            }
+            key => key,
+        };
+        match key {
+            Key::Character("z") => {
+                if !render_state.depth_test {
+                    log::warn!("There's no depth buffer to show with DEPTH_TEST off");
+                    return;
+                }
+                // A multisampled depth buffer can't be sampled like a plain one
+                if render_state.sample_count > 1 {
+                    log::warn!("The depth visualization isn't supported with MSAA_SAMPLES");
+                    return;
+                }
+                render_state.show_depth = !render_state.show_depth;
+                log::info!("Depth visualization: {}", render_state.show_depth);
+            }
+            Key::Character("w") => {
+                if render_state.wireframe_pipeline.is_none() {
+                    log::warn!("Wireframe mode isn't supported on this adapter");
+                    return;
+                }
+                render_state.wireframe = !render_state.wireframe;
+                log::info!("Wireframe: {}", render_state.wireframe);
+            }
+            Key::Character("o") => {
+                if render_state.wireframe_overlay_pipeline.is_none() {
+                    log::warn!("The wireframe overlay isn't supported on this adapter");
+                    return;
+                }
+                let overlay = &mut render_state.wireframe_overlay;
+                overlay.enabled = !overlay.enabled;
+                log::info!(
+                    "Wireframe overlay: {}, color = {:?}, bias = {:?}",
+                    overlay.enabled,
+                    overlay.color(),
+                    overlay.depth_bias()
+                );
+            }
+            Key::Named(NamedKey::F5) => {
+                let config = render_state.camera_state.camera().to_config();
+                match config.save(CAMERA_CONFIG_PATH) {
+                    Ok(()) => log::info!("Saved camera to {CAMERA_CONFIG_PATH}"),
+                    Err(e) => log::error!("Failed to save camera to {CAMERA_CONFIG_PATH}: {e}"),
+                }
+                let window = self.surface_state.as_ref().map(|state| &state.window);
+                if let Some(position) = window.and_then(outer_position) {
+                    match WindowConfig::from(position).save(WINDOW_CONFIG_PATH) {
+                        Ok(()) => log::info!("Saved window position to {WINDOW_CONFIG_PATH}"),
+                        Err(e) => {
+                            log::error!(
+                                "Failed to save window position to {WINDOW_CONFIG_PATH}: {e}"
+                            )
+                        }
+                    }
+                }
+            }
+            Key::Named(NamedKey::F9) => {
+                match camera::CameraConfig::load(CAMERA_CONFIG_PATH)
+                    .and_then(camera::Camera::from_config)
+                {
+                    Ok(camera) => {
+                        *render_state.camera_state.camera_mut() = camera;
+                        log::info!("Loaded camera from {CAMERA_CONFIG_PATH}");
+                    }
+                    Err(e) => log::warn!("Ignoring camera config {CAMERA_CONFIG_PATH}: {e}"),
+                }
+                // Only there when the platform told where the window was
+                if let Ok(config) = WindowConfig::load(WINDOW_CONFIG_PATH) {
+                    let position = config.position();
+                    self.window_position = Some(position);
+                    if let Some(surface_state) = &self.surface_state {
+                        surface_state.window.set_outer_position(position);
+                    }
+                    log::info!("Loaded window position from {WINDOW_CONFIG_PATH}");
+                }
+            }
+            Key::Character("f") => {
+                render_state.frustum_culling = !render_state.frustum_culling;
+                log::info!("Frustum culling: {}", render_state.frustum_culling);
+            }
+            Key::Character("g") => {
+                let post_process = &mut render_state.post_process;
+                post_process.effect = match post_process.effect {
+                    postprocess::PostEffect::Grayscale => postprocess::PostEffect::None,
+                    _ => postprocess::PostEffect::Grayscale,
+                };
+                log::info!("Post effect: {:?}", post_process.effect);
+            }
+            Key::Character("b") => {
+                render_state.set_alpha_blending(!render_state.alpha_blending);
+                log::info!("Alpha blending: {}", render_state.alpha_blending);
+            }
+            Key::Character("t") => {
+                let controller = &mut self.camera_controller;
+                controller.mode = match controller.mode {
+                    camera::ControllerMode::Orbit => camera::ControllerMode::Arcball,
+                    camera::ControllerMode::Arcball => camera::ControllerMode::Orbit,
+                };
+                log::info!("Camera controller: {:?}", controller.mode);
+            }
+            Key::Character("p") => {
+                render_state.set_depth_prepass(!render_state.depth_prepass);
+                log::info!("Depth pre-pass: {}", render_state.depth_prepass);
+            }
+            Key::Character("r") => {
+                let depth_mode = match render_state.depth_mode {
+                    DepthMode::Standard => DepthMode::ReverseZ,
+                    DepthMode::ReverseZ => DepthMode::Standard,
+                };
+                render_state.set_depth_mode(depth_mode);
+                log::info!("Depth mode: {depth_mode:?}");
+            }
+            Key::Character("k") => {
+                let cull_mode = match render_state.pipeline_config.cull_mode {
+                    Some(_) => None,
+                    None => Some(wgpu::Face::Back),
+                };
+                render_state.set_cull_mode(cull_mode);
+                log::info!("Cull mode: {cull_mode:?}");
+            }
+            Key::Character("m") => {
+                let stencil = match render_state.stencil {
+                    Some(_) => None,
+                    None => Some(StencilConfig::masked(1)),
+                };
+                match render_state.set_stencil(stencil) {
+                    Ok(()) => log::info!("Stencil mask: {}", stencil.is_some()),
+                    Err(e) => log::warn!("{e}"),
+                }
+            }
+            // `=` is `+` without Shift on US layouts
+            Key::Character("+" | "=" | "-") => {
+                let size = self.instance_layout.size();
+                let size = match key {
+                    Key::Character("+" | "=") => size + 1,
+                    _ => size.saturating_sub(1),
+                };
+                self.instance_layout = self.instance_layout.with_size(size);
+                for (index, mesh) in self.meshes.iter_mut().enumerate() {
+                    if let Err(e) = mesh
+                        .instances
+                        .set_layout(self.instance_layout, self.instance_seed)
+                    {
+                        log::warn!("Keeping mesh {index}'s instances: {e}");
+                    }
+                }
+                let num_instances = self
+                    .meshes
+                    .iter()
+                    .map(|mesh| mesh.instances.num_instances())
+                    .sum::<u32>();
+                log::info!(
+                    "Instance layout: {:?}, {num_instances} instances",
+                    self.instance_layout
+                );
+            }
+            Key::Character("x") => {
+                render_state.axis_gizmo.enabled = !render_state.axis_gizmo.enabled;
+                log::info!("Axis gizmo: {}", render_state.axis_gizmo.enabled);
+            }
+            Key::Character("l") => {
+                render_state.shadow.enabled = !render_state.shadow.enabled;
+                log::info!("Shadows: {}", render_state.shadow.enabled);
+            }
+            Key::Character("h") => {
+                let Some(bounds) = self
+                    .meshes
+                    .iter()
+                    .filter_map(Mesh::bounding_box)
+                    .reduce(instance::merge_bounds)
+                else {
+                    return;
+                };
+                render_state
+                    .camera_state
+                    .camera_mut()
+                    .frame_bounds(bounds.0, bounds.1);
+                log::info!("Framed the scene, bounds = {bounds:?}");
+            }
+            Key::Character("c") => {
+                let index = render_state.camera_state.next_camera();
+                log::info!("Active camera: {index}");
+            }
+            // Select the cameras directly, in order
+            Key::Character(digit) => {
+                let Some(index) = digit
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|number| (1..=9).contains(number))
+                    .map(|number| number - 1)
+                else {
+                    return;
+                };
+                match render_state.camera_state.set_active(index) {
+                    Ok(()) => log::info!("Active camera: {index}"),
+                    Err(e) => log::warn!("{e}"),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Everything created from the device is invalid once it's lost, so throw away the
+    // render state (and the adapter, in case the GPU itself went away) and start over.
+    // The surface belongs to the instance and can be kept.
+    fn recover_from_device_loss(&mut self) {
+        log::warn!("WGPU: device lost, rebuilding render state...");
+        self.render_state = None;
+        self.meshes.clear();
+        self.adapter = None;
+
+        if let Err(e) = pollster::block_on(self.ensure_render_state_for_surface()) {
+            log::error!("Failed to rebuild the render state: {e:#}");
+        }
+        self.configure_surface_swapchain();
+    }
+
+    fn pick_at_cursor(&self) {
+        let (Some(size), Some(render_state), Some(cursor)) = (
+            self.current_size(),
+            &self.render_state,
+            self.cursor_position,
+        ) else {
+            return;
+        };
+
+        let (origin, direction) = render_state
+            .camera_state
+            .screen_ray((cursor.x as f32, cursor.y as f32), size);
+        // The nearest hit over all meshes
+        let hit = self
+            .meshes
+            .iter()
+            .enumerate()
+            .filter_map(|(mesh, m)| m.pick(origin, direction).map(|(index, t)| (mesh, index, t)))
+            .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+        match hit {
+            Some((mesh, index, _)) => log::info!("Picked instance {index} of mesh {mesh}"),
+            None => log::info!("Picked nothing"),
+        }
+    }
+
+    fn schedule_next_frame(&mut self, frame_start: Instant) {
+        if self.paused {
+            return;
+        }
+        match self.target_fps {
+            Some(fps) if fps > 0 => {
+                let deadline = frame_start + Duration::from_secs_f64(1.0 / fps as f64);
+                if deadline <= Instant::now() {
+                    self.queue_redraw();
+                } else {
+                    self.next_frame = Some(deadline);
+                }
+            }
+            _ => self.queue_redraw(),
+        }
+    }
+
+    fn resume(&mut self, event_loop: &ActiveEventLoop) -> anyhow::Result<()> {
         log::info!("Resumed, creating render state...");
-        self.create_surface(event_loop);
-        pollster::block_on(self.ensure_render_state_for_surface());
+        self.create_surface(event_loop)?;
+        pollster::block_on(self.ensure_render_state_for_surface())?;
         self.configure_surface_swapchain();
 
         self.queue_redraw();
+        Ok(())
+    }
+
+    fn redraw(&mut self) {
+        let frame_start = Instant::now();
+        if std::mem::take(&mut self.resize_pending) {
+            self.configure_surface_swapchain();
+        }
+        if self
+            .render_state
+            .as_ref()
+            .is_some_and(RenderState::is_device_lost)
+        {
+            self.recover_from_device_loss();
+        }
+
+        let (Some(surface_state), Some(rs)) = (&self.surface_state, &mut self.render_state) else {
+            return;
+        };
+        // Minimized, nothing is drawn until a resize gives the window a size again
+        let size = surface_state.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        let frame = match surface_state.surface.get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Outdated) => {
+                log::info!("Surface outdated during redraw, skipping frame");
+                surface_state.window.request_redraw();
+                return;
+            }
+            Err(wgpu::SurfaceError::Lost) => {
+                log::warn!("Surface lost during redraw, reconfiguring swapchain");
+                self.configure_surface_swapchain();
+                self.queue_redraw();
+                return;
+            }
+            Err(e) => {
+                log::error!("Failed to acquire surface texture: {}", e);
+                return;
+            }
+        };
+
+        if self.paused {
+            rs.restart_clock();
+        }
+        let dt = rs.advance_clock();
+        if let Some(on_update) = &mut self.on_update {
+            let mut context = AppContext::new(
+                rs.camera_state.camera_mut(),
+                &mut self.meshes,
+                &rs.device,
+                &rs.queue,
+                &mut rs.user_bind_groups,
+                &mut rs.debug_draw,
+            );
+            on_update(&mut context, dt.as_secs_f32());
+            rs.rebuild_pipelines_if_bind_groups_changed();
+        }
+        #[cfg(feature = "gui")]
+        rs.run_gui(&surface_state.window, &mut self.meshes);
+        if let Err(e) = rs.draw_frame(frame, &mut self.meshes, dt) {
+            log::error!("Frame rendering failed: {}", e);
+        }
+        self.stats_log.frame(&rs.frame_stats);
+        #[cfg(feature = "gpu-timing")]
+        if let Some(ms) = rs.gpu_frame_time_ms() {
+            trace!("GPU frame time: {ms:.3} ms");
+        }
+        // The next frame only starts, and samples input, once the GPU has room for it
+        rs.frames_in_flight.wait_for_slot(&rs.device);
+        self.schedule_next_frame(frame_start);
     }
 }
 
-fn run(mut event_loop: EventLoop<()>) {
-    log::info!("Running mainloop...");
+fn parse_backend(name: &str) -> Option<wgpu::Backends> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "vulkan" => Some(wgpu::Backends::VULKAN),
+        "gl" => Some(wgpu::Backends::GL),
+        "dx12" => Some(wgpu::Backends::DX12),
+        "metal" => Some(wgpu::Backends::METAL),
+        _ => None,
+    }
+}
 
-    // doesn't need to be re-considered later
-    let instance = Instance::new(wgpu::InstanceDescriptor {
-        backends: wgpu::Backends::all(),
-        //backends: wgpu::Backends::VULKAN,
-        //backends: wgpu::Backends::GL,
-        ..Default::default()
-    });
+// Lets a specific backend be forced for debugging driver issues without recompiling
+fn backends_from_env() -> wgpu::Backends {
+    let Ok(name) = std::env::var("WGPU_BACKEND") else {
+        return wgpu::Backends::all();
+    };
+    match parse_backend(&name) {
+        Some(backends) => {
+            log::info!("WGPU: using backend {name} from WGPU_BACKEND");
+            backends
+        }
+        None => {
+            log::warn!("WGPU: unknown WGPU_BACKEND \"{name}\", expected vulkan, gl, dx12 or metal");
+            wgpu::Backends::all()
+        }
+    }
+}
 
-    let mut app = App::new(instance);
+// Lowest latency first. Mailbox replaces a queued frame instead of waiting for it
+// to be shown, Immediate doesn't wait for vblank at all and may tear, and Fifo
+// (plain vsync) is the only mode every surface supports.
+const DEFAULT_PRESENT_MODES: [wgpu::PresentMode; 3] = [
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+    wgpu::PresentMode::Fifo,
+];
 
-    // It's not recommended to use `run` on Android because it will call
-    // `std::process::exit` when finished which will short-circuit any
-    // Java lifecycle handling
-    event_loop.run_return(move |event, event_loop, control_flow| {
-        // log::info!("Received Winit event: {event:?}");
+// The first preferred mode the surface supports, Fifo when there's none
+fn choose_present_mode(
+    supported: &[wgpu::PresentMode],
+    preference: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    preference
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(wgpu::PresentMode::Fifo)
+}
 
-        *control_flow = ControlFlow::Wait;
-        match event {
-            Event::Resumed => {
-                app.resume(event_loop);
+// `preferred` when the surface supports it, otherwise Opaque or, on surfaces
+// without it, whatever they support first
+fn choose_alpha_mode(
+    supported: &[wgpu::CompositeAlphaMode],
+    preferred: wgpu::CompositeAlphaMode,
+) -> wgpu::CompositeAlphaMode {
+    if supported.contains(&preferred) {
+        return preferred;
+    }
+    log::warn!(
+        "WGPU: the surface doesn't support the {preferred:?} alpha mode, only {supported:?}"
+    );
+    if supported.contains(&wgpu::CompositeAlphaMode::Opaque) {
+        wgpu::CompositeAlphaMode::Opaque
+    } else {
+        supported[0]
+    }
+}
+
+// e.g. `ALPHA_MODE=premultiplied` to draw over a transparent window, Opaque when unset
+fn alpha_mode_from_env() -> wgpu::CompositeAlphaMode {
+    let Ok(name) = std::env::var("ALPHA_MODE") else {
+        return wgpu::CompositeAlphaMode::Opaque;
+    };
+    match name.trim().to_ascii_lowercase().as_str() {
+        "opaque" => wgpu::CompositeAlphaMode::Opaque,
+        "premultiplied" => wgpu::CompositeAlphaMode::PreMultiplied,
+        "postmultiplied" => wgpu::CompositeAlphaMode::PostMultiplied,
+        "inherit" => wgpu::CompositeAlphaMode::Inherit,
+        _ => {
+            log::warn!(
+                "WGPU: unknown alpha mode \"{name}\" in ALPHA_MODE, expected opaque, premultiplied, postmultiplied or inherit"
+            );
+            wgpu::CompositeAlphaMode::Opaque
+        }
+    }
+}
+
+fn parse_topology(name: &str) -> Option<wgpu::PrimitiveTopology> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "point_list" => Some(wgpu::PrimitiveTopology::PointList),
+        "line_list" => Some(wgpu::PrimitiveTopology::LineList),
+        "line_strip" => Some(wgpu::PrimitiveTopology::LineStrip),
+        "triangle_list" => Some(wgpu::PrimitiveTopology::TriangleList),
+        "triangle_strip" => Some(wgpu::PrimitiveTopology::TriangleStrip),
+        _ => None,
+    }
+}
+
+// Strips take their index format from the meshes, so they all need the same one
+fn set_mesh_topology(
+    render_state: &mut RenderState,
+    meshes: &[Mesh],
+    topology: wgpu::PrimitiveTopology,
+) -> anyhow::Result<()> {
+    let strip_index_format = if topology.is_strip() {
+        let mut formats = meshes.iter().map(|mesh| mesh.geometry.index_format);
+        let format = formats.next();
+        anyhow::ensure!(
+            formats.all(|other| Some(other) == format),
+            "strips need every mesh to have the same index format"
+        );
+        format
+    } else {
+        None
+    };
+    render_state.set_topology(topology, strip_index_format)
+}
+
+// `y`, `z` or any other axis as `x,y,z`
+fn parse_world_up(spec: &str) -> Option<cgmath::Vector3<f32>> {
+    match spec.trim().to_ascii_lowercase().as_str() {
+        "y" => Some(cgmath::Vector3::unit_y()),
+        "z" => Some(cgmath::Vector3::unit_z()),
+        axis => instance::parse_axis(axis).ok(),
+    }
+}
+
+fn parse_present_mode(name: &str) -> Option<wgpu::PresentMode> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "mailbox" => Some(wgpu::PresentMode::Mailbox),
+        "immediate" => Some(wgpu::PresentMode::Immediate),
+        "fifo" => Some(wgpu::PresentMode::Fifo),
+        "fifo_relaxed" => Some(wgpu::PresentMode::FifoRelaxed),
+        _ => None,
+    }
+}
+
+// 1 for the lowest latency, more for throughput when the CPU and GPU take turns
+fn max_frames_in_flight_from_env() -> u32 {
+    let Ok(value) = std::env::var("MAX_FRAMES_IN_FLIGHT") else {
+        return frame_pacing::DEFAULT_MAX_FRAMES_IN_FLIGHT;
+    };
+    value.trim().parse().unwrap_or_else(|_| {
+        log::warn!("Invalid MAX_FRAMES_IN_FLIGHT {value}, expected a whole number");
+        frame_pacing::DEFAULT_MAX_FRAMES_IN_FLIGHT
+    })
+}
+
+// Share of the primary monitor's width and height the window opens at
+const DEFAULT_WINDOW_SIZE_PERCENT: f64 = 75.0;
+// Used when there's no primary monitor to size the window by, e.g. headless or
+// on Wayland, which doesn't have one
+const FALLBACK_WINDOW_SIZE: LogicalSize<f64> = LogicalSize::new(1280.0, 720.0);
+
+// None for `off`, which leaves the size to the platform
+fn parse_window_size_percent(value: &str) -> Option<Option<f64>> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("off") {
+        return Some(None);
+    }
+    let percent: f64 = value.parse().ok()?;
+    (percent > 0.0 && percent <= 100.0).then_some(Some(percent))
+}
+
+// `x,y`, negative on monitors left of or above the primary one
+fn parse_window_position(spec: &str) -> Option<PhysicalPosition<i32>> {
+    let (x, y) = spec.split_once(',')?;
+    Some(PhysicalPosition::new(
+        x.trim().parse().ok()?,
+        y.trim().parse().ok()?,
+    ))
+}
+
+// Logs and ignores the platforms that can't tell where the window is
+fn outer_position(window: &winit::window::Window) -> Option<PhysicalPosition<i32>> {
+    window
+        .outer_position()
+        .map_err(|e| log::warn!("Can't get the window position: {e}"))
+        .ok()
+}
+
+// Where the window was, in the same kind of file as `camera::CameraConfig`
+#[derive(Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct WindowConfig {
+    // Outer position in physical pixels
+    position: [i32; 2],
+}
+
+impl WindowConfig {
+    fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    fn position(&self) -> PhysicalPosition<i32> {
+        PhysicalPosition::new(self.position[0], self.position[1])
+    }
+}
+
+impl From<PhysicalPosition<i32>> for WindowConfig {
+    fn from(position: PhysicalPosition<i32>) -> Self {
+        Self {
+            position: [position.x, position.y],
+        }
+    }
+}
+
+// Logical, so the window covers the same share of a high DPI monitor
+fn initial_window_size(event_loop: &ActiveEventLoop, percent: f64) -> LogicalSize<f64> {
+    let Some(monitor) = event_loop.primary_monitor() else {
+        return FALLBACK_WINDOW_SIZE;
+    };
+    let size = monitor.size().to_logical::<f64>(monitor.scale_factor());
+    if size.width == 0.0 || size.height == 0.0 {
+        return FALLBACK_WINDOW_SIZE;
+    }
+    LogicalSize::new(size.width * percent / 100.0, size.height * percent / 100.0)
+}
+
+fn debug_draw_max_vertices_from_env() -> usize {
+    let Ok(value) = std::env::var("DEBUG_DRAW_MAX_VERTICES") else {
+        return debug_draw::DEFAULT_MAX_VERTICES;
+    };
+    value.trim().parse().unwrap_or_else(|_| {
+        log::warn!("Invalid DEBUG_DRAW_MAX_VERTICES {value}, expected a vertex count");
+        debug_draw::DEFAULT_MAX_VERTICES
+    })
+}
+
+fn hdr_output_from_env() -> bool {
+    std::env::var("HDR_OUTPUT").is_ok_and(|value| {
+        matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "on" | "true"
+        )
+    })
+}
+
+fn tone_map_from_env() -> Option<postprocess::ToneMap> {
+    let name = std::env::var("TONE_MAP").ok()?;
+    postprocess::ToneMap::parse(&name)
+        .map_err(|e| log::warn!("Invalid TONE_MAP {name}, {e}"))
+        .ok()
+}
+
+// Negative exposure would flip the colors, 0 draws the meshes black
+fn check_exposure(exposure: f32) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        exposure.is_finite() && exposure >= 0.0,
+        "exposure {exposure} has to be a finite number of at least 0"
+    );
+    Ok(())
+}
+
+// 1, no multisampling, when unset
+fn msaa_samples_from_env() -> u32 {
+    let Ok(value) = std::env::var("MSAA_SAMPLES") else {
+        return 1;
+    };
+    value.trim().parse().unwrap_or_else(|_| {
+        log::warn!("Invalid MSAA_SAMPLES {value}, expected a sample count like 4");
+        1
+    })
+}
+
+// Falls back to 1 unless every format the scene pass draws to, including both
+// depth formats it can switch between, can take `requested` samples
+fn choose_sample_count(adapter: &Adapter, target_format: TextureFormat, requested: u32) -> u32 {
+    if requested <= 1 {
+        return 1;
+    }
+    let adapter_format_features = adapter
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    let supported = (requested == 4 || adapter_format_features)
+        && [
+            target_format,
+            TextureFormat::Depth32Float,
+            stencil::DEPTH_STENCIL_FORMAT,
+        ]
+        .into_iter()
+        .all(|format| {
+            adapter
+                .get_texture_format_features(format)
+                .flags
+                .sample_count_supported(requested)
+        });
+    if supported {
+        requested
+    } else {
+        log::warn!("{requested}x MSAA isn't supported for {target_format:?}, not multisampling");
+        1
+    }
+}
+
+// A comma separated preference list, e.g. `PRESENT_MODES=fifo` for plain vsync
+fn present_modes_from_env() -> Vec<wgpu::PresentMode> {
+    let Ok(names) = std::env::var("PRESENT_MODES") else {
+        return DEFAULT_PRESENT_MODES.to_vec();
+    };
+    let modes: Vec<_> = names
+        .split(',')
+        .filter_map(|name| {
+            let mode = parse_present_mode(name);
+            if mode.is_none() {
+                log::warn!(
+                    "WGPU: unknown present mode \"{name}\" in PRESENT_MODES, expected mailbox, immediate, fifo or fifo_relaxed"
+                );
             }
-            Event::Suspended => {
-                log::info!("Suspended, dropping render state...");
-                app.render_state = None;
-                app.vertex_state = None;
-                app.instance_state = None;
+            mode
+        })
+        .collect();
+    if modes.is_empty() {
+        DEFAULT_PRESENT_MODES.to_vec()
+    } else {
+        modes
+    }
+}
+
+impl ApplicationHandler for App {
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            self.next_frame = None;
+            self.queue_redraw();
+        }
+    }
+
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if let Err(e) = self.resume(event_loop) {
+            log::error!("Failed to start rendering, exiting: {e:#}");
+            event_loop.exit();
+        }
+    }
+
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        log::info!("Suspended, dropping render state...");
+        self.set_mouse_look(false);
+        self.render_state = None;
+        self.meshes.clear();
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        // Input the debug panel uses doesn't reach the camera or the key bindings
+        #[cfg(feature = "gui")]
+        if let Some(gui) = self.render_state.as_mut().and_then(|rs| rs.gui.as_mut()) {
+            if gui.on_event(&event) {
+                return;
             }
-            Event::WindowEvent {
-                event: WindowEvent::Resized(_size),
-                ..
-            } => {
-                app.configure_surface_swapchain();
+        }
+
+        match event {
+            WindowEvent::Resized(_size) => {
+                self.resize_pending = true;
                 // Winit: doesn't currently implicitly request a redraw
                 // for a resize which may be required on some platforms...
-                app.queue_redraw();
-            }
-            Event::RedrawRequested(_) => {
-                if let (
-                    Some(ref surface_state),
-                    Some(ref mut rs),
-                    Some(ref vertex_state),
-                    Some(ref mut instance_state),
-                ) = (
-                    &app.surface_state,
-                    &mut app.render_state,
-                    &app.vertex_state,
-                    &mut app.instance_state,
-                ) {
-                    let frame = match surface_state.surface.get_current_texture() {
-                        Ok(frame) => frame,
-                        Err(wgpu::SurfaceError::Outdated) => {
-                            log::info!("Surface outdated during redraw, skipping frame");
-                            surface_state.window.request_redraw();
-                            return;
-                        }
-                        Err(e) => {
-                            log::error!("Failed to acquire surface texture: {}", e);
-                            return;
-                        }
-                    };
-                    
-                    if let Err(e) = rs.draw_frame(frame, vertex_state, instance_state) {
-                        log::error!("Frame rendering failed: {}", e);
+                self.queue_redraw();
+            }
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                mut inner_size_writer,
+            } => {
+                // Fires when moving between monitors with different DPI, or when
+                // the OS scaling changes. The window keeps its logical size, the
+                // surface is resized to match right away since not every platform
+                // follows up with `Resized`.
+                log::info!("Scale factor changed to {scale_factor}");
+                let previous = std::mem::replace(&mut self.scale_factor, scale_factor);
+                if let (Some(render_state), Some(surface_state)) =
+                    (&mut self.render_state, &self.surface_state)
+                {
+                    let size = surface_state
+                        .window
+                        .inner_size()
+                        .to_logical::<f64>(previous)
+                        .to_physical(scale_factor);
+                    if let Err(e) = inner_size_writer.request_inner_size(size) {
+                        log::warn!("Can't resize the window for the new scale factor: {e}");
                     }
-                    surface_state.window.request_redraw();
+                    render_state.configure_surface(&surface_state.surface, size);
                 }
+                self.queue_redraw();
             }
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key: key,
+                        state: ElementState::Pressed,
+                        ..
+                    },
                 ..
-            } => *control_flow = ControlFlow::Exit,
-            Event::WindowEvent { event: _, .. } => {
-                log::info!("Window event {:#?}", event);
+            } => {
+                self.handle_key(&key);
             }
-            _ => {}
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some(position);
+                let cursor = (position.x as f32, position.y as f32);
+                if let (Some(size), Some(render_state)) =
+                    (self.current_size(), &mut self.render_state)
+                {
+                    // Only one of them has a drag going, both track the cursor
+                    self.light_controller
+                        .cursor_moved(&mut render_state.light_state, cursor);
+                    self.camera_controller.cursor_moved(
+                        render_state.camera_state.camera_mut(),
+                        cursor,
+                        size,
+                    );
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = state == ElementState::Pressed;
+                // Shift + left drag turns the light, the release ends it even
+                // when Shift was let go first
+                let light_drag = if pressed {
+                    self.modifiers.shift_key()
+                } else {
+                    self.light_controller.is_dragging()
+                };
+                if button == MouseButton::Left && light_drag {
+                    self.light_controller.set_drag(pressed);
+                    return;
+                }
+                // Ctrl + right held looks around, same as the light drag
+                let look = if pressed {
+                    self.modifiers.control_key()
+                } else {
+                    self.mouse_look
+                };
+                if button == MouseButton::Right && look {
+                    self.set_mouse_look(pressed);
+                    return;
+                }
+                let action = match button {
+                    MouseButton::Left => camera::DragAction::Rotate,
+                    MouseButton::Middle => camera::DragAction::Pan,
+                    MouseButton::Right => camera::DragAction::Zoom,
+                    _ => return,
+                };
+                if pressed && button == MouseButton::Left {
+                    self.pick_at_cursor();
+                }
+                self.camera_controller.set_drag(action, pressed);
+            }
+            WindowEvent::Focused(focused) => {
+                // The release can't be seen once the window is in the background
+                if !focused {
+                    self.set_mouse_look(false);
+                }
+                self.set_focused(focused);
+            }
+            WindowEvent::RedrawRequested => self.redraw(),
+            WindowEvent::CloseRequested => event_loop.exit(),
+            // Cursor moves and the like come in every frame, keep them out of the way
+            _ => trace!("Window event {event:?}"),
+        }
+    }
+
+    // Raw, unaccelerated mouse movement that keeps coming when the cursor can't
+    // move any further, unlike `CursorMoved`. Only used while looking, the drags
+    // want the cursor's actual position.
+    fn device_event(&mut self, _event_loop: &ActiveEventLoop, _id: DeviceId, event: DeviceEvent) {
+        let DeviceEvent::MouseMotion { delta } = event else {
+            return;
+        };
+        if !self.mouse_look {
+            return;
         }
-    });
+        if let Some(render_state) = &mut self.render_state {
+            self.camera_controller.look(
+                render_state.camera_state.camera_mut(),
+                (delta.0 as f32, delta.1 as f32),
+            );
+        }
+    }
+
+    // Sleeps until the next frame is due when the frame rate is capped
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        event_loop.set_control_flow(match self.next_frame {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        });
+    }
+}
+
+// Takes over the thread until the event loop exits
+pub fn run(mut app: App, event_loop: EventLoop<()>) {
+    log::info!("Running mainloop...");
+
+    if let Err(e) = event_loop.run_app(&mut app) {
+        log::error!("Event loop failed: {e}");
+    }
 }
 
 fn _main(event_loop: EventLoop<()>) {
-    run(event_loop);
+    run(App::from_env(), event_loop);
 }
 
 #[allow(dead_code)]
@@ -411,7 +3255,7 @@ fn main() {
         .parse_default_env()
         .init();
 
-    let event_loop = EventLoopBuilder::new().build();
+    let event_loop = EventLoop::new().expect("Failed to create the event loop");
     _main(event_loop);
 }
 #[cfg(target_os = "android")]
@@ -427,6 +3271,9 @@ fn android_main(app: AndroidApp) {
         android_logger::Config::default().with_max_level(log::LevelFilter::Info),
     );
 
-    let event_loop = EventLoopBuilder::new().with_android_app(app).build();
+    let event_loop = EventLoop::builder()
+        .with_android_app(app)
+        .build()
+        .expect("Failed to create the event loop");
     _main(event_loop);
 }
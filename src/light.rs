@@ -0,0 +1,218 @@
+use std::num::NonZeroU64;
+
+use bytemuck::Zeroable;
+use cgmath::InnerSpace;
+use wgpu::util::DeviceExt;
+use winit::keyboard::{Key, NamedKey};
+
+// Has to match the array size in shader.wgsl
+pub const MAX_LIGHTS: usize = 8;
+
+// Towards the directional light, from above and in front of the grid
+const DEFAULT_DIRECTION: [f32; 3] = [0.4, 1.0, 0.6];
+// How far the directional light can tilt up or down, short of straight up, where
+// the direction would lose its azimuth
+const MAX_ELEVATION: f32 = 1.5;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    pub intensity: f32,
+    pub color: [f32; 3],
+    // A vec3 takes up 16 bytes in a uniform array element
+    _padding: f32,
+}
+
+impl PointLight {
+    pub fn new(position: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            position,
+            intensity,
+            color,
+            _padding: 0.0,
+        }
+    }
+}
+
+// Laid out by the std140 rules uniforms follow, the array stride is already a
+// multiple of 16 and the count fills the rest of the direction's vec3 slot
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightUniform {
+    lights: [PointLight; MAX_LIGHTS],
+    direction: [f32; 3],
+    count: u32,
+}
+
+pub struct LightState {
+    lights: Vec<PointLight>,
+    // Unit vector towards the directional light
+    direction: cgmath::Vector3<f32>,
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    // Set whenever `lights` changes so the next `update` re-uploads them
+    dirty: bool,
+}
+
+impl LightState {
+    // `shadow_layout_entries` and `shadow_entries` are bound after the lights,
+    // from binding 1 on, see `ShadowState::layout_entries`
+    pub fn new(
+        device: &wgpu::Device,
+        shadow_layout_entries: &[wgpu::BindGroupLayoutEntry],
+        shadow_entries: &[wgpu::BindGroupEntry],
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("light uniform"),
+            contents: bytemuck::bytes_of(&LightUniform::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let light_layout_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(std::mem::size_of::<LightUniform>() as u64),
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("light bind group layout"),
+            entries: &[&[light_layout_entry], shadow_layout_entries].concat(),
+        });
+
+        let light_entry = wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("light bind group"),
+            layout: &bind_group_layout,
+            entries: &[&[light_entry], shadow_entries].concat(),
+        });
+
+        Self {
+            lights: Vec::new(),
+            direction: cgmath::Vector3::from(DEFAULT_DIRECTION).normalize(),
+            buffer,
+            bind_group,
+            bind_group_layout,
+            // The buffer starts zeroed, without a direction
+            dirty: true,
+        }
+    }
+
+    pub fn direction(&self) -> cgmath::Vector3<f32> {
+        self.direction
+    }
+
+    // Turns the directional light `yaw` radians around the Y axis and tilts it
+    // `pitch` radians up, stopping at `MAX_ELEVATION`
+    pub fn rotate_direction(&mut self, yaw: f32, pitch: f32) {
+        let azimuth = self.direction.z.atan2(self.direction.x) + yaw;
+        let elevation = (self.direction.y.asin() + pitch).clamp(-MAX_ELEVATION, MAX_ELEVATION);
+        self.direction = cgmath::Vector3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        );
+        self.dirty = true;
+    }
+
+    // Returns false, and drops the light, once MAX_LIGHTS are in use
+    pub fn add_light(&mut self, light: PointLight) -> bool {
+        if self.lights.len() >= MAX_LIGHTS {
+            log::warn!("Only {MAX_LIGHTS} point lights are supported, ignoring {light:?}");
+            return false;
+        }
+
+        self.lights.push(light);
+        self.dirty = true;
+        true
+    }
+
+    pub fn clear_lights(&mut self) {
+        self.lights.clear();
+        self.dirty = true;
+    }
+
+    pub fn lights(&self) -> &[PointLight] {
+        &self.lights
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        let mut uniform = LightUniform::zeroed();
+        uniform.lights[..self.lights.len()].copy_from_slice(&self.lights);
+        uniform.direction = self.direction.into();
+        uniform.count = self.lights.len() as u32;
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+}
+
+// Turns the directional light with a drag or the arrow keys. It keeps its own
+// drag state, a drag that starts here is never also seen by the camera controller.
+pub struct LightController {
+    // Radians turned per pixel dragged
+    pub drag_sensitivity: f32,
+    // Radians turned per arrow key press
+    pub key_step: f32,
+    dragging: bool,
+    last_cursor: Option<(f32, f32)>,
+}
+
+impl Default for LightController {
+    fn default() -> Self {
+        Self {
+            drag_sensitivity: 0.01,
+            key_step: 0.1,
+            dragging: false,
+            last_cursor: None,
+        }
+    }
+}
+
+impl LightController {
+    pub fn is_dragging(&self) -> bool {
+        self.dragging
+    }
+
+    pub fn set_drag(&mut self, pressed: bool) {
+        self.dragging = pressed;
+    }
+
+    // Call for every cursor move, like `CameraController::cursor_moved`
+    pub fn cursor_moved(&mut self, light: &mut LightState, cursor: (f32, f32)) {
+        let Some(last) = self.last_cursor.replace(cursor) else {
+            return;
+        };
+        if self.dragging {
+            // The light follows the cursor sideways and up
+            light.rotate_direction(
+                (cursor.0 - last.0) * self.drag_sensitivity,
+                (last.1 - cursor.1) * self.drag_sensitivity,
+            );
+        }
+    }
+
+    // False for keys other than the arrows
+    pub fn handle_key(&mut self, light: &mut LightState, key: &Key) -> bool {
+        let (yaw, pitch) = match key {
+            Key::Named(NamedKey::ArrowLeft) => (-self.key_step, 0.0),
+            Key::Named(NamedKey::ArrowRight) => (self.key_step, 0.0),
+            Key::Named(NamedKey::ArrowUp) => (0.0, self.key_step),
+            Key::Named(NamedKey::ArrowDown) => (0.0, -self.key_step),
+            _ => return false,
+        };
+        light.rotate_direction(yaw, pitch);
+        true
+    }
+}
@@ -0,0 +1,86 @@
+use crate::camera::Frustum;
+use crate::data::{VertexData, VertexState};
+use crate::instance::{InstanceRaw, InstanceState};
+
+// Vertex buffer slots of every scene pipeline, in the order of `buffer_layouts`
+pub const GEOMETRY_SLOT: u32 = 0;
+pub const INSTANCE_SLOT: u32 = 1;
+
+// The vertex buffers the scene pipelines are built with, one per slot
+pub fn buffer_layouts() -> [wgpu::VertexBufferLayout<'static>; 2] {
+    [VertexData::desc(), InstanceRaw::desc()]
+}
+
+// Geometry and the instances to draw it at, rendered with one instanced draw call.
+// Each mesh carries its own index format and count in `geometry`, and its
+// instances are culled and picked by the geometry's bounds.
+pub struct Mesh {
+    pub geometry: VertexState,
+    pub instances: InstanceState,
+}
+
+impl Mesh {
+    pub fn new(geometry: VertexState, instances: InstanceState) -> Self {
+        Self {
+            geometry,
+            instances,
+        }
+    }
+
+    // See `InstanceState::update`
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        frustum: Option<&Frustum>,
+        dt: f32,
+    ) {
+        self.instances
+            .update(device, encoder, frustum, self.geometry.bounds, dt);
+        #[cfg(feature = "indirect-draw")]
+        self.instances
+            .write_indirect_args(device, encoder, self.geometry.num_indices);
+    }
+
+    // Index of the nearest instance hit by the ray, and the distance to it
+    pub fn pick(
+        &self,
+        origin: cgmath::Point3<f32>,
+        direction: cgmath::Vector3<f32>,
+    ) -> Option<(usize, f32)> {
+        self.instances.pick(origin, direction, self.geometry.bounds)
+    }
+
+    // World space box around every instance, None when there are none
+    pub fn bounding_box(&self) -> Option<(cgmath::Point3<f32>, cgmath::Point3<f32>)> {
+        self.instances.bounding_box(self.geometry.bounds)
+    }
+
+    // The pipeline and bind groups have to be set already, see `RenderState::bind_resources`
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        self.set_buffers(rpass);
+        rpass.draw_indexed(
+            0..self.geometry.num_indices,
+            0,
+            0..self.instances.num_visible(),
+        );
+    }
+
+    // Like `draw`, with the counts read from `InstanceState::indirect_buffer`.
+    // The device needs `DownlevelFlags::INDIRECT_EXECUTION`.
+    #[cfg(feature = "indirect-draw")]
+    pub fn draw_indirect<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        self.set_buffers(rpass);
+        rpass.draw_indexed_indirect(&self.instances.indirect_buffer, 0);
+    }
+
+    fn set_buffers<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        let Self {
+            geometry,
+            instances,
+        } = self;
+        rpass.set_vertex_buffer(GEOMETRY_SLOT, geometry.vertex_buffer.slice(..));
+        rpass.set_vertex_buffer(INSTANCE_SLOT, instances.instance_buffer.slice(..));
+        rpass.set_index_buffer(geometry.index_buffer.slice(..), geometry.index_format);
+    }
+}
@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+
+use anyhow::{bail, Result};
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+
+// Matches `PostUniform` in postprocess.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    // 1 when the target format isn't sRGB
+    gamma_encode: u32,
+    // `ToneMap` as its index
+    tone_map: u32,
+    // Uniform buffers are laid out in 16 byte chunks
+    _padding: [u32; 2],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    None,
+    Grayscale,
+}
+
+// How the scene's linear color, which lighting can push past 1, is brought into
+// the range the display takes. In the order `tone_map` in postprocess.wgsl
+// switches on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMap {
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" => Self::None,
+            "reinhard" => Self::Reinhard,
+            "aces" => Self::Aces,
+            _ => bail!("expected none, reinhard or aces"),
+        })
+    }
+
+    // ACES for the HDR swapchain, which would otherwise pass on whatever the
+    // scene drew. LDR targets clip at 1 and are left as they always were.
+    fn default_for(target_format: wgpu::TextureFormat) -> Self {
+        if target_format == crate::HDR_SWAPCHAIN_FORMAT {
+            Self::Aces
+        } else {
+            Self::None
+        }
+    }
+}
+
+// Draws the offscreen scene target to the swapchain with a fullscreen triangle.
+// New effects are extra fragment entry points in `postprocess.wgsl`. Being the
+// last pass before the swapchain, it also tone maps and does the sRGB encode a
+// plain `Unorm` swapchain wouldn't.
+pub struct PostProcessState {
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    blit_pipeline: wgpu::RenderPipeline,
+    grayscale_pipeline: wgpu::RenderPipeline,
+    // Points at the current scene target, rebuilt whenever that is resized
+    bind_group: Option<wgpu::BindGroup>,
+    pub effect: PostEffect,
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    target_format: wgpu::TextureFormat,
+    fragment_entry_point: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(fragment_entry_point),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(target_format.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+impl PostProcessState {
+    // `tone_map` is picked by the target format when None
+    pub fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        tone_map: Option<ToneMap>,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post process bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        // The scene shader works in linear color and counts on the target to
+        // encode it on store, which only sRGB formats do. The HDR swapchain
+        // takes linear color as it is.
+        let gamma_encode = !target_format.is_srgb() && target_format != crate::HDR_SWAPCHAIN_FORMAT;
+        if gamma_encode {
+            log::info!("{target_format:?} isn't sRGB, the post process pass encodes it");
+        }
+        let tone_map = tone_map.unwrap_or_else(|| ToneMap::default_for(target_format));
+        log::info!("Tone map: {tone_map:?}");
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("post process uniform"),
+            contents: bytemuck::bytes_of(&PostUniform {
+                gamma_encode: gamma_encode as u32,
+                tone_map: tone_map as u32,
+                _padding: [0; 2],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("post process shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("postprocess.wgsl"))),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post process pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let blit_pipeline =
+            create_pipeline(device, &pipeline_layout, &shader, target_format, "fs_blit");
+        let grayscale_pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            target_format,
+            "fs_grayscale",
+        );
+
+        Self {
+            bind_group_layout,
+            uniform_buffer,
+            blit_pipeline,
+            grayscale_pipeline,
+            bind_group: None,
+            effect: PostEffect::None,
+        }
+    }
+
+    pub fn set_source(&mut self, device: &wgpu::Device, source: &Texture) {
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("post process bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&source.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        }));
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let Some(bind_group) = &self.bind_group else {
+            return;
+        };
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("post process pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        rpass.set_pipeline(match self.effect {
+            PostEffect::None => &self.blit_pipeline,
+            PostEffect::Grayscale => &self.grayscale_pipeline,
+        });
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
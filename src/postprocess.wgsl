@@ -0,0 +1,84 @@
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+
+@group(0) @binding(1)
+var s_scene: sampler;
+
+struct PostUniform {
+    // 1 when the swapchain isn't sRGB, so its encode on store has to be done here
+    gamma_encode: u32,
+    // 0 none, 1 Reinhard, 2 ACES, see `ToneMap`
+    tone_map: u32,
+}
+
+@group(0) @binding(2)
+var<uniform> post: PostUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle that covers the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // Texture coordinates start at the top left, clip space at the bottom left
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Brings linear color of any brightness into 0 to 1
+fn tone_map(color: vec3<f32>) -> vec3<f32> {
+    let rgb = max(color, vec3<f32>(0.0));
+    switch post.tone_map {
+        case 1u: {
+            return rgb / (1.0 + rgb);
+        }
+        case 2u: {
+            return aces(rgb);
+        }
+        default: {
+            return rgb;
+        }
+    }
+}
+
+// Tone maps, then applies the sRGB transfer function rather than a plain 1/2.2
+// power, so the result matches what an sRGB swapchain would store
+fn encode_output(color: vec4<f32>) -> vec4<f32> {
+    let mapped = vec4<f32>(tone_map(color.rgb), color.a);
+    if post.gamma_encode == 0u {
+        return mapped;
+    }
+    let rgb = clamp(mapped.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    let low = rgb * 12.92;
+    let high = 1.055 * pow(rgb, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return vec4<f32>(select(high, low, rgb <= vec3<f32>(0.0031308)), color.a);
+}
+
+// Copies the offscreen scene to the screen unchanged
+@fragment
+fn fs_blit(in: VertexOutput) -> @location(0) vec4<f32> {
+    return encode_output(textureSample(t_scene, s_scene, in.uv));
+}
+
+@fragment
+fn fs_grayscale(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_scene, s_scene, in.uv);
+    let luminance = dot(color.rgb, vec3<f32>(0.299, 0.587, 0.114));
+    return encode_output(vec4<f32>(vec3<f32>(luminance), color.a));
+}
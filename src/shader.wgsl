@@ -1,22 +1,62 @@
 struct CameraUniform {
     view_proj: mat4x4<f32>,
+    // Texels with less alpha are discarded, 0 keeps all of them
+    alpha_cutoff: f32,
+    // Scales the shaded color, 1 leaves it as it is
+    exposure: f32,
+    // 1 when the surface shows premultiplied colors
+    premultiply_alpha: u32,
 }
 
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Set every frame once `App::enable_shader_globals` was called, zero otherwise
+struct GlobalsUniform {
+    // Seconds of animation so far, stops while the app is suspended
+    time_secs: f32,
+    // The first frame drawn is 0
+    frame: u32,
+    // Of the frame in pixels
+    resolution: vec2<f32>,
+}
+
+@group(1) @binding(1)
+var<uniform> globals: GlobalsUniform;
+
+// The sprite sheet cell to show, the whole texture for still textures
+struct SpriteUniform {
+    offset: vec2<f32>,
+    scale: vec2<f32>,
+}
+
+@group(0) @binding(2)
+var<uniform> sprite: SpriteUniform;
+
 struct VertextInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    // w is the handedness of the bitangent
+    @location(3) tangent: vec4<f32>,
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    @location(9) tex_index: u32,
+    @location(10) normal_matrix_0: vec3<f32>,
+    @location(11) normal_matrix_1: vec3<f32>,
+    @location(12) normal_matrix_2: vec3<f32>,
 }
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // Invariant so the depth pre-pass and the main pass compute identical depth
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) @interpolate(flat) tex_index: u32,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) world_tangent: vec4<f32>,
 }
 
 @vertex
@@ -27,20 +67,176 @@ fn vs_main(model: VertextInput) -> VertexOutput {
         model.model_matrix_2,
         model.model_matrix_3,
     );
+    let normal_matrix = mat3x3<f32>(
+        model.normal_matrix_0,
+        model.normal_matrix_1,
+        model.normal_matrix_2,
+    );
     
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
     var out: VertexOutput;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
-    out.tex_coords = model.tex_coords;
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.tex_coords = sprite.offset + model.tex_coords * sprite.scale;
+    out.tex_index = model.tex_index;
+    // The model matrix would skew normals of non-uniformly scaled instances
+    out.world_normal = normal_matrix * model.normal;
+    // Tangents lie in the surface, so unlike normals they follow the model matrix
+    out.world_tangent = vec4<f32>(
+        (model_matrix * vec4<f32>(model.tangent.xyz, 0.0)).xyz,
+        model.tangent.w,
+    );
     return out;
 }
 
 @group(0) @binding(0)
-var t_diffuse : texture_2d<f32>;
+var t_diffuse : texture_2d_array<f32>;
 
 @group(0) @binding(1)
 var s_diffuse_sampler : sampler;
 
+@group(0) @binding(3)
+var t_normal : texture_2d<f32>;
+
+struct PointLight {
+    position: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+}
+
+// MAX_LIGHTS in light.rs
+struct LightUniform {
+    lights: array<PointLight, 8>,
+    // Towards the directional light, normalized
+    direction: vec3<f32>,
+    count: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> light: LightUniform;
+
+const AMBIENT = 0.2;
+
+// See shadow.rs
+struct ShadowUniform {
+    // World space to the shadow map's clip space
+    light_view_proj: mat4x4<f32>,
+    // One texel of the shadow map in texture coordinates
+    texel_size: f32,
+    // 0 while shadows are off, everything is lit then
+    enabled: u32,
+}
+
+@group(2) @binding(1)
+var<uniform> shadow: ShadowUniform;
+
+@group(2) @binding(2)
+var t_shadow: texture_depth_2d;
+
+@group(2) @binding(3)
+var s_shadow: sampler_comparison;
+
+// How much of the directional light reaches `world_position`, from 0 in full
+// shadow to 1. Percentage closer filtering over 3x3 texels softens the edges.
+fn directional_shadow(world_position: vec3<f32>) -> f32 {
+    if shadow.enabled == 0u {
+        return 1.0;
+    }
+    let light_clip = shadow.light_view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = light_clip.xyz / light_clip.w;
+    // Texture coordinates run down from the top left, clip space up from the center
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    // Nothing outside the map casts a shadow
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            // The Level variant can be called from non-uniform control flow
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// The normal map's tangent space normal, brought to world space by the TBN matrix.
+// Vertices without a tangent, like those from `VertexData::new` that never went
+// through `compute_tangents`, keep their interpolated normal.
+fn surface_normal(in: VertexOutput) -> vec3<f32> {
+    let tangent_normal = textureSample(t_normal, s_diffuse_sampler, in.tex_coords).xyz * 2.0 - 1.0;
+
+    // Interpolation shortens the normal and skews the tangent off it, so
+    // renormalize and make the tangent perpendicular again
+    let n = normalize(in.world_normal);
+    let tangent = in.world_tangent.xyz - n * dot(n, in.world_tangent.xyz);
+    let length_squared = dot(tangent, tangent);
+    // Normalizing a zero tangent would be NaN, the clamp keeps it finite for the
+    // branch `select` throws away
+    let t = tangent * inverseSqrt(max(length_squared, 1e-12));
+    let b = cross(n, t) * in.world_tangent.w;
+    let mapped = normalize(mat3x3<f32>(t, b, n) * tangent_normal);
+    return select(n, mapped, length_squared > 1e-12);
+}
+
+// Sampling the sRGB texture returns linear color, so the lighting is done here
+// before the swapchain's sRGB encode
+fn shade(in: VertexOutput, color: vec4<f32>) -> vec4<f32> {
+    let normal = surface_normal(in);
+    let directional = max(dot(normal, light.direction), 0.0) * directional_shadow(in.world_position);
+    var lighting = vec3<f32>(AMBIENT + directional);
+
+    for (var i = 0u; i < light.count; i += 1u) {
+        let point = light.lights[i];
+        let to_light = point.position - in.world_position;
+        let distance = length(to_light);
+        // Inverse square falloff, offset so it stays finite right at the light
+        let attenuation = point.intensity / (1.0 + distance * distance);
+        let diffuse = max(dot(normal, to_light / distance), 0.0);
+        lighting += point.color * diffuse * attenuation;
+    }
+
+    return vec4<f32>(color.rgb * lighting * camera.exposure, color.a);
+}
+
+// Alpha testing, cheaper than blending and needs no sorting, but only suits
+// textures that are either opaque or fully transparent, like cutout cards
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse_sampler, in.tex_coords);
-}
\ No newline at end of file
+    let color = textureSample(t_diffuse, s_diffuse_sampler, in.tex_coords, in.tex_index);
+    if color.a < camera.alpha_cutoff {
+        discard;
+    }
+    let shaded = shade(in, color);
+    // Blending leaves premultiplied colors behind by itself
+    if camera.premultiply_alpha != 0u {
+        return vec4<f32>(shaded.rgb * shaded.a, shaded.a);
+    }
+    return shaded;
+}
+
+// Used when alpha blending is enabled. Fully transparent texels are dropped so
+// they don't write depth and hide the cubes behind them.
+@fragment
+fn fs_blended(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse_sampler, in.tex_coords, in.tex_index);
+    if color.a < max(camera.alpha_cutoff, 0.01) {
+        discard;
+    }
+    return shade(in, color);
+}
+
+struct WireframeOverlayUniform {
+    color: vec4<f32>,
+};
+@group(3) @binding(0)
+var<uniform> overlay: WireframeOverlayUniform;
+
+// Flat colored lines over the shaded faces, only bound by the wireframe overlay
+@fragment
+fn fs_wireframe_overlay() -> @location(0) vec4<f32> {
+    return overlay.color;
+}
@@ -0,0 +1,241 @@
+use std::num::NonZeroU64;
+
+use bytemuck::Zeroable;
+use cgmath::{EuclideanSpace, InnerSpace};
+use wgpu::util::DeviceExt;
+
+use crate::camera::{CameraState, CameraUniform};
+use crate::texture::Texture;
+use crate::PipelineConfig;
+
+// Width and height of the shadow map in texels when `SHADOW_MAP_SIZE` isn't set
+pub const DEFAULT_SHADOW_MAP_SIZE: u32 = 2048;
+
+const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// Maps GL's -1 to 1 clip space depth to wgpu's 0 to 1 and leaves w alone. Not
+// `camera::OPENGL_TO_WGPU_MATRIX`, which also adds depth to w and so would bend
+// an orthographic projection.
+#[rustfmt::skip]
+const GL_TO_WGPU_DEPTH: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// Matches `ShadowUniform` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    light_view_proj: [[f32; 4]; 4],
+    texel_size: f32,
+    // 0 while shadows are off
+    enabled: u32,
+    // Uniform buffers are laid out in 16 byte chunks
+    _padding: [f32; 2],
+}
+
+// Shadows cast by the directional light. The scene's depth is drawn from the
+// light with an orthographic projection fitted around the meshes, then the
+// main pass compares against it in `directional_shadow` in shader.wgsl. The
+// map and light matrix are bound with the lights, see `layout_entries`.
+pub struct ShadowState {
+    pub enabled: bool,
+    size: u32,
+    uniform: ShadowUniform,
+    buffer: wgpu::Buffer,
+    pub map: Texture,
+    // Bound in place of the camera's during the shadow pass, so the scene's
+    // vertex shader projects into light space
+    light_camera_buffer: wgpu::Buffer,
+    pub light_camera_bind_group: wgpu::BindGroup,
+    // Only the textures and the camera, the lights' group holds the shadow map,
+    // which can't be bound while it's drawn to. Vertex stages reading the later
+    // groups can't cast shadows.
+    pipeline_layout: wgpu::PipelineLayout,
+}
+
+impl ShadowState {
+    pub fn new(
+        device: &wgpu::Device,
+        size: u32,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_state: &CameraState,
+    ) -> Self {
+        let size = size.clamp(1, device.limits().max_texture_dimension_2d);
+        let uniform = ShadowUniform {
+            texel_size: 1.0 / size as f32,
+            ..ShadowUniform::zeroed()
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("shadow uniform"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // Comes with a `LessEqual` comparison sampler
+        let map = Texture::create_depth_tex(
+            device,
+            winit::dpi::PhysicalSize::new(size, size),
+            SHADOW_FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING,
+            1,
+        );
+
+        let light_camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("shadow light camera uniform"),
+            contents: bytemuck::bytes_of(&CameraUniform::new()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_camera_bind_group = camera_state.create_bind_group(device, &light_camera_buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shadow pipeline layout"),
+            bind_group_layouts: &[texture_bind_group_layout, &camera_state.bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            enabled: true,
+            size,
+            uniform,
+            buffer,
+            map,
+            light_camera_buffer,
+            light_camera_bind_group,
+            pipeline_layout,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    // The light matrix, the map and its comparison sampler, for the group that
+    // binds them from `first_binding` on
+    pub fn layout_entries(first_binding: u32) -> [wgpu::BindGroupLayoutEntry; 3] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: NonZeroU64::new(std::mem::size_of::<ShadowUniform>() as u64),
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+        ]
+    }
+
+    // Matches `layout_entries`
+    pub fn bind_group_entries(&self, first_binding: u32) -> [wgpu::BindGroupEntry<'_>; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: first_binding,
+                resource: self.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 1,
+                resource: wgpu::BindingResource::TextureView(&self.map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 2,
+                resource: wgpu::BindingResource::Sampler(&self.map.sampler),
+            },
+        ]
+    }
+
+    // The depth pre-pass's pipeline with the shadow map's format and a slope
+    // scaled bias against shadow acne. Standard depth, whatever the scene uses.
+    pub fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        config: &PipelineConfig,
+    ) -> wgpu::RenderPipeline {
+        crate::create_depth_only_pipeline(
+            device,
+            Some("shadow pipeline"),
+            &self.pipeline_layout,
+            shader,
+            config,
+            1,
+            wgpu::DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            },
+        )
+    }
+
+    // Once per frame, fits the light's projection around `scene_bounds`, the
+    // world space box of everything that casts or receives shadows
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        light_direction: cgmath::Vector3<f32>,
+        scene_bounds: Option<(cgmath::Point3<f32>, cgmath::Point3<f32>)>,
+    ) {
+        let light_view_proj = scene_bounds
+            .filter(|_| self.enabled)
+            .map(|bounds| light_view_projection(light_direction, bounds));
+        self.uniform.enabled = light_view_proj.is_some() as u32;
+        if let Some(light_view_proj) = light_view_proj {
+            self.uniform.light_view_proj = light_view_proj.into();
+            queue.write_buffer(
+                &self.light_camera_buffer,
+                0,
+                bytemuck::bytes_of(&CameraUniform::from_view_proj(light_view_proj)),
+            );
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.uniform));
+    }
+
+    // Whether the last `update` turned shadows on, and the shadow pass has to run
+    pub fn is_active(&self) -> bool {
+        self.uniform.enabled != 0
+    }
+}
+
+// Looks at the bounding sphere of `bounds` along `-direction`, from just outside
+// it, so the whole box lands inside the map whichever way the light points
+fn light_view_projection(
+    direction: cgmath::Vector3<f32>,
+    (min, max): (cgmath::Point3<f32>, cgmath::Point3<f32>),
+) -> cgmath::Matrix4<f32> {
+    let center = min.midpoint(max);
+    let radius = ((max - min).magnitude() * 0.5).max(f32::EPSILON);
+    let eye = center + direction * (2.0 * radius);
+    // Any up vector works as long as it isn't parallel to the light
+    let up = if direction.y.abs() > 0.99 {
+        cgmath::Vector3::unit_z()
+    } else {
+        cgmath::Vector3::unit_y()
+    };
+    let view = cgmath::Matrix4::look_at_rh(eye, center, up);
+    let proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, 4.0 * radius);
+    GL_TO_WGPU_DEPTH * proj * view
+}
@@ -0,0 +1,325 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use image::GenericImageView;
+use wgpu::util::DeviceExt;
+
+use crate::camera::Camera;
+use crate::texture;
+
+// Layer order of a cube texture in wgpu, and the file names `load_faces` looks for.
+// A view direction selects the face of its largest axis, with z flipped since
+// cube maps are left-handed, so the default camera looking down -Z sees the
+// `pz` face straight ahead.
+pub const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxUniform {
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+pub struct SkyboxState {
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // Kept to rebuild the pipeline when the depth format changes
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    target_format: wgpu::TextureFormat,
+    sample_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    // None until faces are set, nothing is drawn without them
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl SkyboxState {
+    pub fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("skybox uniform"),
+            contents: bytemuck::cast_slice(&[SkyboxUniform {
+                inv_view_proj: [[0.0; 4]; 4],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("skybox bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("skybox shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("skybox.wgsl"))),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("skybox pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            target_format,
+            sample_count,
+            Some(wgpu::TextureFormat::Depth32Float),
+        );
+
+        Self {
+            buffer,
+            bind_group_layout,
+            shader,
+            pipeline_layout,
+            target_format,
+            sample_count,
+            pipeline,
+            bind_group: None,
+        }
+    }
+
+    // Has to follow the scene pass's depth attachment, None when it has none
+    pub fn set_depth_format(
+        &mut self,
+        device: &wgpu::Device,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) {
+        self.pipeline = create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.target_format,
+            self.sample_count,
+            depth_format,
+        );
+    }
+
+    // Has to follow the frame's color format, with the scene pass's depth format
+    pub fn set_target_format(
+        &mut self,
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) {
+        self.target_format = target_format;
+        self.set_depth_format(device, depth_format);
+    }
+
+    // Faces are in `FACE_NAMES` order and must all be the same square size
+    pub fn set_faces(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: [image::DynamicImage; 6],
+    ) -> Result<()> {
+        let (width, height) = faces[0].dimensions();
+        ensure!(
+            width == height,
+            "skybox faces must be square, got {width}x{height}"
+        );
+        for (face, name) in faces.iter().zip(FACE_NAMES) {
+            ensure!(
+                face.dimensions() == (width, height),
+                "skybox face {name} is {:?} but the others are {:?}",
+                face.dimensions(),
+                (width, height)
+            );
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("skybox texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                &face.to_rgba8(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(texture::BYTES_PER_PIXEL * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("skybox sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("skybox bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        }));
+        Ok(())
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        if self.bind_group.is_none() {
+            return;
+        }
+
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[SkyboxUniform {
+                inv_view_proj: camera.build_inverse_sky_matrix().into(),
+            }]),
+        );
+    }
+
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        if let Some(bind_group) = &self.bind_group {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    target_format: wgpu::TextureFormat,
+    sample_count: u32,
+    depth_format: Option<wgpu::TextureFormat>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("skybox pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(target_format.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Drawn first into the scene pass, so it has to match the depth attachment
+        // but must leave the depth buffer alone for the cubes. The stencil isn't
+        // tested, a mask only applies to the meshes.
+        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+// Loads `px`, `nx`, `py`, `ny`, `pz` and `nz` images (any extension) from a directory
+pub fn load_faces(dir: impl AsRef<Path>) -> Result<[image::DynamicImage; 6]> {
+    let dir = dir.as_ref();
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("reading skybox directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+
+    let load = |name: &str| -> Result<image::DynamicImage> {
+        let path = entries
+            .iter()
+            .find(|path| path.file_stem().is_some_and(|stem| stem == name))
+            .with_context(|| format!("skybox face {name} not found in {}", dir.display()))?;
+        image::open(path).with_context(|| format!("loading skybox face {}", path.display()))
+    };
+
+    Ok([
+        load(FACE_NAMES[0])?,
+        load(FACE_NAMES[1])?,
+        load(FACE_NAMES[2])?,
+        load(FACE_NAMES[3])?,
+        load(FACE_NAMES[4])?,
+        load(FACE_NAMES[5])?,
+    ])
+}
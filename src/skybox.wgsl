@@ -0,0 +1,41 @@
+struct SkyboxUniform {
+    inv_view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> sky: SkyboxUniform;
+
+@group(0) @binding(1)
+var t_sky: texture_cube<f32>;
+
+@group(0) @binding(2)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle that covers the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Unproject a point in front of the camera, without the camera translation
+    // that's the view direction. Depth 0 rather than the far plane, which with
+    // `OPENGL_TO_WGPU_MATRIX` lands behind the camera and reverses the direction.
+    let world = sky.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let direction = world.xyz / world.w;
+    // Cube map faces are laid out for a left-handed world, flipping z keeps
+    // them from showing up mirrored in this right-handed one
+    return textureSample(t_sky, s_sky, vec3<f32>(direction.xy, -direction.z));
+}
@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+// How often `StatsLog` reports
+const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+// What the meshes' draws added up to in the last frame, over every pass that
+// draws them: the shadow pass, the depth pre-pass, the scene pass once per
+// viewport and the wireframe overlay. A mesh drawn in three passes counts its
+// instances three times. The skybox, gizmos and post process aren't counted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    // Vertex shader invocations, indices times instances
+    pub vertices: u64,
+    pub instances: u64,
+    // 0 for line and point topologies
+    pub triangles: u64,
+    // Shown instances frustum culling left out of the upload, once per mesh
+    pub culled_instances: u32,
+}
+
+impl FrameStats {
+    pub fn add_draw(&mut self, indices: u32, instances: u32, topology: wgpu::PrimitiveTopology) {
+        let triangles = match topology {
+            wgpu::PrimitiveTopology::TriangleList => indices / 3,
+            wgpu::PrimitiveTopology::TriangleStrip => indices.saturating_sub(2),
+            _ => 0,
+        };
+        let instances = instances as u64;
+        self.draw_calls += 1;
+        self.vertices += indices as u64 * instances;
+        self.instances += instances;
+        self.triangles += triangles as u64 * instances;
+    }
+}
+
+// Logs the frame rate along with the last frame's stats, at debug level and
+// about once every `LOG_INTERVAL`
+pub struct StatsLog {
+    since: Instant,
+    frames: u32,
+}
+
+impl Default for StatsLog {
+    fn default() -> Self {
+        Self {
+            since: Instant::now(),
+            frames: 0,
+        }
+    }
+}
+
+impl StatsLog {
+    // After each frame is drawn
+    pub fn frame(&mut self, stats: &FrameStats) {
+        self.frames += 1;
+        let elapsed = self.since.elapsed();
+        if elapsed < LOG_INTERVAL {
+            return;
+        }
+        log::debug!(
+            "{:.1} FPS, {} draw calls, {} instances ({} culled), {} triangles, {} vertices",
+            self.frames as f64 / elapsed.as_secs_f64(),
+            stats.draw_calls,
+            stats.instances,
+            stats.culled_instances,
+            stats.triangles,
+            stats.vertices
+        );
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_draw_no_triangles() {
+        let mut stats = FrameStats::default();
+        stats.add_draw(36, 4, wgpu::PrimitiveTopology::TriangleList);
+        stats.add_draw(8, 2, wgpu::PrimitiveTopology::LineList);
+        assert_eq!(stats.draw_calls, 2);
+        assert_eq!(stats.instances, 6);
+        assert_eq!(stats.vertices, 36 * 4 + 8 * 2);
+        assert_eq!(stats.triangles, 12 * 4);
+    }
+}
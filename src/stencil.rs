@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+
+// Depth32Float has no stencil aspect, the depth buffer switches to this while
+// stencil testing is on
+pub const DEPTH_STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+
+// How the scene's draws test and update the stencil buffer, which is cleared to
+// 0 at the start of every frame
+#[derive(Copy, Clone, Debug)]
+pub struct StencilConfig {
+    pub reference: u32,
+    pub compare: wgpu::CompareFunction,
+    // When the stencil test fails
+    pub fail_op: wgpu::StencilOperation,
+    // When the stencil test passes but the depth test fails
+    pub depth_fail_op: wgpu::StencilOperation,
+    // When both tests pass
+    pub pass_op: wgpu::StencilOperation,
+}
+
+impl StencilConfig {
+    // Only draws where `StencilMask` wrote `reference`, leaving the buffer as is
+    pub fn masked(reference: u32) -> Self {
+        Self {
+            reference,
+            compare: wgpu::CompareFunction::Equal,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        }
+    }
+
+    // Front and back faces are treated the same
+    pub fn state(&self) -> wgpu::StencilState {
+        let face = wgpu::StencilFaceState {
+            compare: self.compare,
+            fail_op: self.fail_op,
+            depth_fail_op: self.depth_fail_op,
+            pass_op: self.pass_op,
+        };
+        wgpu::StencilState {
+            front: face,
+            back: face,
+            read_mask: 0xff,
+            write_mask: 0xff,
+        }
+    }
+}
+
+// Writes the render pass's stencil reference inside a circle in the middle of
+// the screen, leaving color and depth alone
+pub struct StencilMask {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl StencilMask {
+    pub fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("stencil mask shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("stencil_mask.wgsl"))),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("stencil mask pipeline layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let write_reference = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Replace,
+        };
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("stencil mask pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // The scene pass has a color attachment, so there has to be a target
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::empty(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_STENCIL_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState {
+                    front: write_reference,
+                    back: write_reference,
+                    read_mask: 0xff,
+                    write_mask: 0xff,
+                },
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        Self { pipeline }
+    }
+
+    // Draw before anything tested against the mask, with the stencil reference set
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.draw(0..3, 0..1);
+    }
+}
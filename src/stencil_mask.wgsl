@@ -0,0 +1,27 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// Radius of the mask in normalized device coordinates, it stretches with the window
+const MASK_RADIUS = 0.6;
+
+// A single triangle that covers the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 0.0, 1.0);
+    return out;
+}
+
+// Color writes are masked off, only the surviving fragments' stencil writes matter
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if length(in.ndc) > MASK_RADIUS {
+        discard;
+    }
+    return vec4<f32>(0.0);
+}
@@ -1,7 +1,65 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
 use anyhow::*;
 use image::GenericImageView;
+use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalSize;
 
+use crate::compressed::{self, CompressedImage};
+
+// Every color texture here is Rgba8
+pub const BYTES_PER_PIXEL: u32 = 4;
+
+// Colors are stored sRGB, so sampling decodes them to linear for the lighting.
+// Data like masks or height maps wants `Rgba8Unorm` or `R8Unorm` instead, which
+// are sampled as stored.
+pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Bytes per row of an image `width` pixels wide, padded to the 256 byte
+// alignment that copies between buffers and textures need. `queue.write_texture`
+// accepts tightly packed rows, `copy_texture_to_buffer` and friends don't.
+pub fn align_bytes_per_row(width: u32) -> u32 {
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * BYTES_PER_PIXEL).div_ceil(alignment) * alignment
+}
+
+// How color textures are sampled
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FilterPreset {
+    // Nearest texel at every distance and no mip levels, keeps hard pixel edges
+    PixelArt,
+    // Linear filtering between texels and mip levels, which are generated on upload
+    #[default]
+    Smooth,
+}
+
+impl FilterPreset {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "pixel" | "pixelart" | "pixel_art" => Ok(FilterPreset::PixelArt),
+            "smooth" => Ok(FilterPreset::Smooth),
+            other => bail!("unknown filter preset {other}, expected pixel or smooth"),
+        }
+    }
+
+    fn filter_mode(self) -> wgpu::FilterMode {
+        match self {
+            FilterPreset::PixelArt => wgpu::FilterMode::Nearest,
+            FilterPreset::Smooth => wgpu::FilterMode::Linear,
+        }
+    }
+
+    // Down to a single texel for smooth filtering
+    fn mip_level_count(self, (width, height): (u32, u32)) -> u32 {
+        match self {
+            FilterPreset::PixelArt => 1,
+            FilterPreset::Smooth => u32::BITS - width.max(height).max(1).leading_zeros(),
+        }
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -13,53 +71,315 @@ impl Texture {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
+        format: wgpu::TextureFormat,
+        preset: FilterPreset,
         label: &str,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, img, label)
+        Self::from_image(device, queue, img, format, preset, label)
+    }
+
+    // Decodes the image on a background thread, poll the returned handle each
+    // frame to upload it once it's ready. A KTX2 or DDS file is uploaded as it's
+    // stored if `features`, the device's, cover its format, otherwise the image
+    // next to it is decoded instead, see `compressed::fallback_path`.
+    pub fn from_path_async(path: impl AsRef<Path>, features: wgpu::Features) -> PendingTexture {
+        let path = path.as_ref().to_path_buf();
+        let (sender, receiver) = mpsc::channel();
+        let thread_path = path.clone();
+        thread::spawn(move || {
+            let decoded = Decoded::open(&thread_path, features);
+            // The receiver is gone if the renderer was dropped meanwhile
+            let _ = sender.send(decoded);
+        });
+        PendingTexture { path, receiver }
+    }
+
+    // Uploads the file's mip levels as they are, only the first one with
+    // `FilterPreset::PixelArt`. Fails unless the device has the features the
+    // format needs, see `compressed::FEATURES`.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        preset: FilterPreset,
+        label: &str,
+    ) -> Result<Self> {
+        let required = image.format.required_features();
+        ensure!(
+            device.features().contains(required),
+            "texture {label:?} is {:?}, which needs {required:?}",
+            image.format
+        );
+        let mip_level_count =
+            (image.levels.len() as u32).min(preset.mip_level_count((image.width, image.height)));
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: image.width,
+                height: image.height,
+                depth_or_array_layers: image.layers,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: image.format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (mip_level, bytes) in (0..mip_level_count).zip(&image.levels) {
+            let (bytes_per_row, rows_per_image) = image.level_layout(mip_level);
+            let (width, height) = image.level_extent(mip_level);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                bytes,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(rows_per_image),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: image.layers,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let filter = preset.filter_mode();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    // A single white texel, to draw with while the real texture loads
+    pub fn placeholder(device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]));
+        Self::from_image(
+            device,
+            queue,
+            img.into(),
+            COLOR_FORMAT,
+            // There's nothing to filter with one texel
+            FilterPreset::PixelArt,
+            "placeholder texture",
+        )
     }
 
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: image::DynamicImage,
+        format: wgpu::TextureFormat,
+        preset: FilterPreset,
         label: &str,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        Self::from_images(device, queue, &[img], format, preset, label)
+    }
+
+    /// Uploads the images as the layers of a single `D2Array` texture, in order.
+    /// All images must share the same dimensions. They're converted to the
+    /// channels of `format`, one of `Rgba8UnormSrgb`, `Rgba8Unorm` or `R8Unorm`.
+    /// With `FilterPreset::Smooth` every layer gets a full mip chain.
+    pub fn from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::DynamicImage],
+        format: wgpu::TextureFormat,
+        preset: FilterPreset,
+        label: &str,
+    ) -> Result<Self> {
+        let usage = wgpu::TextureUsages::empty();
+        Self::from_images_with_usage(device, queue, images, format, preset, label, usage)
+    }
+
+    // `from_images` for a texture that's also used as `extra_usage`, e.g.
+    // `COPY_SRC` to read back what was uploaded
+    pub fn from_images_with_usage(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::DynamicImage],
+        format: wgpu::TextureFormat,
+        preset: FilterPreset,
+        label: &str,
+        extra_usage: wgpu::TextureUsages,
+    ) -> Result<Self> {
+        ensure!(!images.is_empty(), "texture {label:?} needs at least one image");
+        let dimensions = images[0].dimensions();
+        for (i, img) in images.iter().enumerate() {
+            ensure!(
+                img.dimensions() == dimensions,
+                "texture {label:?} layer {i} is {:?} but layer 0 is {:?}, all layers must share dimensions",
+                img.dimensions(),
+                dimensions
+            );
+        }
+
+        let bytes_per_pixel = format
+            .block_size(None)
+            .with_context(|| format!("texture {label:?} has no fixed texel size as {format:?}"))?;
+        let bytes_per_row = bytes_per_pixel * dimensions.0;
+        let layers = images
+            .iter()
+            .map(|img| image_bytes(img, format))
+            .collect::<Result<Vec<_>>>()?;
+        for (i, bytes) in layers.iter().enumerate() {
+            ensure!(
+                bytes.len() == (bytes_per_row * dimensions.1) as usize,
+                "texture {label:?} layer {i} converted to {} bytes, {format:?} needs {bytes_per_row} per row",
+                bytes.len()
+            );
+        }
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: images.len() as u32,
         };
+        let mip_level_count = preset.mip_level_count(dimensions);
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | extra_usage,
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+        for (layer, bytes) in layers.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                bytes,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(dimensions.1),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+        // Each level is scaled down from the full image rather than the level
+        // before it. The texels are averaged as stored, for sRGB that's a bit
+        // darker than averaging the linear colors would be.
+        for mip_level in 1..mip_level_count {
+            let width = (dimensions.0 >> mip_level).max(1);
+            let height = (dimensions.1 >> mip_level).max(1);
+            for (layer, img) in images.iter().enumerate() {
+                let level = img.resize_exact(width, height, image::imageops::FilterType::Triangle);
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                    },
+                    &image_bytes(&level, format)?,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(bytes_per_pixel * width),
+                        rows_per_image: Some(height),
+                    },
+                    wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        // Always view as an array, even for a single layer, so every texture
+        // matches the `texture_2d_array` binding in the shader
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let filter = preset.filter_mode();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    // Normal maps hold directions rather than colors, so they're stored linear
+    // where color textures get an sRGB decode when sampled
+    pub fn normal_map(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: &str,
+    ) -> Self {
+        let rgba = img.to_rgba8();
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: rgba.width(),
+                    height: rgba.height(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
             },
             &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
         );
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -71,29 +391,122 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+        }
+    }
+
+    // A single texel pointing straight out of the surface, (0, 0, 1) in tangent
+    // space, which leaves the lighting as if there was no normal map
+    pub fn flat_normal_map(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]));
+        Self::normal_map(device, queue, &img.into(), "flat normal map")
     }
 
-    pub fn create_depth_tex(device: &wgpu::Device, size: PhysicalSize<u32>) -> Texture {
+    // An offscreen color target the scene can be rendered into and then sampled
+    // by later passes
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+    ) -> Texture {
         let size = wgpu::Extent3d {
             width: size.width.max(1),
             height: size.height.max(1),
             depth_or_array_layers: 1,
         };
 
-        let desc = wgpu::TextureDescriptor {
-            label: Some("depth texture desc"),
+        let tex = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("render target"),
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
+        });
+        let view = tex.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("render target sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture: tex,
+            view,
+            sampler,
+        }
+    }
+
+    // Drawn into instead of a `create_render_target` when multisampling, which
+    // it's then resolved into. Can only be a render attachment.
+    pub fn create_multisampled_target(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Texture {
+        let size = wgpu::Extent3d {
+            width: size.width.max(1),
+            height: size.height.max(1),
+            depth_or_array_layers: 1,
+        };
+
+        let tex = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("multisampled render target"),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = tex.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Self {
+            texture: tex,
+            view,
+            sampler,
+        }
+    }
+
+    // Always a render attachment, `usage` adds to that, e.g. `TEXTURE_BINDING`
+    // for sampling it. Tiled GPUs can keep a depth buffer that's only rendered to
+    // in tile memory and never write it out. `sample_count` has to match the
+    // color attachment it's drawn with.
+    pub fn create_depth_tex(
+        device: &wgpu::Device,
+        size: PhysicalSize<u32>,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+        sample_count: u32,
+    ) -> Texture {
+        let size = wgpu::Extent3d {
+            width: size.width.max(1),
+            height: size.height.max(1),
+            depth_or_array_layers: 1,
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label: Some("depth texture desc"),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+            view_formats: &[],
         };
 
         let tex = device.create_texture(&desc);
@@ -120,17 +533,239 @@ impl Texture {
     }
 }
 
+// The decoded image as texels of `format`
+fn image_bytes(img: &image::DynamicImage, format: wgpu::TextureFormat) -> Result<Vec<u8>> {
+    match format {
+        wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm => {
+            Ok(img.to_rgba8().into_raw())
+        }
+        wgpu::TextureFormat::R8Unorm => Ok(img.to_luma8().into_raw()),
+        _ => bail!(
+            "images can't be uploaded as {format:?}, only as Rgba8UnormSrgb, Rgba8Unorm or R8Unorm"
+        ),
+    }
+}
+
+// What `Texture::from_path_async` read on its thread
+enum Decoded {
+    Image(image::DynamicImage),
+    Compressed(CompressedImage),
+}
+
+impl Decoded {
+    fn open(path: &Path, features: wgpu::Features) -> Result<Self> {
+        if !compressed::is_compressed_path(path) {
+            let img = image::open(path)
+                .with_context(|| format!("failed to decode {}", path.display()))?;
+            return Ok(Decoded::Image(img));
+        }
+        let image = CompressedImage::open(path)?;
+        let required = image.format.required_features();
+        if features.contains(required) {
+            return Ok(Decoded::Compressed(image));
+        }
+        let Some(fallback) = compressed::fallback_path(path) else {
+            bail!(
+                "the device can't sample {:?} without {required:?}, and there's no png, jpg or webp next to {}",
+                image.format,
+                path.display()
+            );
+        };
+        log::warn!(
+            "The device can't sample {:?}, decoding {} instead",
+            image.format,
+            fallback.display()
+        );
+        let img = image::open(&fallback)
+            .with_context(|| format!("failed to decode {}", fallback.display()))?;
+        Ok(Decoded::Image(img))
+    }
+}
+
+pub struct PendingTexture {
+    path: PathBuf,
+    receiver: mpsc::Receiver<Result<Decoded>>,
+}
+
+impl PendingTexture {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // None while the image is still decoding
+    pub fn poll(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        preset: FilterPreset,
+    ) -> Option<Result<Texture>> {
+        let decoded = match self.receiver.try_recv() {
+            Result::Ok(decoded) => decoded,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                return Some(Err(anyhow!(
+                    "decoding {} stopped without a result",
+                    self.path.display()
+                )))
+            }
+        };
+        let label = self.path.to_string_lossy();
+        Some(decoded.and_then(|decoded| match decoded {
+            Decoded::Image(img) => {
+                Texture::from_image(device, queue, img, COLOR_FORMAT, preset, &label)
+            }
+            Decoded::Compressed(image) => {
+                Texture::from_compressed(device, queue, &image, preset, &label)
+            }
+        }))
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteUniform {
+    // Added to the scaled tex_coords, in texture coordinates
+    offset: [f32; 2],
+    scale: [f32; 2],
+}
+
+impl SpriteUniform {
+    // Still textures show all of the texture
+    const WHOLE: Self = Self {
+        offset: [0.0, 0.0],
+        scale: [1.0, 1.0],
+    };
+}
+
+// Animation frames in a grid of equally sized cells, played left to right and
+// top to bottom
+#[derive(Copy, Clone, Debug)]
+struct SpriteSheet {
+    cols: u32,
+    rows: u32,
+    fps: f32,
+    // Seconds into the animation, wraps around at the end of the last frame
+    elapsed: f32,
+}
+
+impl SpriteSheet {
+    fn frame_count(&self) -> u32 {
+        self.cols * self.rows
+    }
+
+    fn current_frame(&self) -> u32 {
+        (self.elapsed * self.fps) as u32 % self.frame_count()
+    }
+
+    fn advance(&mut self, dt: f32) {
+        let duration = self.frame_count() as f32 / self.fps;
+        self.elapsed = (self.elapsed + dt) % duration;
+    }
+
+    fn uniform(&self) -> SpriteUniform {
+        let frame = self.current_frame();
+        let scale = [1.0 / self.cols as f32, 1.0 / self.rows as f32];
+        SpriteUniform {
+            offset: [
+                (frame % self.cols) as f32 * scale[0],
+                (frame / self.cols) as f32 * scale[1],
+            ],
+            scale,
+        }
+    }
+}
+
 pub struct TextureData {
     pub texture: Texture,
+    // Shared by every layer of `texture`, flat unless one was given to `new`
+    pub normal_map: Texture,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
+    // Which part of the texture the shader maps tex_coords onto
+    sprite_buffer: wgpu::Buffer,
+    sprite_sheet: Option<SpriteSheet>,
 }
 
 impl TextureData {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
+    // The card textures, with the normal map at `normal_map_path` when given.
+    // The normal map is sampled with the cards' sampler.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        normal_map_path: Option<&Path>,
+        preset: FilterPreset,
+    ) -> Result<Self> {
         let bytes = include_bytes!("card.webp");
-        let texture = Texture::from_bytes(device, queue, bytes, "texture")?;
+        let card = image::load_from_memory(bytes)?;
+        let mut inverted = card.clone();
+        inverted.invert();
+
+        let texture = Texture::from_images(
+            device,
+            queue,
+            &[card.clone(), card.grayscale(), inverted],
+            COLOR_FORMAT,
+            preset,
+            "texture",
+        )?;
+        let normal_map = match normal_map_path {
+            Some(path) => {
+                let img = image::open(path)
+                    .with_context(|| format!("failed to decode {}", path.display()))?;
+                Texture::normal_map(device, queue, &img, &path.to_string_lossy())
+            }
+            None => Texture::flat_normal_map(device, queue),
+        };
+        Ok(Self::with_normal_map(device, texture, normal_map))
+    }
+
+    pub fn from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: Vec<image::DynamicImage>,
+        preset: FilterPreset,
+    ) -> Result<Self> {
+        let texture =
+            Texture::from_images(device, queue, &images, COLOR_FORMAT, preset, "texture")?;
+        Ok(Self::from_texture(device, queue, texture))
+    }
+
+    // Animates through the `cols` x `rows` cells of `image` at `fps` frames per
+    // second, driven by `update`. The smaller mip levels of a smooth preset
+    // blend neighbouring cells at their edges.
+    pub fn sprite_sheet(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: image::DynamicImage,
+        cols: u32,
+        rows: u32,
+        fps: f32,
+        preset: FilterPreset,
+    ) -> Result<Self> {
+        ensure!(
+            cols > 0 && rows > 0,
+            "sprite sheet needs at least one column and row, got {cols}x{rows}"
+        );
+        ensure!(fps > 0.0, "sprite sheet fps has to be positive, got {fps}");
+
+        let mut data = Self::from_images(device, queue, vec![image], preset)?;
+        data.sprite_sheet = Some(SpriteSheet {
+            cols,
+            rows,
+            fps,
+            elapsed: 0.0,
+        });
+        data.update(queue, 0.0);
+        Ok(data)
+    }
 
+    // A normal map belongs to the texture it was made for, so replacing the
+    // texture goes back to a flat one
+    pub fn from_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: Texture) -> Self {
+        Self::with_normal_map(device, texture, Texture::flat_normal_map(device, queue))
+    }
+
+    fn with_normal_map(device: &wgpu::Device, texture: Texture, normal_map: Texture) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
@@ -138,7 +773,7 @@ impl TextureData {
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
                         multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
                         sample_type: wgpu::TextureSampleType::Float { filterable: true },
                     },
                     count: None,
@@ -149,10 +784,38 @@ impl TextureData {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                // The vertex shader remaps tex_coords to the current sprite cell
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Sampled with the sampler at binding 1
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
             ],
             label: Some("texture_bind_group_layout"),
         });
 
+        let sprite_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("sprite uniform"),
+            contents: bytemuck::cast_slice(&[SpriteUniform::WHOLE]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &bind_group_layout,
             entries: &[
@@ -164,14 +827,105 @@ impl TextureData {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: sprite_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&normal_map.view),
+                },
             ],
             label: Some("texture_bind_group"),
         });
 
-        Ok(Self {
+        Self {
             texture,
+            normal_map,
             bind_group,
             bind_group_layout,
+            sprite_buffer,
+            sprite_sheet: None,
+        }
+    }
+
+    // Moves a sprite sheet `dt` seconds further, still textures have nothing to do
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        let Some(sprite_sheet) = &mut self.sprite_sheet else {
+            return;
+        };
+        sprite_sheet.advance(dt);
+        queue.write_buffer(
+            &self.sprite_buffer,
+            0,
+            bytemuck::cast_slice(&[sprite_sheet.uniform()]),
+        );
+    }
+
+    pub fn num_layers(&self) -> u32 {
+        self.texture.texture.depth_or_array_layers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_rows_are_left_alone() {
+        assert_eq!(align_bytes_per_row(64), 256);
+        assert_eq!(align_bytes_per_row(128), 512);
+    }
+
+    #[test]
+    fn odd_widths_are_padded_to_256_bytes() {
+        // 100 px * 4 bytes = 400 bytes
+        assert_eq!(align_bytes_per_row(100), 512);
+        assert_eq!(align_bytes_per_row(1), 256);
+        assert_eq!(align_bytes_per_row(65), 512);
+    }
+
+    #[test]
+    fn zero_width_needs_no_bytes() {
+        assert_eq!(align_bytes_per_row(0), 0);
+    }
+
+    #[test]
+    fn unsupported_compression_decodes_the_image_next_to_it() {
+        let dir = std::env::temp_dir().join(format!("compressed_fallback_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC7_UNorm_sRGB,
+            mipmap_levels: None,
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
         })
+        .unwrap();
+        dds.get_mut_data(0).unwrap().fill(0);
+        let path = dir.join("card.dds");
+        dds.write(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+        image::RgbaImage::new(2, 2)
+            .save(dir.join("card.png"))
+            .unwrap();
+
+        let compression = wgpu::Features::TEXTURE_COMPRESSION_BC;
+        assert!(matches!(
+            Decoded::open(&path, compression).unwrap(),
+            Decoded::Compressed(_)
+        ));
+        assert!(matches!(
+            Decoded::open(&path, wgpu::Features::empty()).unwrap(),
+            Decoded::Image(_)
+        ));
+        std::fs::remove_file(dir.join("card.png")).unwrap();
+        assert!(Decoded::open(&path, wgpu::Features::empty()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,184 @@
+use wgpu::util::DeviceExt;
+
+use crate::{mesh, DepthMode, PipelineConfig};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct WireframeOverlayUniform {
+    color: [f32; 4],
+}
+
+// Pulls the lines towards the camera so they win the depth test against the
+// faces they outline, given for standard depth. The slope term does most of
+// the work, faces seen at a grazing angle change depth quickly across a pixel
+// and are the ones where the lines shimmer. The constant term covers faces
+// seen head on, and a clamp of 0 leaves the total bias unlimited.
+pub const DEFAULT_DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+    constant: -2,
+    slope_scale: -1.0,
+    clamp: 0.0,
+};
+
+const DEFAULT_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+
+// The meshes' edges drawn as lines over their shaded faces, for a "shaded
+// wireframe" look. Like the wireframe mode it needs `POLYGON_MODE_LINE`.
+pub struct WireframeOverlay {
+    pub enabled: bool,
+    color: [f32; 4],
+    depth_bias: wgpu::DepthBiasState,
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl WireframeOverlay {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("wireframe overlay uniform"),
+            contents: bytemuck::cast_slice(&[WireframeOverlayUniform {
+                color: DEFAULT_COLOR,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("wireframe overlay bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("wireframe overlay bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            enabled: false,
+            color: DEFAULT_COLOR,
+            depth_bias: DEFAULT_DEPTH_BIAS,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    // Linear RGBA, the lines are alpha blended over the faces
+    pub fn color(&self) -> [f32; 4] {
+        self.color
+    }
+
+    pub fn set_color(&mut self, queue: &wgpu::Queue, color: [f32; 4]) {
+        self.color = color;
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[WireframeOverlayUniform { color }]),
+        );
+    }
+
+    pub fn depth_bias(&self) -> wgpu::DepthBiasState {
+        self.depth_bias
+    }
+
+    // Only takes effect once the pipeline is rebuilt, see `RenderState::set_wireframe_overlay_bias`
+    pub fn set_depth_bias(&mut self, depth_bias: wgpu::DepthBiasState) {
+        self.depth_bias = depth_bias;
+    }
+
+    // Keeps the scene's depth format and stencil test. The lines leave the depth
+    // buffer alone and also pass where they're level with the faces.
+    pub fn depth_stencil_state(
+        &self,
+        scene: wgpu::DepthStencilState,
+        depth_mode: DepthMode,
+    ) -> wgpu::DepthStencilState {
+        // Towards the camera is towards 1 with reverse-Z
+        let (depth_compare, sign) = match depth_mode {
+            DepthMode::Standard => (wgpu::CompareFunction::LessEqual, 1),
+            DepthMode::ReverseZ => (wgpu::CompareFunction::GreaterEqual, -1),
+        };
+        wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            depth_compare,
+            bias: wgpu::DepthBiasState {
+                constant: self.depth_bias.constant * sign,
+                slope_scale: self.depth_bias.slope_scale * sign as f32,
+                clamp: self.depth_bias.clamp * sign as f32,
+            },
+            ..scene
+        }
+    }
+}
+
+// `spec` is linear `r,g,b,a`, e.g. `0,0,0,0.6`
+pub fn parse_color(spec: &str) -> anyhow::Result<[f32; 4]> {
+    let parts: Vec<_> = spec.split(',').map(str::trim).collect();
+    let [r, g, b, a] = parts[..] else {
+        anyhow::bail!("expected r,g,b,a");
+    };
+    Ok([r.parse()?, g.parse()?, b.parse()?, a.parse()?])
+}
+
+// `spec` is `constant,slope_scale,clamp` for standard depth, e.g. `-2,-1,0`
+pub fn parse_depth_bias(spec: &str) -> anyhow::Result<wgpu::DepthBiasState> {
+    let parts: Vec<_> = spec.split(',').map(str::trim).collect();
+    let [constant, slope_scale, clamp] = parts[..] else {
+        anyhow::bail!("expected constant,slope_scale,clamp");
+    };
+    Ok(wgpu::DepthBiasState {
+        constant: constant.parse()?,
+        slope_scale: slope_scale.parse()?,
+        clamp: clamp.parse()?,
+    })
+}
+
+// `layout` is the scene's pipeline layout with the overlay's bind group added
+// as group 3
+pub fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &PipelineConfig,
+    target_format: wgpu::TextureFormat,
+    sample_count: u32,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("wireframe overlay pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: &config.vertex_entry_point,
+            buffers: &mesh::buffer_layouts(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: &config.wireframe_overlay_fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: config.primitive_state(wgpu::PolygonMode::Line),
+        depth_stencil,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,127 @@
+// Every test binary only uses some of these
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use image::RgbaImage;
+use main::headless::run_headless;
+
+// Machines without any adapter, like most CI runners, skip what needs a GPU
+pub fn device_or_skip() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let Some(adapter) = pollster::block_on(instance.request_adapter(&Default::default())) else {
+        eprintln!("skipping, there's no adapter");
+        return None;
+    };
+    let descriptor = wgpu::DeviceDescriptor {
+        label: None,
+        features: wgpu::Features::empty(),
+        limits: wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+    };
+    match pollster::block_on(adapter.request_device(&descriptor, None)) {
+        Ok(device) => Some(device),
+        Err(e) => {
+            eprintln!("skipping, can't create a device: {e}");
+            None
+        }
+    }
+}
+
+// The same for tests that check rendered frames
+pub fn frames_or_skip(frames: u32) -> Option<Vec<RgbaImage>> {
+    match run_headless(frames) {
+        Ok(images) => Some(images),
+        Err(e) => {
+            eprintln!("skipping, can't render headless: {e:#}");
+            None
+        }
+    }
+}
+
+// Compares `actual` against the PNG at `reference_path` and fails when any
+// channel of any `BLOCK_SIZE` square of pixels is on average more than
+// `tolerance` off. Drivers are allowed to rasterize and filter slightly
+// differently, the tolerance absorbs that.
+//
+// A failed comparison saves `actual` under the test's target directory for a
+// side by side look. `UPDATE_SNAPSHOTS=1` overwrites the reference instead.
+pub fn assert_frame_matches(actual: &RgbaImage, reference_path: impl AsRef<Path>, tolerance: u8) {
+    let reference_path = reference_path.as_ref();
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        actual
+            .save(reference_path)
+            .unwrap_or_else(|e| panic!("can't write {}: {e}", reference_path.display()));
+        return;
+    }
+
+    let reference = image::open(reference_path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "can't open {}: {e}, run with UPDATE_SNAPSHOTS=1 to create it",
+                reference_path.display()
+            )
+        })
+        .to_rgba8();
+    assert_eq!(
+        actual.dimensions(),
+        reference.dimensions(),
+        "frame and {} differ in size",
+        reference_path.display()
+    );
+
+    let (actual_blocks, reference_blocks) = (block_means(actual), block_means(&reference));
+    let (max, total) =
+        actual_blocks
+            .iter()
+            .zip(&reference_blocks)
+            .fold((0, 0), |(max, total), (a, b)| {
+                let difference = a.abs_diff(*b);
+                (max.max(difference), total + difference as u64)
+            });
+    let mean = total as f64 / actual_blocks.len() as f64;
+    eprintln!(
+        "{}: max difference {max}, mean {mean:.3}",
+        reference_path.display()
+    );
+
+    if max > tolerance {
+        let file_name = reference_path.file_name().expect("reference is a file");
+        let actual_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(file_name);
+        actual
+            .save(&actual_path)
+            .unwrap_or_else(|e| panic!("can't write {}: {e}", actual_path.display()));
+        panic!(
+            "frame differs from {} by up to {max} (mean {mean:.3}, tolerance {tolerance}), it was saved to {}",
+            reference_path.display(),
+            actual_path.display()
+        );
+    }
+}
+
+// Without mipmaps, minified textures alias and each driver can land on different
+// texels for the same pixel. Averaging squares of pixels hides that, while a
+// moved cube or a changed color still shifts the averages.
+const BLOCK_SIZE: u32 = 4;
+
+// Each channel averaged over `BLOCK_SIZE` squares, row by row. Squares cut off
+// at the right or bottom edge average the pixels they have.
+fn block_means(image: &RgbaImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mut means = Vec::new();
+    for block_y in (0..height).step_by(BLOCK_SIZE as usize) {
+        for block_x in (0..width).step_by(BLOCK_SIZE as usize) {
+            let mut sums = [0u32; 4];
+            let mut count = 0;
+            for y in block_y..(block_y + BLOCK_SIZE).min(height) {
+                for x in block_x..(block_x + BLOCK_SIZE).min(width) {
+                    for (sum, channel) in sums.iter_mut().zip(image.get_pixel(x, y).0) {
+                        *sum += channel as u32;
+                    }
+                    count += 1;
+                }
+            }
+            means.extend(sums.map(|sum| ((sum + count / 2) / count) as u8));
+        }
+    }
+    means
+}
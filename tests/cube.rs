@@ -0,0 +1,14 @@
+mod common;
+
+use common::device_or_skip;
+use main::data::VertexState;
+
+#[test]
+fn the_cube_has_four_vertices_per_face() {
+    let Some((device, _queue)) = device_or_skip() else {
+        return;
+    };
+    let state = VertexState::new(&device);
+    assert_eq!(state.num_vertices, 24);
+    assert_eq!(state.num_indices, 36);
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::frames_or_skip;
+use main::headless::HEADLESS_SIZE;
+
+#[test]
+fn returns_one_image_per_frame() {
+    let Some(images) = frames_or_skip(3) else {
+        return;
+    };
+    assert_eq!(images.len(), 3);
+    for image in &images {
+        assert_eq!(
+            image.dimensions(),
+            (HEADLESS_SIZE.width, HEADLESS_SIZE.height)
+        );
+    }
+}
+
+#[test]
+fn draws_the_cubes_over_the_clear_color() {
+    let Some(images) = frames_or_skip(1) else {
+        return;
+    };
+    // The default clear color is pure blue, which the sRGB target stores unchanged
+    let clear = image::Rgba([0, 0, 255, 255]);
+    assert!(images[0].pixels().any(|pixel| *pixel != clear));
+}
+
+#[test]
+fn frames_are_reproducible() {
+    let (Some(first), Some(second)) = (frames_or_skip(2), frames_or_skip(2)) else {
+        return;
+    };
+    assert_eq!(first, second);
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use common::{assert_frame_matches, frames_or_skip};
+
+// Room for small differences between drivers, far less than a moved cube or a
+// changed color makes
+const TOLERANCE: u8 = 8;
+
+// The default scene, with the headless seed and time step and the default
+// camera. Environment variables that change the scene, like `SKYBOX_DIR` or
+// `TEXTURE_PATH`, have to be unset.
+#[test]
+fn default_scene_matches_the_reference() {
+    let Some(images) = frames_or_skip(1) else {
+        return;
+    };
+    assert_frame_matches(
+        &images[0],
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/snapshots/default_scene.png"
+        ),
+        TOLERANCE,
+    );
+}
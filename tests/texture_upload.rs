@@ -0,0 +1,134 @@
+mod common;
+
+use common::device_or_skip;
+use image::{DynamicImage, Rgba, RgbaImage};
+use main::texture::{align_bytes_per_row, FilterPreset, Texture, BYTES_PER_PIXEL, COLOR_FORMAT};
+
+// Every pixel different, so a row that starts at the wrong offset shows up
+fn test_image(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([
+            (x * 50) as u8,
+            (y * 80) as u8,
+            (x * 7 + y * 31) as u8,
+            255 - (x + y * width) as u8,
+        ])
+    })
+}
+
+// Copies mip level 0 of `layer` out through a buffer with rows padded to
+// `COPY_BYTES_PER_ROW_ALIGNMENT`, and drops the padding again
+fn read_back(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    layer: u32,
+) -> RgbaImage {
+    let (width, height) = (texture.width(), texture.height());
+    let padded_bytes_per_row = align_bytes_per_row(width);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("texture readback"),
+        size: (padded_bytes_per_row * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: 0,
+                y: 0,
+                z: layer,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .expect("the readback buffer was never mapped")
+        .expect("mapping the readback buffer");
+
+    let row_bytes = (width * BYTES_PER_PIXEL) as usize;
+    let pixels = slice
+        .get_mapped_range()
+        .chunks(padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect();
+    RgbaImage::from_raw(width, height, pixels).expect("readback has the wrong size")
+}
+
+// 5 pixels are 20 bytes a row, nowhere near the 256 byte copy alignment
+#[test]
+fn unaligned_width_round_trips() {
+    let Some((device, queue)) = device_or_skip() else {
+        return;
+    };
+    let image = test_image(5, 3);
+    let texture = Texture::from_images_with_usage(
+        &device,
+        &queue,
+        &[DynamicImage::ImageRgba8(image.clone())],
+        COLOR_FORMAT,
+        FilterPreset::PixelArt,
+        "5x3",
+        wgpu::TextureUsages::COPY_SRC,
+    )
+    .unwrap();
+
+    assert_eq!(read_back(&device, &queue, &texture.texture, 0), image);
+}
+
+#[test]
+fn every_layer_round_trips() {
+    let Some((device, queue)) = device_or_skip() else {
+        return;
+    };
+    let layers = [
+        test_image(5, 3),
+        image::imageops::flip_horizontal(&test_image(5, 3)),
+    ];
+    let texture = Texture::from_images_with_usage(
+        &device,
+        &queue,
+        &layers.clone().map(DynamicImage::ImageRgba8),
+        COLOR_FORMAT,
+        FilterPreset::PixelArt,
+        "5x3 layers",
+        wgpu::TextureUsages::COPY_SRC,
+    )
+    .unwrap();
+
+    for (layer, image) in layers.iter().enumerate() {
+        assert_eq!(
+            &read_back(&device, &queue, &texture.texture, layer as u32),
+            image,
+            "layer {layer}"
+        );
+    }
+}